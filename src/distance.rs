@@ -1,9 +1,178 @@
+use crate::element::Element;
+use crate::simd;
 use ndarray::ArrayView1;
 
+/// Describes how the scores produced by a [`Metric`] rank vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+  /// Lower scores mean more similar vectors, as with distances.
+  SmallerIsBetter,
+  /// Higher scores mean more similar vectors, as with similarities.
+  LargerIsBetter,
+}
+
+impl Order {
+  /// Maps a score to a key where smaller always means closer, so that scores
+  /// of any metric can be ranked the same way.
+  ///
+  /// The mapping is its own inverse: applying it to a key gives back the
+  /// score.
+  pub fn key(self, score: f64) -> f64 {
+    match self {
+      Order::SmallerIsBetter => score,
+      Order::LargerIsBetter => -score,
+    }
+  }
+}
+
+/// A way of scoring how close two vectors are.
+///
+/// Implementations return either a distance or a similarity; [`Metric::order`]
+/// tells callers which one so that results can be ranked correctly. Vectors
+/// may hold any [`Element`] type, and scores are computed in `f64`.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::{Cosine, Metric, Order};
+/// use ndarray::arr1;
+///
+/// let a = arr1(&[1.0, 0.0]);
+/// let b = arr1(&[1.0, 1.0]);
+/// let score = Cosine.score(&a.view(), &b.view());
+/// assert_eq!(Cosine.order(), Order::LargerIsBetter);
+/// ```
+pub trait Metric {
+  /// Scores the pair of vectors `a` and `b`, accumulating in `f64`.
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64;
+
+  /// Whether smaller or larger scores mean closer vectors.
+  fn order(&self) -> Order {
+    Order::SmallerIsBetter
+  }
+}
+
+/// The Euclidean (L2) distance. See [`euclidean`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Euclidean;
+
+impl Metric for Euclidean {
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+    euclidean(a, b)
+  }
+}
+
+/// The cosine similarity. See [`cosine_similarity`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cosine;
+
+impl Metric for Cosine {
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+    cosine_similarity(a, b)
+  }
+
+  fn order(&self) -> Order {
+    Order::LargerIsBetter
+  }
+}
+
+/// The inner product. See [`dot_product`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DotProduct;
+
+impl Metric for DotProduct {
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+    dot_product(a, b)
+  }
+
+  fn order(&self) -> Order {
+    Order::LargerIsBetter
+  }
+}
+
+/// The Manhattan (L1) distance. See [`manhattan`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Manhattan;
+
+impl Metric for Manhattan {
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+    manhattan(a, b)
+  }
+}
+
+/// The Chebyshev (L∞) distance. See [`chebyshev`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Chebyshev;
+
+impl Metric for Chebyshev {
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+    chebyshev(a, b)
+  }
+}
+
+/// The Minkowski distance of order `p`. See [`minkowski`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Minkowski {
+  pub p: f64,
+}
+
+impl Metric for Minkowski {
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+    minkowski(a, b, self.p)
+  }
+}
+
+/// The built-in metrics, for choosing one at runtime.
+///
+/// This is what a [`VectorDatabase`](crate::vector_database::VectorDatabase)
+/// is configured with.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::{Metric, MetricKind};
+/// use ndarray::arr1;
+///
+/// let a = arr1(&[0.0, 0.0]);
+/// let b = arr1(&[3.0, 4.0]);
+/// assert_eq!(MetricKind::Manhattan.score(&a.view(), &b.view()), 7.0);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MetricKind {
+  #[default]
+  Euclidean,
+  Cosine,
+  DotProduct,
+  Manhattan,
+  Chebyshev,
+  Minkowski(f64),
+}
+
+impl Metric for MetricKind {
+  fn score<T: Element>(&self, a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+    match *self {
+      MetricKind::Euclidean => euclidean(a, b),
+      MetricKind::Cosine => cosine_similarity(a, b),
+      MetricKind::DotProduct => dot_product(a, b),
+      MetricKind::Manhattan => manhattan(a, b),
+      MetricKind::Chebyshev => chebyshev(a, b),
+      MetricKind::Minkowski(p) => minkowski(a, b, p),
+    }
+  }
+
+  fn order(&self) -> Order {
+    match self {
+      MetricKind::Cosine | MetricKind::DotProduct => Order::LargerIsBetter,
+      _ => Order::SmallerIsBetter,
+    }
+  }
+}
+
 /// Computes the Euclidean distance between two 1-dimensional array views.
 ///
 /// # Arguments
-/// 
+///
 /// * `a` - The first array view.
 /// * `b` - The second array view.
 ///
@@ -17,40 +186,279 @@ use ndarray::ArrayView1;
 /// let array2 = arr1(&[4.0, 5.0, 6.0]);
 /// let dist = euclidean(&array1.view(), &array2.view());
 /// ```
-pub fn euclidean(a: &ArrayView1<f64>, b: &ArrayView1<f64>) -> f64 {
+pub fn euclidean<T: Element>(a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+  squared_euclidean(a, b).sqrt()
+}
+
+/// Computes the squared Euclidean distance between two 1-dimensional array
+/// views.
+///
+/// It ranks vectors like [`euclidean`] without taking a square root, and is
+/// computed with SIMD instructions when the CPU has them.
+///
+/// # Arguments
+///
+/// * `a` - The first array view.
+/// * `b` - The second array view.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::squared_euclidean;
+/// use ndarray::arr1;
+///
+/// let array1 = arr1(&[1.0, 2.0, 3.0]);
+/// let array2 = arr1(&[4.0, 5.0, 6.0]);
+/// assert_eq!(squared_euclidean(&array1.view(), &array2.view()), 27.0);
+/// ```
+pub fn squared_euclidean<T: Element>(
+  a: &ArrayView1<T>,
+  b: &ArrayView1<T>,
+) -> f64 {
+  simd::squared_euclidean(a, b)
+}
+
+/// Computes the cosine similarity between two 1-dimensional array views.
+///
+/// The result lies in `[-1, 1]`, where `1` means the vectors point in the same
+/// direction. If either vector has a zero norm the similarity is `0`.
+///
+/// # Arguments
+///
+/// * `a` - The first array view.
+/// * `b` - The second array view.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::cosine_similarity;
+/// use ndarray::arr1;
+///
+/// let array1 = arr1(&[1.0, 0.0]);
+/// let array2 = arr1(&[0.0, 1.0]);
+/// let sim = cosine_similarity(&array1.view(), &array2.view());
+/// ```
+pub fn cosine_similarity<T: Element>(
+  a: &ArrayView1<T>,
+  b: &ArrayView1<T>,
+) -> f64 {
+  let [ab, aa, bb] = simd::cosine(a, b);
+  let norms = aa.sqrt() * bb.sqrt();
+  if norms == 0.0 {
+    return 0.0;
+  }
+  ab / norms
+}
+
+/// Computes the inner product of two 1-dimensional array views.
+///
+/// # Arguments
+///
+/// * `a` - The first array view.
+/// * `b` - The second array view.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::dot_product;
+/// use ndarray::arr1;
+///
+/// let array1 = arr1(&[1.0, 2.0, 3.0]);
+/// let array2 = arr1(&[4.0, 5.0, 6.0]);
+/// let dot = dot_product(&array1.view(), &array2.view());
+/// ```
+pub fn dot_product<T: Element>(a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+  simd::dot(a, b)
+}
+
+/// Computes the Manhattan distance between two 1-dimensional array views.
+///
+/// # Arguments
+///
+/// * `a` - The first array view.
+/// * `b` - The second array view.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::manhattan;
+/// use ndarray::arr1;
+///
+/// let array1 = arr1(&[1.0, 2.0, 3.0]);
+/// let array2 = arr1(&[4.0, 5.0, 6.0]);
+/// let dist = manhattan(&array1.view(), &array2.view());
+/// ```
+pub fn manhattan<T: Element>(a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
   a.iter()
     .zip(b)
-    .map(|(&x, &y)| (x - y).powi(2))
+    .map(|(&x, &y)| (x.to_f64() - y.to_f64()).abs())
+    .sum()
+}
+
+/// Computes the Chebyshev distance between two 1-dimensional array views,
+/// i.e. the largest absolute difference between their components.
+///
+/// # Arguments
+///
+/// * `a` - The first array view.
+/// * `b` - The second array view.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::chebyshev;
+/// use ndarray::arr1;
+///
+/// let array1 = arr1(&[1.0, 2.0, 3.0]);
+/// let array2 = arr1(&[4.0, 7.0, 6.0]);
+/// let dist = chebyshev(&array1.view(), &array2.view());
+/// ```
+pub fn chebyshev<T: Element>(a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+  a.iter()
+    .zip(b)
+    .map(|(&x, &y)| (x.to_f64() - y.to_f64()).abs())
+    .fold(0.0, f64::max)
+}
+
+/// Computes the Minkowski distance of order `p` between two 1-dimensional
+/// array views.
+///
+/// `p = 1` is the Manhattan distance, `p = 2` the Euclidean distance and
+/// `p = ∞` the Chebyshev distance. `p` should be at least `1` for the result
+/// to be a proper metric.
+///
+/// # Arguments
+///
+/// * `a` - The first array view.
+/// * `b` - The second array view.
+/// * `p` - The order of the distance.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::minkowski;
+/// use ndarray::arr1;
+///
+/// let array1 = arr1(&[1.0, 2.0, 3.0]);
+/// let array2 = arr1(&[4.0, 5.0, 6.0]);
+/// let dist = minkowski(&array1.view(), &array2.view(), 3.0);
+/// ```
+pub fn minkowski<T: Element>(
+  a: &ArrayView1<T>,
+  b: &ArrayView1<T>,
+  p: f64,
+) -> f64 {
+  if p.is_infinite() {
+    return chebyshev(a, b);
+  }
+  a.iter()
+    .zip(b)
+    .map(|(&x, &y)| (x.to_f64() - y.to_f64()).abs().powf(p))
     .sum::<f64>()
-    .sqrt()
+    .powf(p.recip())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use ndarray::arr1;
+  use super::*;
+  use ndarray::arr1;
 
-    #[test]
-    fn test_zero_distance_same_vectors() {
-        let vec1 = arr1(&[1.0, 2.0, 3.0, 4.0, 5.0]);
-        let vec2 = arr1(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+  #[test]
+  fn test_zero_distance_same_vectors() {
+    let vec1 = arr1(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+    let vec2 = arr1(&[1.0, 2.0, 3.0, 4.0, 5.0]);
 
-        assert_eq!(euclidean(&vec1.view(), &vec2.view()), 0.0);
-    }
+    assert_eq!(euclidean(&vec1.view(), &vec2.view()), 0.0);
+  }
 
-    #[test]
-    fn test_known_distance_diff_vectors() {
-        let vec1 = arr1(&[1.0, 2.0, 3.0]);
-        let vec2 = arr1(&[4.0, 5.0, 6.0]);
+  #[test]
+  fn test_known_distance_diff_vectors() {
+    let vec1 = arr1(&[1.0, 2.0, 3.0]);
+    let vec2 = arr1(&[4.0, 5.0, 6.0]);
 
-        assert_eq!(euclidean(&vec1.view(), &vec2.view()), (27.0 as f64).sqrt());
-    }
+    assert_eq!(euclidean(&vec1.view(), &vec2.view()), 27.0_f64.sqrt());
+  }
+
+  #[test]
+  fn test_distance_invariant_to_scaling() {
+    let vec1 = arr1(&[1.0, 2.0, 3.0]);
+    let vec2 = arr1(&[2.0, 4.0, 6.0]);
+
+    assert_eq!(euclidean(&vec1.view(), &vec2.view()), 14.0_f64.sqrt());
+  }
+
+  #[test]
+  fn test_cosine_similarity() {
+    let vec1 = arr1(&[1.0, 0.0]);
+    let vec2 = arr1(&[0.0, 1.0]);
+    let vec3 = arr1(&[2.0, 0.0]);
+    let zero = arr1(&[0.0, 0.0]);
 
-    #[test]
-    fn test_distance_invariant_to_scaling() {
-        let vec1 = arr1(&[1.0, 2.0, 3.0]);
-        let vec2 = arr1(&[2.0, 4.0, 6.0]);
+    assert_eq!(cosine_similarity(&vec1.view(), &vec2.view()), 0.0);
+    assert_eq!(cosine_similarity(&vec1.view(), &vec3.view()), 1.0);
+    assert_eq!(cosine_similarity(&vec1.view(), &zero.view()), 0.0);
+  }
 
-        assert_eq!(euclidean(&vec1.view(), &vec2.view()), (14.0 as f64).sqrt());
+  #[test]
+  fn test_dot_product() {
+    let vec1 = arr1(&[1.0, 2.0, 3.0]);
+    let vec2 = arr1(&[4.0, 5.0, 6.0]);
+
+    assert_eq!(dot_product(&vec1.view(), &vec2.view()), 32.0);
+  }
+
+  #[test]
+  fn test_manhattan_and_chebyshev() {
+    let vec1 = arr1(&[1.0, 2.0, 3.0]);
+    let vec2 = arr1(&[4.0, 7.0, 6.0]);
+
+    assert_eq!(manhattan(&vec1.view(), &vec2.view()), 11.0);
+    assert_eq!(chebyshev(&vec1.view(), &vec2.view()), 5.0);
+  }
+
+  #[test]
+  fn test_minkowski_generalizes_other_metrics() {
+    let vec1 = arr1(&[1.0, 2.0, 3.0]);
+    let vec2 = arr1(&[4.0, 7.0, 6.0]);
+    let (a, b) = (&vec1.view(), &vec2.view());
+
+    assert_eq!(minkowski(a, b, 1.0), manhattan(a, b));
+    assert!((minkowski(a, b, 2.0) - euclidean(a, b)).abs() < 1e-12);
+    assert_eq!(minkowski(a, b, f64::INFINITY), chebyshev(a, b));
+  }
+
+  #[test]
+  fn test_metric_kind_order() {
+    assert_eq!(MetricKind::Euclidean.order(), Order::SmallerIsBetter);
+    assert_eq!(MetricKind::Minkowski(3.0).order(), Order::SmallerIsBetter);
+    assert_eq!(MetricKind::Cosine.order(), Order::LargerIsBetter);
+    assert_eq!(MetricKind::DotProduct.order(), Order::LargerIsBetter);
+  }
+
+  #[test]
+  fn test_narrow_elements_accumulate_in_f64() {
+    use crate::element::f16;
+
+    let a = arr1(&[0.5_f32, -1.25, 3.0]);
+    let b = arr1(&[2.0_f32, 0.75, -1.5]);
+    let (a64, b64) = (a.mapv(f64::from), b.mapv(f64::from));
+    for metric in [
+      MetricKind::Euclidean,
+      MetricKind::Cosine,
+      MetricKind::DotProduct,
+      MetricKind::Manhattan,
+      MetricKind::Chebyshev,
+      MetricKind::Minkowski(3.0),
+    ] {
+      assert_eq!(
+        metric.score(&a.view(), &b.view()),
+        metric.score(&a64.view(), &b64.view())
+      );
     }
+
+    // Summed in f16, the squares would stop growing at 2048.
+    let ones = ndarray::Array1::from_elem(4096, f16::ONE);
+    let zeros = ndarray::Array1::from_elem(4096, f16::ZERO);
+    assert_eq!(euclidean(&ones.view(), &zeros.view()), 64.0);
+  }
 }
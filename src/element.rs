@@ -0,0 +1,134 @@
+//! The types vector components can be stored as.
+//!
+//! A [`VectorDatabase`](crate::vector_database::VectorDatabase) stores its
+//! vectors as `f64` by default, and as `f32`, [`f16`](struct@f16) or [`bf16`] to halve
+//! or quarter the memory they take. Whatever the storage type, distances are
+//! accumulated in `f64`, so that summing many small terms does not lose the
+//! precision that the stored components have.
+//!
+//! Database files record the element type, so a database saved with
+//! [`VectorDatabase::save`](crate::vector_database::VectorDatabase::save) is
+//! loaded or memory-mapped as the same type with
+//! [`VectorDatabase::open_with_element_type`](crate::vector_database::VectorDatabase::open_with_element_type)
+//! or
+//! [`MappedDatabase::open_with_element_type`](crate::storage::MappedDatabase::open_with_element_type).
+//! A [`DurableDatabase`](crate::storage::DurableDatabase) only stores `f64`
+//! vectors.
+//!
+//! # Examples
+//!
+//! ```
+//! use rustyvectors::distance::MetricKind;
+//! use rustyvectors::element::f16;
+//! use rustyvectors::vector_database::VectorDatabase;
+//!
+//! let mut db = VectorDatabase::<f16>::with_element_type(2, MetricKind::Euclidean)?;
+//! let id = db.add(&[f16::from_f32(1.0), f16::from_f32(2.0)])?;
+//! let query = [f16::from_f32(1.0), f16::from_f32(2.5)];
+//! assert_eq!(db.k_nearest(&query, 1)?, [(id, 0.5)]);
+//! # Ok::<(), rustyvectors::Error>(())
+//! ```
+
+use crate::simd::Simd;
+use crate::storage::Component;
+use std::borrow::Cow;
+use std::fmt::Debug;
+
+pub use half::{bf16, f16};
+
+/// A floating-point type vector components can be stored as.
+///
+/// This trait is sealed: it is implemented for `f64`, `f32`, [`f16`](struct@f16) and
+/// [`bf16`] only.
+pub trait Element:
+  Copy
+  + Debug
+  + Default
+  + PartialEq
+  + Send
+  + Sync
+  + 'static
+  + private::Sealed
+  + Simd
+  + Component
+{
+  /// Converts the value to `f64`, which is exact for every element type.
+  fn to_f64(self) -> f64;
+
+  /// Converts an `f64` to the nearest value of this type.
+  fn from_f64(value: f64) -> Self;
+
+  /// Converts a slice of values to `f64`, without copying if they already
+  /// are.
+  fn widen(values: &[Self]) -> Cow<'_, [f64]> {
+    Cow::Owned(values.iter().map(|value| value.to_f64()).collect())
+  }
+}
+
+impl Element for f64 {
+  fn to_f64(self) -> f64 {
+    self
+  }
+
+  fn from_f64(value: f64) -> Self {
+    value
+  }
+
+  fn widen(values: &[Self]) -> Cow<'_, [f64]> {
+    Cow::Borrowed(values)
+  }
+}
+
+impl Element for f32 {
+  fn to_f64(self) -> f64 {
+    self.into()
+  }
+
+  fn from_f64(value: f64) -> Self {
+    value as f32
+  }
+}
+
+impl Element for f16 {
+  fn to_f64(self) -> f64 {
+    f16::to_f64(self)
+  }
+
+  fn from_f64(value: f64) -> Self {
+    f16::from_f64(value)
+  }
+}
+
+impl Element for bf16 {
+  fn to_f64(self) -> f64 {
+    bf16::to_f64(self)
+  }
+
+  fn from_f64(value: f64) -> Self {
+    bf16::from_f64(value)
+  }
+}
+
+mod private {
+  pub trait Sealed {}
+
+  impl Sealed for f64 {}
+  impl Sealed for f32 {}
+  impl Sealed for super::f16 {}
+  impl Sealed for super::bf16 {}
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_conversions() {
+    assert_eq!(f32::from_f64(0.1).to_f64(), 0.1_f32 as f64);
+    assert_eq!(f16::from_f64(65504.0).to_f64(), 65504.0);
+    assert!(f16::from_f64(1e6).to_f64().is_infinite());
+    assert_eq!(bf16::from_f64(1e30).to_f64(), bf16::from_f32(1e30).to_f64());
+    assert!(matches!(f64::widen(&[1.0]), Cow::Borrowed(_)));
+    assert_eq!(*f32::widen(&[1.5, -2.0]), [1.5, -2.0]);
+  }
+}
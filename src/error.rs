@@ -0,0 +1,88 @@
+use crate::vector_database::VectorId;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// The errors returned by this crate.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Error {
+  /// A vector's length differs from the dimension of the database.
+  DimensionMismatch { expected: usize, found: usize },
+  /// No vector is stored under the given ID.
+  NotFound(VectorId),
+  /// An argument is outside of its allowed range, such as a vector with a
+  /// non-finite component.
+  InvalidValue(String),
+  /// Reading or writing a file failed.
+  Io(Arc<io::Error>),
+  /// A file is not a valid database file or was damaged.
+  Corrupted(String),
+  /// A file was written in a format version this build cannot read.
+  UnsupportedVersion { found: u32, supported: u32 },
+}
+
+/// A `Result` whose error type is [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::DimensionMismatch { expected, found } => write!(
+        f,
+        "dimension mismatch: expected {expected} components, found {found}"
+      ),
+      Error::NotFound(id) => write!(f, "no vector with id {id}"),
+      Error::InvalidValue(message) => write!(f, "invalid value: {message}"),
+      Error::Io(error) => write!(f, "i/o error: {error}"),
+      Error::Corrupted(message) => write!(f, "corrupted file: {message}"),
+      Error::UnsupportedVersion { found, supported } => write!(
+        f,
+        "unsupported file format version {found}, this build reads version \
+         {supported}"
+      ),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(error) => Some(error.as_ref()),
+      _ => None,
+    }
+  }
+}
+
+/// I/O errors compare equal when they are of the same kind.
+impl PartialEq for Error {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (
+        Error::DimensionMismatch { expected, found },
+        Error::DimensionMismatch {
+          expected: other_expected,
+          found: other_found,
+        },
+      ) => expected == other_expected && found == other_found,
+      (Error::NotFound(a), Error::NotFound(b)) => a == b,
+      (Error::InvalidValue(a), Error::InvalidValue(b)) => a == b,
+      (Error::Io(a), Error::Io(b)) => a.kind() == b.kind(),
+      (Error::Corrupted(a), Error::Corrupted(b)) => a == b,
+      (
+        Error::UnsupportedVersion { found, supported },
+        Error::UnsupportedVersion {
+          found: other_found,
+          supported: other_supported,
+        },
+      ) => found == other_found && supported == other_supported,
+      _ => false,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Self {
+    Error::Io(Arc::new(error))
+  }
+}
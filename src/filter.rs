@@ -0,0 +1,509 @@
+use crate::metadata::{Metadata, Value};
+use crate::vector_database::VectorId;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+/// A condition on the metadata of a vector, used to constrain searches.
+///
+/// Conditions on a field whose value is a list match when any of the list's
+/// elements satisfies them, so `Filter::eq("tags", "rust")` matches a vector
+/// tagged `["rust", "search"]`. Numbers compare by value regardless of whether
+/// they are stored as `Int` or `Float`. A condition on a missing field never
+/// matches.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::filter::Filter;
+/// use rustyvectors::metadata::Value;
+///
+/// let filter = Filter::eq("lang", "en")
+///   .and(Filter::gte("year", 2020))
+///   .and(!Filter::is_in("source", vec!["spam.com", "ads.com"]));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+  /// The field equals the value.
+  Eq(String, Value),
+  /// The field lies between the bounds.
+  Range {
+    field: String,
+    lower: Bound<Value>,
+    upper: Bound<Value>,
+  },
+  /// The field equals one of the values.
+  In(String, Vec<Value>),
+  /// All of the filters match.
+  And(Vec<Filter>),
+  /// At least one of the filters matches.
+  Or(Vec<Filter>),
+  /// The filter does not match.
+  Not(Box<Filter>),
+}
+
+impl Filter {
+  /// Matches vectors whose `field` equals `value`.
+  pub fn eq(field: impl Into<String>, value: impl Into<Value>) -> Self {
+    Filter::Eq(field.into(), value.into())
+  }
+
+  /// Matches vectors whose `field` equals one of `values`.
+  pub fn is_in<V: Into<Value>>(
+    field: impl Into<String>,
+    values: Vec<V>,
+  ) -> Self {
+    Filter::In(field.into(), values.into_iter().map(Into::into).collect())
+  }
+
+  /// Matches vectors whose `field` is greater than `value`.
+  pub fn gt(field: impl Into<String>, value: impl Into<Value>) -> Self {
+    Self::range(field, Bound::Excluded(value.into()), Bound::Unbounded)
+  }
+
+  /// Matches vectors whose `field` is greater than or equal to `value`.
+  pub fn gte(field: impl Into<String>, value: impl Into<Value>) -> Self {
+    Self::range(field, Bound::Included(value.into()), Bound::Unbounded)
+  }
+
+  /// Matches vectors whose `field` is less than `value`.
+  pub fn lt(field: impl Into<String>, value: impl Into<Value>) -> Self {
+    Self::range(field, Bound::Unbounded, Bound::Excluded(value.into()))
+  }
+
+  /// Matches vectors whose `field` is less than or equal to `value`.
+  pub fn lte(field: impl Into<String>, value: impl Into<Value>) -> Self {
+    Self::range(field, Bound::Unbounded, Bound::Included(value.into()))
+  }
+
+  /// Matches vectors whose `field` lies between `lower` and `upper`.
+  pub fn range(
+    field: impl Into<String>,
+    lower: Bound<Value>,
+    upper: Bound<Value>,
+  ) -> Self {
+    Filter::Range {
+      field: field.into(),
+      lower,
+      upper,
+    }
+  }
+
+  /// Matches vectors matched by both `self` and `other`.
+  pub fn and(self, other: Filter) -> Self {
+    match self {
+      Filter::And(mut filters) => {
+        filters.push(other);
+        Filter::And(filters)
+      }
+      filter => Filter::And(vec![filter, other]),
+    }
+  }
+
+  /// Matches vectors matched by `self`, `other` or both.
+  pub fn or(self, other: Filter) -> Self {
+    match self {
+      Filter::Or(mut filters) => {
+        filters.push(other);
+        Filter::Or(filters)
+      }
+      filter => Filter::Or(vec![filter, other]),
+    }
+  }
+
+  /// Returns `true` if a vector with the given payload satisfies the filter.
+  pub fn matches(&self, metadata: &Metadata) -> bool {
+    match self {
+      Filter::Eq(field, value) => {
+        any_element(metadata, field, |v| values_equal(v, value))
+      }
+      Filter::Range {
+        field,
+        lower,
+        upper,
+      } => any_element(metadata, field, |v| in_range(v, lower, upper)),
+      Filter::In(field, values) => any_element(metadata, field, |v| {
+        values.iter().any(|value| values_equal(v, value))
+      }),
+      Filter::And(filters) => filters.iter().all(|f| f.matches(metadata)),
+      Filter::Or(filters) => filters.iter().any(|f| f.matches(metadata)),
+      Filter::Not(filter) => !filter.matches(metadata),
+    }
+  }
+
+  /// Returns a superset of the IDs matched by the filter using the secondary
+  /// indexes, or `None` if the indexes cannot narrow the filter down.
+  pub(crate) fn candidates(
+    &self,
+    indexes: &BTreeMap<String, FieldIndex>,
+  ) -> Option<BTreeSet<VectorId>> {
+    match self {
+      Filter::Eq(field, value) => {
+        let index = indexes.get(field)?;
+        Some(index.lookup(&IndexKey::new(value)?))
+      }
+      Filter::Range {
+        field,
+        lower,
+        upper,
+      } => indexes.get(field)?.range(lower, upper),
+      Filter::In(field, values) => {
+        let index = indexes.get(field)?;
+        let mut ids = BTreeSet::new();
+        for value in values {
+          ids.extend(index.lookup(&IndexKey::new(value)?));
+        }
+        Some(ids)
+      }
+      Filter::And(filters) => filters
+        .iter()
+        .filter_map(|f| f.candidates(indexes))
+        .reduce(|a, b| a.intersection(&b).copied().collect()),
+      Filter::Or(filters) => {
+        let mut ids = BTreeSet::new();
+        for filter in filters {
+          ids.extend(filter.candidates(indexes)?);
+        }
+        Some(ids)
+      }
+      Filter::Not(_) => None,
+    }
+  }
+}
+
+impl std::ops::Not for Filter {
+  type Output = Filter;
+
+  fn not(self) -> Filter {
+    match self {
+      Filter::Not(filter) => *filter,
+      filter => Filter::Not(Box::new(filter)),
+    }
+  }
+}
+
+/// Applies `predicate` to a field, or to each of its elements if it is a list.
+fn any_element(
+  metadata: &Metadata,
+  field: &str,
+  predicate: impl Fn(&Value) -> bool,
+) -> bool {
+  match metadata.get(field) {
+    Some(value @ Value::List(values)) => {
+      values.iter().any(&predicate) || predicate(value)
+    }
+    Some(value) => predicate(value),
+    None => false,
+  }
+}
+
+fn values_equal(a: &Value, b: &Value) -> bool {
+  match compare(a, b) {
+    Some(ordering) => ordering == Ordering::Equal,
+    None => a == b,
+  }
+}
+
+fn in_range(value: &Value, lower: &Bound<Value>, upper: &Bound<Value>) -> bool {
+  let above = match lower {
+    Bound::Included(bound) => {
+      compare(value, bound).is_some_and(Ordering::is_ge)
+    }
+    Bound::Excluded(bound) => {
+      compare(value, bound).is_some_and(Ordering::is_gt)
+    }
+    Bound::Unbounded => true,
+  };
+  let below = match upper {
+    Bound::Included(bound) => {
+      compare(value, bound).is_some_and(Ordering::is_le)
+    }
+    Bound::Excluded(bound) => {
+      compare(value, bound).is_some_and(Ordering::is_lt)
+    }
+    Bound::Unbounded => true,
+  };
+  above && below
+}
+
+/// Compares two scalar values of compatible types.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+  match (a, b) {
+    (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+    (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+    (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+    _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+  }
+}
+
+/// A scalar metadata value in a form that can be ordered in an index.
+///
+/// Numbers are stored as `f64`, so integers beyond 2^53 may share a key. The
+/// index only narrows down candidates, which are then checked against the
+/// exact filter, so this never produces wrong results.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum IndexKey {
+  Bool(bool),
+  Number(f64),
+  String(String),
+}
+
+impl IndexKey {
+  fn new(value: &Value) -> Option<Self> {
+    match value {
+      Value::Bool(value) => Some(IndexKey::Bool(*value)),
+      Value::Int(_) | Value::Float(_) => {
+        let number = value.as_f64()?;
+        // `-0.0` and `0.0` are equal but ordered apart by `total_cmp`.
+        (!number.is_nan()).then_some(IndexKey::Number(number + 0.0))
+      }
+      Value::String(value) => Some(IndexKey::String(value.clone())),
+      _ => None,
+    }
+  }
+
+  fn rank(&self) -> u8 {
+    match self {
+      IndexKey::Bool(_) => 0,
+      IndexKey::Number(_) => 1,
+      IndexKey::String(_) => 2,
+    }
+  }
+}
+
+impl Eq for IndexKey {}
+
+impl PartialOrd for IndexKey {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for IndexKey {
+  fn cmp(&self, other: &Self) -> Ordering {
+    match (self, other) {
+      (IndexKey::Bool(a), IndexKey::Bool(b)) => a.cmp(b),
+      (IndexKey::Number(a), IndexKey::Number(b)) => a.total_cmp(b),
+      (IndexKey::String(a), IndexKey::String(b)) => a.cmp(b),
+      _ => self.rank().cmp(&other.rank()),
+    }
+  }
+}
+
+/// A secondary index mapping the values of one metadata field to the IDs of
+/// the vectors holding them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FieldIndex {
+  entries: BTreeMap<IndexKey, BTreeSet<VectorId>>,
+}
+
+impl FieldIndex {
+  /// Indexes the value of `field` in `metadata` under `id`.
+  pub(crate) fn insert(
+    &mut self,
+    field: &str,
+    id: VectorId,
+    metadata: &Metadata,
+  ) {
+    for key in Self::keys(field, metadata) {
+      self.entries.entry(key).or_default().insert(id);
+    }
+  }
+
+  /// Removes the entries added by [`Self::insert`] for the same arguments.
+  pub(crate) fn remove(
+    &mut self,
+    field: &str,
+    id: VectorId,
+    metadata: &Metadata,
+  ) {
+    for key in Self::keys(field, metadata) {
+      if let Some(ids) = self.entries.get_mut(&key) {
+        ids.remove(&id);
+        if ids.is_empty() {
+          self.entries.remove(&key);
+        }
+      }
+    }
+  }
+
+  fn keys(field: &str, metadata: &Metadata) -> Vec<IndexKey> {
+    match metadata.get(field) {
+      Some(Value::List(values)) => {
+        values.iter().filter_map(IndexKey::new).collect()
+      }
+      Some(value) => IndexKey::new(value).into_iter().collect(),
+      None => Vec::new(),
+    }
+  }
+
+  fn lookup(&self, key: &IndexKey) -> BTreeSet<VectorId> {
+    self.entries.get(key).cloned().unwrap_or_default()
+  }
+
+  fn range(
+    &self,
+    lower: &Bound<Value>,
+    upper: &Bound<Value>,
+  ) -> Option<BTreeSet<VectorId>> {
+    let to_key = |bound: &Bound<Value>| -> Option<Bound<IndexKey>> {
+      Some(match bound {
+        Bound::Included(value) => Bound::Included(IndexKey::new(value)?),
+        Bound::Excluded(value) => Bound::Excluded(IndexKey::new(value)?),
+        Bound::Unbounded => Bound::Unbounded,
+      })
+    };
+    let (lower, upper) = (to_key(lower)?, to_key(upper)?);
+    // Keys of different types are not comparable, so an open side is closed
+    // at the edge of the other bound's type.
+    let (lower, upper) = match (lower, upper) {
+      (Bound::Unbounded, Bound::Unbounded) => return None,
+      (Bound::Unbounded, upper) => (type_start(bound_key(&upper)), upper),
+      (lower, Bound::Unbounded) => {
+        let end = type_end(bound_key(&lower));
+        (lower, end)
+      }
+      (lower, upper) => {
+        if bound_key(&lower).rank() != bound_key(&upper).rank() {
+          return Some(BTreeSet::new());
+        }
+        (lower, upper)
+      }
+    };
+    if invalid_range(&lower, &upper) {
+      return Some(BTreeSet::new());
+    }
+
+    Some(
+      self
+        .entries
+        .range((lower, upper))
+        .flat_map(|(_, ids)| ids.iter().copied())
+        .collect(),
+    )
+  }
+}
+
+fn bound_key(bound: &Bound<IndexKey>) -> &IndexKey {
+  match bound {
+    Bound::Included(key) | Bound::Excluded(key) => key,
+    Bound::Unbounded => unreachable!("only called on bounded sides"),
+  }
+}
+
+fn type_start(key: &IndexKey) -> Bound<IndexKey> {
+  Bound::Included(match key {
+    IndexKey::Bool(_) => IndexKey::Bool(false),
+    IndexKey::Number(_) => IndexKey::Number(f64::NEG_INFINITY),
+    IndexKey::String(_) => IndexKey::String(String::new()),
+  })
+}
+
+fn type_end(key: &IndexKey) -> Bound<IndexKey> {
+  match key {
+    IndexKey::Bool(_) => Bound::Included(IndexKey::Bool(true)),
+    IndexKey::Number(_) => Bound::Included(IndexKey::Number(f64::INFINITY)),
+    // Strings have no largest value, but every string sorts before the
+    // next key type.
+    IndexKey::String(_) => Bound::Unbounded,
+  }
+}
+
+/// Returns `true` for bounds that `BTreeMap::range` would reject.
+fn invalid_range(lower: &Bound<IndexKey>, upper: &Bound<IndexKey>) -> bool {
+  match (lower, upper) {
+    (Bound::Included(a), Bound::Included(b)) => a > b,
+    (Bound::Included(a), Bound::Excluded(b))
+    | (Bound::Excluded(a), Bound::Included(b)) => a >= b,
+    (Bound::Excluded(a), Bound::Excluded(b)) => a >= b,
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn metadata(fields: &[(&str, Value)]) -> Metadata {
+    fields
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.clone()))
+      .collect()
+  }
+
+  #[test]
+  fn test_matches() {
+    let m = metadata(&[
+      ("lang", Value::from("en")),
+      ("year", Value::from(2021)),
+      ("score", Value::from(0.5)),
+      ("tags", Value::from(vec!["rust", "search"])),
+    ]);
+
+    assert!(Filter::eq("lang", "en").matches(&m));
+    assert!(!Filter::eq("lang", "fr").matches(&m));
+    assert!(Filter::eq("year", 2021.0).matches(&m));
+    assert!(Filter::eq("tags", "rust").matches(&m));
+    assert!(Filter::is_in("lang", vec!["fr", "en"]).matches(&m));
+    assert!(Filter::gte("year", 2021).matches(&m));
+    assert!(!Filter::gt("year", 2021).matches(&m));
+    assert!(Filter::lt("score", 1).matches(&m));
+    assert!(!Filter::lt("lang", 1).matches(&m));
+    assert!(!Filter::eq("missing", 1).matches(&m));
+    assert!(Filter::eq("lang", "en")
+      .and(Filter::lt("year", 2022))
+      .matches(&m));
+    assert!(!Filter::eq("lang", "en")
+      .and(Filter::gt("year", 2022))
+      .matches(&m));
+    assert!(Filter::eq("lang", "fr")
+      .or(Filter::eq("year", 2021))
+      .matches(&m));
+    assert!((!Filter::eq("lang", "fr")).matches(&m));
+    assert!((!Filter::eq("missing", 1)).matches(&m));
+  }
+
+  #[test]
+  fn test_index_candidates() {
+    let mut indexes = BTreeMap::new();
+    let mut year = FieldIndex::default();
+    let mut lang = FieldIndex::default();
+    for (id, (y, l)) in [(2019, "en"), (2020, "fr"), (2021, "en"), (2022, "de")]
+      .into_iter()
+      .enumerate()
+    {
+      let m = metadata(&[("year", Value::from(y)), ("lang", Value::from(l))]);
+      year.insert("year", id as VectorId, &m);
+      lang.insert("lang", id as VectorId, &m);
+    }
+    indexes.insert("year".to_string(), year);
+    indexes.insert("lang".to_string(), lang);
+
+    let ids = |filter: Filter| {
+      filter
+        .candidates(&indexes)
+        .map(|ids| ids.into_iter().collect::<Vec<_>>())
+    };
+    assert_eq!(ids(Filter::eq("lang", "en")), Some(vec![0, 2]));
+    assert_eq!(ids(Filter::gte("year", 2020.5)), Some(vec![2, 3]));
+    assert_eq!(ids(Filter::lt("year", 2020)), Some(vec![0]));
+    assert_eq!(ids(Filter::gt("year", 2030)), Some(vec![]));
+    assert_eq!(
+      ids(Filter::is_in("lang", vec!["fr", "de"])),
+      Some(vec![1, 3])
+    );
+    assert_eq!(
+      ids(Filter::eq("lang", "en").and(Filter::gt("year", 2020))),
+      Some(vec![2])
+    );
+    assert_eq!(
+      ids(Filter::eq("lang", "en").and(Filter::eq("other", 1))),
+      Some(vec![0, 2])
+    );
+    assert_eq!(
+      ids(Filter::eq("lang", "en").or(Filter::eq("other", 1))),
+      None
+    );
+    assert_eq!(ids(!Filter::eq("lang", "en")), None);
+  }
+}
@@ -0,0 +1,890 @@
+//! Apache Arrow record batches and IPC files.
+//!
+//! A table is imported by reading its vectors from one list column, of
+//! `float32` or `float64` values, and turning the other columns into
+//! metadata: booleans, integers, floats, strings, lists and structs map to
+//! the [`Value`] variant of the same name, and null cells are left out of the
+//! payload. IDs are assigned by the database unless an ID column is chosen.
+//!
+//! A database is exported as a table with a `uint64` ID column, a
+//! fixed-size list vector column of `float64` values and one column per
+//! metadata field, with the type shared by the values of that field. Rows
+//! are produced a batch at a time by ascending ID, so that a database can be
+//! written out without first copying all of its vectors.
+//!
+//! Parquet files, with the `parquet` feature, are handled by the `parquet`
+//! module with the same mapping.
+//!
+//! # Examples
+//!
+//! ```
+//! use rustyvectors::formats::arrow::{self, ExportConfig, ImportConfig};
+//! use rustyvectors::metadata::{Metadata, Value};
+//! use rustyvectors::vector_database::VectorDatabase;
+//! use std::io::Cursor;
+//!
+//! let mut db = VectorDatabase::new(2)?;
+//! let mut metadata = Metadata::new();
+//! metadata.insert("title".to_string(), Value::from("Hello"));
+//! let id = db.add_with_metadata(&[1.0, 2.0], metadata)?;
+//! let mut file = Vec::new();
+//! arrow::export_ipc(&db, &mut file, &ExportConfig::default())?;
+//!
+//! let mut copy = VectorDatabase::new(2)?;
+//! let config = ImportConfig {
+//!   id_column: Some("id".to_string()),
+//!   ..ImportConfig::default()
+//! };
+//! arrow::import_ipc(&mut copy, Cursor::new(file), &config)?;
+//! assert_eq!(copy.get(id), db.get(id));
+//! assert_eq!(copy.metadata(id), db.metadata(id));
+//! # Ok::<(), rustyvectors::Error>(())
+//! ```
+
+use crate::error::{Error, Result};
+use crate::metadata::{Metadata, Value};
+use crate::vector_database::{check_vector, VectorDatabase, VectorId};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+  Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type,
+  UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow_array::{
+  Array, ArrayRef, BooleanArray, FixedSizeListArray, Float64Array, Int64Array,
+  ListArray, RecordBatch, StringArray, UInt64Array,
+};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{Read, Seek, Write};
+use std::sync::Arc;
+
+/// How the columns of a table are mapped to vectors and payloads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportConfig {
+  /// The column holding the vectors, a list or fixed-size list of `float32`
+  /// or `float64` values.
+  pub vector_column: String,
+  /// The column holding the ID of each vector, of an integer type. When
+  /// `None`, vectors get new IDs as with [`VectorDatabase::add`].
+  pub id_column: Option<String>,
+  /// The columns stored as metadata, under their column name. When `None`,
+  /// every column but the vector and ID ones is.
+  pub metadata_columns: Option<Vec<String>>,
+}
+
+impl Default for ImportConfig {
+  fn default() -> Self {
+    Self {
+      vector_column: "vector".to_string(),
+      id_column: None,
+      metadata_columns: None,
+    }
+  }
+}
+
+impl ImportConfig {
+  /// Returns the names of the columns read from the table.
+  pub(crate) fn columns<'a>(
+    &'a self,
+    schema: &'a Schema,
+  ) -> impl Iterator<Item = &'a str> + 'a {
+    let metadata: Vec<&str> = match &self.metadata_columns {
+      Some(columns) => columns.iter().map(String::as_str).collect(),
+      None => schema
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .filter(|&name| !self.is_special(name))
+        .collect(),
+    };
+    std::iter::once(self.vector_column.as_str())
+      .chain(self.id_column.as_deref())
+      .chain(metadata)
+  }
+
+  fn is_special(&self, column: &str) -> bool {
+    column == self.vector_column || self.id_column.as_deref() == Some(column)
+  }
+}
+
+/// How a database is laid out as a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportConfig {
+  /// The name of the vector column.
+  pub vector_column: String,
+  /// The name of the ID column.
+  pub id_column: String,
+  /// The number of rows in each record batch. Values below 1 are treated as
+  /// 1.
+  pub batch_size: usize,
+}
+
+impl Default for ExportConfig {
+  fn default() -> Self {
+    Self {
+      vector_column: "vector".to_string(),
+      id_column: "id".to_string(),
+      batch_size: 8192,
+    }
+  }
+}
+
+/// Adds the rows of a sequence of record batches to `db`, in order, and
+/// returns their IDs.
+///
+/// Returns [`Error::InvalidValue`] if a chosen column is missing or of an
+/// unsupported type, or if a row has a null vector or ID, and
+/// [`Error::Corrupted`] or [`Error::Io`] for the errors of the batches.
+/// Returns the errors of [`VectorDatabase::add`] if a vector cannot be
+/// added, and [`Error::InvalidValue`] if its ID is already in use. Each batch
+/// is checked before any of its rows is added, so the batches before the
+/// failing one stay in the database and none of the rows of the failing one
+/// do.
+pub fn import_batches(
+  db: &mut VectorDatabase,
+  batches: impl IntoIterator<Item = std::result::Result<RecordBatch, ArrowError>>,
+  config: &ImportConfig,
+) -> Result<Vec<VectorId>> {
+  let mut ids = Vec::new();
+  for batch in batches {
+    let batch = batch.map_err(arrow_error)?;
+    import_batch(db, &batch, config, &mut ids)?;
+  }
+  Ok(ids)
+}
+
+/// Adds the rows of an Arrow IPC file to `db`, in order, and returns their
+/// IDs.
+///
+/// The file is read one record batch at a time. Returns the errors of
+/// [`import_batches`].
+pub fn import_ipc(
+  db: &mut VectorDatabase,
+  reader: impl Read + Seek,
+  config: &ImportConfig,
+) -> Result<Vec<VectorId>> {
+  let reader = FileReader::try_new(reader, None).map_err(arrow_error)?;
+  import_batches(db, reader, config)
+}
+
+/// Returns the rows of `db` as record batches, by ascending ID.
+///
+/// Returns [`Error::InvalidValue`] if the values of a metadata field cannot
+/// share a column type, if a field holds maps, or if a field is named like
+/// the ID or vector column.
+pub fn record_batches<'a>(
+  db: &'a VectorDatabase,
+  config: &ExportConfig,
+) -> Result<RecordBatches<'a>> {
+  let dimension = i32::try_from(db.dimension()).map_err(|_| {
+    Error::InvalidValue("dimension too large for an Arrow list".to_string())
+  })?;
+  let mut columns: BTreeMap<&str, ColumnType> = BTreeMap::new();
+  for (_, _, metadata) in db.records() {
+    for (field, value) in metadata {
+      let column = columns.entry(field).or_insert(ColumnType::Null);
+      let merged = std::mem::replace(column, ColumnType::Null)
+        .merge(ColumnType::of(value)?);
+      *column = merged.map_err(|(a, b)| {
+        Error::InvalidValue(format!(
+          "metadata field {field} mixes {a} and {b} values"
+        ))
+      })?;
+    }
+  }
+  let columns: Vec<(String, ColumnType)> = columns
+    .into_iter()
+    .filter(|(_, column)| *column != ColumnType::Null)
+    .map(|(field, column)| (field.to_string(), column))
+    .collect();
+
+  let mut fields = vec![
+    Field::new(&config.id_column, DataType::UInt64, false),
+    Field::new(
+      &config.vector_column,
+      DataType::FixedSizeList(Arc::new(vector_item()), dimension),
+      false,
+    ),
+  ];
+  for (name, column) in &columns {
+    if *name == config.id_column || *name == config.vector_column {
+      return Err(Error::InvalidValue(format!(
+        "metadata field {name} has the name of the ID or vector column"
+      )));
+    }
+    fields.push(Field::new(name, column.data_type(), true));
+  }
+
+  let mut ids: Vec<_> = db.ids().collect();
+  ids.sort_unstable();
+  Ok(RecordBatches {
+    db,
+    ids,
+    columns,
+    schema: Arc::new(Schema::new(fields)),
+    dimension,
+    batch_size: config.batch_size.max(1),
+    next: 0,
+  })
+}
+
+/// Writes `db` as an Arrow IPC file, one record batch at a time.
+///
+/// Returns the errors of [`record_batches`], and [`Error::Io`] if writing
+/// fails.
+pub fn export_ipc(
+  db: &VectorDatabase,
+  writer: impl Write,
+  config: &ExportConfig,
+) -> Result<()> {
+  let batches = record_batches(db, config)?;
+  let mut writer = FileWriter::try_new_buffered(writer, &batches.schema())
+    .map_err(arrow_error)?;
+  for batch in batches {
+    writer.write(&batch).map_err(arrow_error)?;
+  }
+  writer.finish().map_err(arrow_error)?;
+  Ok(())
+}
+
+/// An iterator over the rows of a database as record batches, returned by
+/// [`record_batches`].
+///
+/// Each batch is built when it is requested, so that only one batch at a
+/// time is held in memory.
+pub struct RecordBatches<'a> {
+  db: &'a VectorDatabase,
+  ids: Vec<VectorId>,
+  columns: Vec<(String, ColumnType)>,
+  schema: SchemaRef,
+  dimension: i32,
+  batch_size: usize,
+  /// The position in `ids` of the first row of the next batch.
+  next: usize,
+}
+
+impl RecordBatches<'_> {
+  /// Returns the schema of the batches.
+  pub fn schema(&self) -> SchemaRef {
+    self.schema.clone()
+  }
+}
+
+impl fmt::Debug for RecordBatches<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("RecordBatches")
+      .field("schema", &self.schema)
+      .field("batch_size", &self.batch_size)
+      .field("rows_left", &(self.ids.len() - self.next))
+      .finish_non_exhaustive()
+  }
+}
+
+impl Iterator for RecordBatches<'_> {
+  type Item = RecordBatch;
+
+  fn next(&mut self) -> Option<RecordBatch> {
+    if self.next == self.ids.len() {
+      return None;
+    }
+    let end = self.ids.len().min(self.next + self.batch_size);
+    let ids = &self.ids[self.next..end];
+    self.next = end;
+
+    let mut components: Vec<f64> =
+      Vec::with_capacity(ids.len() * self.db.dimension());
+    let mut payloads = Vec::with_capacity(ids.len());
+    for &id in ids {
+      components.extend(self.db.get(id).expect("exported IDs exist"));
+      payloads.push(self.db.metadata(id).expect("exported IDs exist"));
+    }
+    let mut arrays: Vec<ArrayRef> = vec![
+      Arc::new(UInt64Array::from(ids.to_vec())),
+      Arc::new(FixedSizeListArray::new(
+        Arc::new(vector_item()),
+        self.dimension,
+        Arc::new(Float64Array::from(components)),
+        None,
+      )),
+    ];
+    for (name, column) in &self.columns {
+      let values: Vec<_> =
+        payloads.iter().map(|metadata| metadata.get(name)).collect();
+      arrays.push(column.build(&values));
+    }
+    let batch = RecordBatch::try_new(self.schema.clone(), arrays)
+      .expect("columns match the schema");
+    Some(batch)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let len = (self.ids.len() - self.next).div_ceil(self.batch_size);
+    (len, Some(len))
+  }
+}
+
+impl ExactSizeIterator for RecordBatches<'_> {}
+
+fn import_batch(
+  db: &mut VectorDatabase,
+  batch: &RecordBatch,
+  config: &ImportConfig,
+  ids: &mut Vec<VectorId>,
+) -> Result<()> {
+  let column = |name: &str| {
+    batch.column_by_name(name).ok_or_else(|| {
+      Error::InvalidValue(format!("the table has no column named {name}"))
+    })
+  };
+  let vectors = column(&config.vector_column)?;
+  let id_column = config.id_column.as_deref().map(column).transpose()?;
+  let metadata_columns = config
+    .columns(batch.schema_ref())
+    .skip(1 + usize::from(id_column.is_some()))
+    .map(|name| Ok((name, column(name)?)))
+    .collect::<Result<Vec<_>>>()?;
+
+  // Convert and check the whole batch first, so that malformed rows are
+  // caught before anything is added.
+  let mut rows = Vec::with_capacity(batch.num_rows());
+  let mut batch_ids = HashSet::new();
+  for row in 0..batch.num_rows() {
+    let vector = vector_at(vectors, row)?;
+    check_vector(&vector, db.dimension())?;
+    let id = id_column.map(|ids| id_at(ids, row)).transpose()?;
+    if let Some(id) = id {
+      if db.contains(id) || !batch_ids.insert(id) {
+        return Err(Error::InvalidValue(format!("id {id} is already in use")));
+      }
+      if id == VectorId::MAX {
+        return Err(Error::InvalidValue(format!("id {id} is out of range")));
+      }
+    }
+    let mut metadata = Metadata::new();
+    for &(name, values) in &metadata_columns {
+      if let Some(value) = value_at(values, row)? {
+        metadata.insert(name.to_string(), value);
+      }
+    }
+    rows.push((id, vector, metadata));
+  }
+
+  for (id, vector, metadata) in rows {
+    let id = match id {
+      Some(id) => {
+        db.insert_with_id(id, &vector, metadata)?;
+        id
+      }
+      None => db.add_with_metadata(&vector, metadata)?,
+    };
+    ids.push(id);
+  }
+  Ok(())
+}
+
+fn vector_at(column: &dyn Array, row: usize) -> Result<Vec<f64>> {
+  let components = match column.data_type() {
+    DataType::FixedSizeList(..) => column.as_fixed_size_list().value(row),
+    DataType::List(_) => column.as_list::<i32>().value(row),
+    DataType::LargeList(_) => column.as_list::<i64>().value(row),
+    data_type => {
+      return Err(Error::InvalidValue(format!(
+        "unsupported vector column type {data_type}, expected a list"
+      )))
+    }
+  };
+  if column.is_null(row) || components.null_count() > 0 {
+    return Err(Error::InvalidValue(format!("row {row} has a null vector")));
+  }
+  match components.data_type() {
+    DataType::Float32 => Ok(
+      components
+        .as_primitive::<Float32Type>()
+        .values()
+        .iter()
+        .map(|&x| x.into())
+        .collect(),
+    ),
+    DataType::Float64 => {
+      Ok(components.as_primitive::<Float64Type>().values().to_vec())
+    }
+    data_type => Err(Error::InvalidValue(format!(
+      "unsupported vector component type {data_type}, expected float32 or \
+       float64"
+    ))),
+  }
+}
+
+fn id_at(column: &dyn Array, row: usize) -> Result<VectorId> {
+  if column.is_null(row) {
+    return Err(Error::InvalidValue(format!("row {row} has a null ID")));
+  }
+  let id = match column.data_type() {
+    DataType::UInt64 => Some(column.as_primitive::<UInt64Type>().value(row)),
+    DataType::UInt32 => {
+      Some(column.as_primitive::<UInt32Type>().value(row).into())
+    }
+    DataType::Int64 => {
+      u64::try_from(column.as_primitive::<Int64Type>().value(row)).ok()
+    }
+    DataType::Int32 => {
+      u64::try_from(column.as_primitive::<Int32Type>().value(row)).ok()
+    }
+    data_type => {
+      return Err(Error::InvalidValue(format!(
+        "unsupported ID column type {data_type}, expected an integer"
+      )))
+    }
+  };
+  id.ok_or_else(|| Error::InvalidValue(format!("row {row} has a negative ID")))
+}
+
+/// Converts a cell to a metadata value, or `None` if it is null.
+fn value_at(column: &dyn Array, row: usize) -> Result<Option<Value>> {
+  if column.is_null(row) {
+    return Ok(None);
+  }
+  let value = match column.data_type() {
+    DataType::Boolean => Value::Bool(column.as_boolean().value(row)),
+    DataType::Int8 => {
+      i32::from(column.as_primitive::<Int8Type>().value(row)).into()
+    }
+    DataType::Int16 => {
+      i32::from(column.as_primitive::<Int16Type>().value(row)).into()
+    }
+    DataType::Int32 => column.as_primitive::<Int32Type>().value(row).into(),
+    DataType::Int64 => column.as_primitive::<Int64Type>().value(row).into(),
+    DataType::UInt8 => {
+      i32::from(column.as_primitive::<UInt8Type>().value(row)).into()
+    }
+    DataType::UInt16 => {
+      i32::from(column.as_primitive::<UInt16Type>().value(row)).into()
+    }
+    DataType::UInt32 => {
+      i64::from(column.as_primitive::<UInt32Type>().value(row)).into()
+    }
+    DataType::UInt64 => {
+      let value = column.as_primitive::<UInt64Type>().value(row);
+      match i64::try_from(value) {
+        Ok(value) => Value::Int(value),
+        Err(_) => Value::Float(value as f64),
+      }
+    }
+    DataType::Float32 => {
+      f64::from(column.as_primitive::<Float32Type>().value(row)).into()
+    }
+    DataType::Float64 => column.as_primitive::<Float64Type>().value(row).into(),
+    DataType::Utf8 => column.as_string::<i32>().value(row).into(),
+    DataType::LargeUtf8 => column.as_string::<i64>().value(row).into(),
+    DataType::List(_) => list_value(&column.as_list::<i32>().value(row))?,
+    DataType::LargeList(_) => list_value(&column.as_list::<i64>().value(row))?,
+    DataType::FixedSizeList(..) => {
+      list_value(&column.as_fixed_size_list().value(row))?
+    }
+    DataType::Struct(_) => {
+      let column = column.as_struct();
+      let mut map = Metadata::new();
+      for (name, field) in
+        column.column_names().into_iter().zip(column.columns())
+      {
+        if let Some(value) = value_at(field, row)? {
+          map.insert(name.to_string(), value);
+        }
+      }
+      Value::Map(map)
+    }
+    data_type => {
+      return Err(Error::InvalidValue(format!(
+        "unsupported metadata column type {data_type}"
+      )))
+    }
+  };
+  Ok(Some(value))
+}
+
+fn list_value(items: &ArrayRef) -> Result<Value> {
+  let items = (0..items.len())
+    .map(|i| Ok(value_at(items, i)?.unwrap_or(Value::Null)))
+    .collect::<Result<_>>()?;
+  Ok(Value::List(items))
+}
+
+/// The item field of exported vector columns.
+fn vector_item() -> Field {
+  Field::new("item", DataType::Float64, false)
+}
+
+/// The type of an exported metadata column.
+#[derive(Debug, Clone, PartialEq)]
+enum ColumnType {
+  /// Only null values, or none at all.
+  Null,
+  Bool,
+  Int,
+  Float,
+  String,
+  List(Box<ColumnType>),
+}
+
+impl ColumnType {
+  fn of(value: &Value) -> Result<Self> {
+    Ok(match value {
+      Value::Null => ColumnType::Null,
+      Value::Bool(_) => ColumnType::Bool,
+      Value::Int(_) => ColumnType::Int,
+      Value::Float(_) => ColumnType::Float,
+      Value::String(_) => ColumnType::String,
+      Value::List(items) => {
+        let mut item = ColumnType::Null;
+        for value in items {
+          item = item.merge(Self::of(value)?).map_err(|(a, b)| {
+            Error::InvalidValue(format!("a list mixes {a} and {b} values"))
+          })?;
+        }
+        ColumnType::List(Box::new(item))
+      }
+      Value::Map(_) => {
+        return Err(Error::InvalidValue(
+          "maps cannot be exported as Arrow columns".to_string(),
+        ))
+      }
+    })
+  }
+
+  /// Returns the type able to hold the values of both types, or both types
+  /// if there is none.
+  fn merge(self, other: Self) -> std::result::Result<Self, (Self, Self)> {
+    match (self, other) {
+      (ColumnType::Null, other) | (other, ColumnType::Null) => Ok(other),
+      (ColumnType::Int, ColumnType::Float)
+      | (ColumnType::Float, ColumnType::Int) => Ok(ColumnType::Float),
+      (ColumnType::List(a), ColumnType::List(b)) => match a.merge(*b) {
+        Ok(item) => Ok(ColumnType::List(Box::new(item))),
+        Err((a, b)) => {
+          Err((ColumnType::List(Box::new(a)), ColumnType::List(Box::new(b))))
+        }
+      },
+      (a, b) if a == b => Ok(a),
+      (a, b) => Err((a, b)),
+    }
+  }
+
+  fn data_type(&self) -> DataType {
+    match self {
+      ColumnType::Bool => DataType::Boolean,
+      ColumnType::Int => DataType::Int64,
+      ColumnType::Float => DataType::Float64,
+      // Lists with only null items get string items.
+      ColumnType::Null | ColumnType::String => DataType::Utf8,
+      ColumnType::List(item) => {
+        DataType::List(Arc::new(Field::new("item", item.data_type(), true)))
+      }
+    }
+  }
+
+  /// Builds a column of this type, with nulls for missing values.
+  fn build(&self, values: &[Option<&Value>]) -> ArrayRef {
+    match self {
+      ColumnType::Bool => Arc::new(BooleanArray::from(
+        values
+          .iter()
+          .map(|value| value.and_then(Value::as_bool))
+          .collect::<Vec<_>>(),
+      )),
+      ColumnType::Int => Arc::new(Int64Array::from(
+        values
+          .iter()
+          .map(|value| value.and_then(Value::as_i64))
+          .collect::<Vec<_>>(),
+      )),
+      ColumnType::Float => Arc::new(Float64Array::from(
+        values
+          .iter()
+          .map(|value| value.and_then(Value::as_f64))
+          .collect::<Vec<_>>(),
+      )),
+      ColumnType::Null | ColumnType::String => Arc::new(StringArray::from(
+        values
+          .iter()
+          .map(|value| value.and_then(Value::as_str))
+          .collect::<Vec<_>>(),
+      )),
+      ColumnType::List(item) => {
+        let lists: Vec<_> = values
+          .iter()
+          .map(|value| value.and_then(Value::as_list))
+          .collect();
+        let items: Vec<_> = lists
+          .iter()
+          .flatten()
+          .flat_map(|list| list.iter())
+          .map(|value| Some(value).filter(|value| !value.is_null()))
+          .collect();
+        Arc::new(ListArray::new(
+          Arc::new(Field::new("item", item.data_type(), true)),
+          OffsetBuffer::from_lengths(
+            lists.iter().map(|list| list.map_or(0, <[Value]>::len)),
+          ),
+          item.build(&items),
+          Some(NullBuffer::from_iter(lists.iter().map(Option::is_some))),
+        ))
+      }
+    }
+  }
+}
+
+impl fmt::Display for ColumnType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ColumnType::Null => write!(f, "null"),
+      ColumnType::Bool => write!(f, "boolean"),
+      ColumnType::Int => write!(f, "integer"),
+      ColumnType::Float => write!(f, "float"),
+      ColumnType::String => write!(f, "string"),
+      ColumnType::List(item) => write!(f, "list of {item}"),
+    }
+  }
+}
+
+pub(crate) fn arrow_error(error: ArrowError) -> Error {
+  match error {
+    ArrowError::IoError(_, error) => error.into(),
+    error => Error::Corrupted(format!("invalid Arrow data: {error}")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Cursor;
+
+  fn metadata(entries: &[(&str, Value)]) -> Metadata {
+    entries
+      .iter()
+      .map(|(field, value)| (field.to_string(), value.clone()))
+      .collect()
+  }
+
+  fn sample() -> VectorDatabase {
+    let mut db = VectorDatabase::new(2).unwrap();
+    db.add_with_metadata(
+      &[1.0, 2.0],
+      metadata(&[
+        ("title", "a".into()),
+        ("score", 1.into()),
+        ("tags", vec!["x", "y"].into()),
+      ]),
+    )
+    .unwrap();
+    let removed = db.add(&[0.0, 0.0]).unwrap();
+    db.add_with_metadata(
+      &[3.0, 4.0],
+      metadata(&[("score", 2.5.into()), ("empty", Value::Null)]),
+    )
+    .unwrap();
+    db.add_with_metadata(
+      &[5.0, 6.0],
+      metadata(&[("tags", Value::List(vec![]))]),
+    )
+    .unwrap();
+    db.remove(removed);
+    db
+  }
+
+  #[test]
+  fn test_record_batches() {
+    let db = sample();
+    let config = ExportConfig {
+      batch_size: 2,
+      ..ExportConfig::default()
+    };
+    let batches: Vec<_> = record_batches(&db, &config).unwrap().collect();
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].num_rows(), 2);
+    assert_eq!(batches[1].num_rows(), 1);
+
+    let schema = batches[0].schema();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name()).collect();
+    assert_eq!(names, ["id", "vector", "score", "tags", "title"]);
+    assert_eq!(schema.field(2).data_type(), &DataType::Float64);
+    assert_eq!(
+      schema.field(3).data_type(),
+      &DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)))
+    );
+
+    let ids = batches[1].column(0).as_primitive::<UInt64Type>();
+    assert_eq!(ids.value(0), 3);
+    let scores = batches[0].column(2).as_primitive::<Float64Type>();
+    assert_eq!(scores.value(1), 2.5);
+    assert!(batches[1].column(2).is_null(0));
+  }
+
+  #[test]
+  fn test_ipc_round_trip() {
+    let db = sample();
+    let mut file = Vec::new();
+    export_ipc(&db, &mut file, &ExportConfig::default()).unwrap();
+
+    let config = ImportConfig {
+      id_column: Some("id".to_string()),
+      ..ImportConfig::default()
+    };
+    let mut copy = VectorDatabase::new(2).unwrap();
+    let ids = import_ipc(&mut copy, Cursor::new(&file), &config).unwrap();
+    assert_eq!(ids, [0, 2, 3]);
+    for id in ids {
+      assert_eq!(copy.get(id), db.get(id));
+    }
+    // Integers in a float column come back as floats, and null values are
+    // left out.
+    assert_eq!(
+      copy.metadata(0),
+      Some(&metadata(&[
+        ("title", "a".into()),
+        ("score", 1.0.into()),
+        ("tags", vec!["x", "y"].into()),
+      ]))
+    );
+    assert_eq!(copy.metadata(2), Some(&metadata(&[("score", 2.5.into())])));
+    assert_eq!(copy.add(&[0.0, 0.0]).unwrap(), 4);
+
+    // Without an ID column, the IDs become metadata.
+    let config = ImportConfig {
+      metadata_columns: Some(vec!["id".to_string()]),
+      ..ImportConfig::default()
+    };
+    let mut copy = VectorDatabase::new(2).unwrap();
+    let ids = import_ipc(&mut copy, Cursor::new(&file), &config).unwrap();
+    assert_eq!(ids, [0, 1, 2]);
+    assert_eq!(copy.metadata(2), Some(&metadata(&[("id", 3.into())])));
+  }
+
+  #[test]
+  fn test_import_column_types() {
+    let schema = Arc::new(Schema::new(vec![
+      Field::new(
+        "embedding",
+        DataType::List(Arc::new(Field::new("item", DataType::Float32, true))),
+        true,
+      ),
+      Field::new("flag", DataType::Boolean, true),
+      Field::new("count", DataType::UInt64, true),
+    ]));
+    let vectors = ListArray::from_iter_primitive::<Float32Type, _, _>([
+      Some(vec![Some(1.0), Some(2.0)]),
+      Some(vec![Some(3.0), Some(4.0)]),
+    ]);
+    let batch = RecordBatch::try_new(
+      schema,
+      vec![
+        Arc::new(vectors),
+        Arc::new(BooleanArray::from(vec![Some(true), None])),
+        Arc::new(UInt64Array::from(vec![7, u64::MAX])),
+      ],
+    )
+    .unwrap();
+
+    let config = ImportConfig {
+      vector_column: "embedding".to_string(),
+      ..ImportConfig::default()
+    };
+    let mut db = VectorDatabase::new(2).unwrap();
+    let ids = import_batches(&mut db, [Ok(batch.clone())], &config).unwrap();
+    assert_eq!(ids, [0, 1]);
+    assert_eq!(db.get(1).unwrap().to_vec(), [3.0, 4.0]);
+    assert_eq!(
+      db.metadata(0),
+      Some(&metadata(&[("flag", true.into()), ("count", 7.into())]))
+    );
+    assert_eq!(
+      db.metadata(1),
+      Some(&metadata(&[("count", (u64::MAX as f64).into())]))
+    );
+
+    let mut db = VectorDatabase::new(3).unwrap();
+    assert_eq!(
+      import_batches(&mut db, [Ok(batch.clone())], &config),
+      Err(Error::DimensionMismatch {
+        expected: 3,
+        found: 2
+      })
+    );
+    let mut db = VectorDatabase::new(2).unwrap();
+    assert!(matches!(
+      import_batches(&mut db, [Ok(batch)], &ImportConfig::default()),
+      Err(Error::InvalidValue(_))
+    ));
+  }
+
+  #[test]
+  fn test_failing_batch_adds_nothing() {
+    let schema = Arc::new(Schema::new(vec![
+      Field::new("id", DataType::UInt64, false),
+      Field::new(
+        "vector",
+        DataType::List(Arc::new(Field::new("item", DataType::Float64, true))),
+        false,
+      ),
+    ]));
+    let batch = |ids: Vec<u64>, vectors: Vec<Vec<f64>>| {
+      let vectors = ListArray::from_iter_primitive::<Float64Type, _, _>(
+        vectors
+          .into_iter()
+          .map(|vector| Some(vector.into_iter().map(Some))),
+      );
+      let columns: Vec<ArrayRef> =
+        vec![Arc::new(UInt64Array::from(ids)), Arc::new(vectors)];
+      RecordBatch::try_new(schema.clone(), columns).unwrap()
+    };
+    let config = ImportConfig {
+      id_column: Some("id".to_string()),
+      ..ImportConfig::default()
+    };
+
+    let mut db = VectorDatabase::new(2).unwrap();
+    let first = batch(vec![1, 2], vec![vec![1.0, 1.0], vec![2.0, 2.0]]);
+    let invalid = [
+      batch(vec![3, 4], vec![vec![3.0, 3.0], vec![4.0]]),
+      batch(vec![3, 3], vec![vec![3.0, 3.0], vec![4.0, 4.0]]),
+      batch(vec![3, 2], vec![vec![3.0, 3.0], vec![4.0, 4.0]]),
+      batch(vec![3, 4], vec![vec![3.0, 3.0], vec![f64::NAN, 4.0]]),
+    ];
+    for second in invalid {
+      let mut db = VectorDatabase::new(2).unwrap();
+      assert!(import_batches(
+        &mut db,
+        [Ok(first.clone()), Ok(second)],
+        &config
+      )
+      .is_err());
+      let mut ids: Vec<_> = db.ids().collect();
+      ids.sort_unstable();
+      assert_eq!(ids, [1, 2]);
+    }
+    let second = batch(vec![4, 3], vec![vec![4.0, 4.0], vec![3.0, 3.0]]);
+    assert_eq!(
+      import_batches(&mut db, [Ok(first), Ok(second)], &config).unwrap(),
+      [1, 2, 4, 3]
+    );
+  }
+
+  #[test]
+  fn test_export_rejects_mixed_fields() {
+    let mut db = VectorDatabase::new(1).unwrap();
+    db.add_with_metadata(&[1.0], metadata(&[("x", 1.into())]))
+      .unwrap();
+    db.add_with_metadata(&[2.0], metadata(&[("x", "one".into())]))
+      .unwrap();
+    let error = record_batches(&db, &ExportConfig::default()).unwrap_err();
+    assert_eq!(
+      error,
+      Error::InvalidValue(
+        "metadata field x mixes integer and string values".to_string()
+      )
+    );
+  }
+}
@@ -0,0 +1,15 @@
+//! Import and export of vectors in the file formats of other tools.
+//!
+//! - [`vecs`]: the `.fvecs`, `.ivecs` and `.bvecs` formats of the standard
+//!   approximate nearest neighbor benchmark datasets.
+//! - [`npy`]: the NumPy `.npy` format and `.npz` archives.
+//! - `arrow`: Apache Arrow record batches and IPC files, with the `arrow`
+//!   feature.
+//! - `parquet`: Apache Parquet files, with the `parquet` feature.
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod npy;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod vecs;
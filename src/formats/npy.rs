@@ -0,0 +1,538 @@
+//! The NumPy `.npy` format, and `.npz` archives of `.npy` files.
+//!
+//! Matrices of `f32` or `f64` values with one vector per row can be read, in
+//! either byte order and in C (row-major) or Fortran (column-major) order,
+//! which covers the output of `numpy.save` and `numpy.savez` for float
+//! embeddings. Matrices are written as `f64` values in C order, which
+//! `numpy.load` reads back as `float64` arrays.
+//!
+//! # Examples
+//!
+//! ```
+//! use rustyvectors::formats::npy;
+//! use rustyvectors::vector_database::VectorDatabase;
+//!
+//! let mut db = VectorDatabase::new(2)?;
+//! db.add(&[1.0, 2.0])?;
+//! db.add(&[3.0, 4.0])?;
+//! let mut file = Vec::new();
+//! npy::export_npy(&db, &mut file)?;
+//!
+//! let mut copy = VectorDatabase::new(2)?;
+//! assert_eq!(npy::load_npy(&mut copy, file.as_slice())?, [0, 1]);
+//! assert_eq!(copy.get(1), db.get(1));
+//! # Ok::<(), rustyvectors::Error>(())
+//! ```
+
+use crate::error::{Error, Result};
+use crate::vector_database::{VectorDatabase, VectorId};
+use ndarray::{Array2, ArrayView2, ShapeBuilder};
+use std::io::{self, BufWriter, Read, Seek, Write};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// The alignment numpy pads the header to.
+const HEADER_ALIGNMENT: usize = 64;
+
+/// Reads a `.npy` file holding a 2-dimensional matrix of `f32` or `f64`
+/// values, with one vector per row. A 1-dimensional array is read as a
+/// single row.
+///
+/// Returns [`Error::InvalidValue`] for arrays of other types or dimensions,
+/// [`Error::Corrupted`] if the file is malformed or truncated, and
+/// [`Error::Io`] if reading fails.
+pub fn read_npy(mut reader: impl Read) -> Result<Array2<f64>> {
+  let mut preamble = [0; 8];
+  read_exact(&mut reader, &mut preamble)?;
+  if &preamble[..6] != MAGIC {
+    return Err(corrupted("not a .npy file"));
+  }
+  let header_len = match preamble[6] {
+    1 => {
+      let mut len = [0; 2];
+      read_exact(&mut reader, &mut len)?;
+      u16::from_le_bytes(len).into()
+    }
+    2 | 3 => {
+      let mut len = [0; 4];
+      read_exact(&mut reader, &mut len)?;
+      u32::from_le_bytes(len) as usize
+    }
+    major => {
+      return Err(Error::InvalidValue(format!(
+        "unsupported .npy format version {major}"
+      )))
+    }
+  };
+  let header = read_bytes(&mut reader, header_len)?;
+  let header = String::from_utf8_lossy(&header);
+  let header = Header::parse(&header)?;
+
+  let len = header
+    .rows
+    .checked_mul(header.columns)
+    .and_then(|len| len.checked_mul(header.item_size()))
+    .ok_or_else(|| corrupted("array too large"))?;
+  let bytes = read_bytes(&mut reader, len)?;
+  let values: Vec<f64> = match (header.dtype, header.little_endian) {
+    (Dtype::F32, true) => decode(&bytes, |b| f32::from_le_bytes(b).into()),
+    (Dtype::F32, false) => decode(&bytes, |b| f32::from_be_bytes(b).into()),
+    (Dtype::F64, true) => decode(&bytes, f64::from_le_bytes),
+    (Dtype::F64, false) => decode(&bytes, f64::from_be_bytes),
+  };
+
+  // An empty array may still have an axis longer than ndarray allows.
+  let too_large = |_| corrupted("array too large");
+  let shape = (header.rows, header.columns);
+  let matrix = if header.fortran_order {
+    let matrix =
+      Array2::from_shape_vec(shape.f(), values).map_err(too_large)?;
+    matrix.as_standard_layout().into_owned()
+  } else {
+    Array2::from_shape_vec(shape, values).map_err(too_large)?
+  };
+  Ok(matrix)
+}
+
+/// Writes `matrix` as a `.npy` file of `f64` values in C order.
+pub fn write_npy(writer: impl Write, matrix: ArrayView2<f64>) -> Result<()> {
+  let mut writer = BufWriter::new(writer);
+  let (rows, columns) = matrix.dim();
+  let mut header = format!(
+    "{{'descr': '<f8', 'fortran_order': False, 'shape': ({rows}, {columns}), }}"
+  );
+  // The preamble is 10 bytes long and the header ends with a newline.
+  let padding = (HEADER_ALIGNMENT - (10 + header.len() + 1) % HEADER_ALIGNMENT)
+    % HEADER_ALIGNMENT;
+  header.extend(std::iter::repeat_n(' ', padding));
+  header.push('\n');
+
+  writer.write_all(MAGIC)?;
+  writer.write_all(&[1, 0])?;
+  let header_len = u16::try_from(header.len()).expect("header too long");
+  writer.write_all(&header_len.to_le_bytes())?;
+  writer.write_all(header.as_bytes())?;
+  for value in matrix.iter() {
+    writer.write_all(&value.to_le_bytes())?;
+  }
+  writer.flush()?;
+  Ok(())
+}
+
+/// Adds the rows of a `.npy` matrix to `db`, in order, and returns their IDs.
+///
+/// Besides the errors of [`read_npy`], returns the errors of
+/// [`VectorDatabase::add`] if a row cannot be added; the rows before it stay
+/// in the database.
+pub fn load_npy(
+  db: &mut VectorDatabase,
+  reader: impl Read,
+) -> Result<Vec<VectorId>> {
+  add_rows(db, read_npy(reader)?)
+}
+
+/// Writes the vectors of `db` as a `.npy` matrix, one per row by ascending
+/// ID, and returns their IDs in row order.
+pub fn export_npy(
+  db: &VectorDatabase,
+  writer: impl Write,
+) -> Result<Vec<VectorId>> {
+  let mut ids: Vec<_> = db.ids().collect();
+  ids.sort_unstable();
+  let mut matrix = Array2::zeros((ids.len(), db.dimension()));
+  for (mut row, &id) in matrix.outer_iter_mut().zip(&ids) {
+    if let Some(vector) = db.get(id) {
+      row.assign(&vector);
+    }
+  }
+  write_npy(writer, matrix.view())?;
+  Ok(ids)
+}
+
+/// Reads all the matrices of a `.npz` archive, as written by `numpy.savez`
+/// or `numpy.savez_compressed`, along with their names.
+///
+/// Archive members are read with [`read_npy`], and named after their file
+/// name without the `.npy` extension. Returns [`Error::Corrupted`] if the
+/// archive is malformed, besides the errors of [`read_npy`].
+pub fn read_npz(
+  reader: impl Read + Seek,
+) -> Result<Vec<(String, Array2<f64>)>> {
+  let mut archive = zip::ZipArchive::new(reader).map_err(zip_error)?;
+  let mut matrices = Vec::new();
+  for i in 0..archive.len() {
+    let file = archive.by_index(i).map_err(zip_error)?;
+    let Some(name) = file.name().strip_suffix(".npy") else {
+      continue;
+    };
+    let name = name.to_string();
+    matrices.push((name, read_npy(file)?));
+  }
+  Ok(matrices)
+}
+
+/// Adds the rows of the matrix called `name` in a `.npz` archive to `db`, in
+/// order, and returns their IDs.
+///
+/// Returns [`Error::InvalidValue`] if the archive holds no such matrix,
+/// besides the errors of [`read_npz`] and [`load_npy`].
+pub fn load_npz(
+  db: &mut VectorDatabase,
+  reader: impl Read + Seek,
+  name: &str,
+) -> Result<Vec<VectorId>> {
+  let mut archive = zip::ZipArchive::new(reader).map_err(zip_error)?;
+  let file = match archive.by_name(&format!("{name}.npy")) {
+    Ok(file) => file,
+    Err(zip::result::ZipError::FileNotFound) => {
+      return Err(Error::InvalidValue(format!("no array named {name}")))
+    }
+    Err(error) => return Err(zip_error(error)),
+  };
+  load_npy(db, file)
+}
+
+fn add_rows(
+  db: &mut VectorDatabase,
+  matrix: Array2<f64>,
+) -> Result<Vec<VectorId>> {
+  if matrix.ncols() != db.dimension() {
+    return Err(Error::DimensionMismatch {
+      expected: db.dimension(),
+      found: matrix.ncols(),
+    });
+  }
+  matrix
+    .outer_iter()
+    .map(|row| db.add(row.as_slice().expect("rows are contiguous")))
+    .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dtype {
+  F32,
+  F64,
+}
+
+/// The parsed header dictionary of a `.npy` file.
+#[derive(Debug, PartialEq)]
+struct Header {
+  dtype: Dtype,
+  little_endian: bool,
+  fortran_order: bool,
+  rows: usize,
+  columns: usize,
+}
+
+impl Header {
+  /// Parses a header such as
+  /// `{'descr': '<f4', 'fortran_order': False, 'shape': (10, 3), }`.
+  fn parse(header: &str) -> Result<Self> {
+    let descr = dict_value(header, "descr")?;
+    let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+    let (byte_order, dtype) = match descr.chars().next() {
+      Some('<' | '>' | '=' | '|') => descr.split_at(1),
+      _ => ("=", descr),
+    };
+    let little_endian = match byte_order {
+      "<" => true,
+      ">" => false,
+      _ => cfg!(target_endian = "little"),
+    };
+    let dtype = match dtype {
+      "f4" => Dtype::F32,
+      "f8" => Dtype::F64,
+      _ => {
+        return Err(Error::InvalidValue(format!(
+          "unsupported array type '{descr}', expected float32 or float64"
+        )))
+      }
+    };
+
+    let fortran_order = match dict_value(header, "fortran_order")? {
+      "True" => true,
+      "False" => false,
+      value => {
+        return Err(corrupted(&format!("invalid fortran_order {value}")))
+      }
+    };
+
+    let shape = dict_value(header, "shape")?;
+    let dimensions = shape
+      .trim_start_matches('(')
+      .trim_end_matches(')')
+      .split(',')
+      .map(str::trim)
+      .filter(|dimension| !dimension.is_empty())
+      .map(|dimension| {
+        dimension
+          .parse()
+          .map_err(|_| corrupted(&format!("invalid shape {shape}")))
+      })
+      .collect::<Result<Vec<usize>>>()?;
+    let (rows, columns) = match dimensions[..] {
+      [columns] => (1, columns),
+      [rows, columns] => (rows, columns),
+      _ => {
+        return Err(Error::InvalidValue(format!(
+          "expected a 1 or 2-dimensional array, got shape {shape}"
+        )))
+      }
+    };
+
+    Ok(Self {
+      dtype,
+      little_endian,
+      fortran_order,
+      rows,
+      columns,
+    })
+  }
+
+  fn item_size(&self) -> usize {
+    match self.dtype {
+      Dtype::F32 => 4,
+      Dtype::F64 => 8,
+    }
+  }
+}
+
+/// Returns the text of the value of `key` in a Python dictionary literal.
+fn dict_value<'a>(dict: &'a str, key: &str) -> Result<&'a str> {
+  let missing = || corrupted(&format!("header has no '{key}' entry"));
+  let start = ["'", "\""]
+    .iter()
+    .find_map(|quote| dict.find(&format!("{quote}{key}{quote}")))
+    .ok_or_else(missing)?;
+  let rest = &dict[start + key.len() + 2..];
+  let rest = rest.trim_start().strip_prefix(':').ok_or_else(missing)?;
+  let rest = rest.trim_start();
+  // The value ends at the first comma or brace outside of parentheses.
+  let mut depth = 0;
+  let end = rest
+    .char_indices()
+    .find(|&(_, c)| match c {
+      '(' => {
+        depth += 1;
+        false
+      }
+      ')' => {
+        depth -= 1;
+        false
+      }
+      ',' | '}' => depth == 0,
+      _ => false,
+    })
+    .map_or(rest.len(), |(end, _)| end);
+  Ok(rest[..end].trim())
+}
+
+fn decode<const N: usize>(
+  bytes: &[u8],
+  from_bytes: impl Fn([u8; N]) -> f64,
+) -> Vec<f64> {
+  bytes
+    .chunks_exact(N)
+    .map(|chunk| from_bytes(chunk.try_into().unwrap()))
+    .collect()
+}
+
+fn corrupted(message: &str) -> Error {
+  Error::Corrupted(message.to_string())
+}
+
+fn zip_error(error: zip::result::ZipError) -> Error {
+  match error {
+    zip::result::ZipError::Io(error) => error.into(),
+    error => Error::Corrupted(format!("invalid .npz archive: {error}")),
+  }
+}
+
+/// Fills `buffer`, reporting a premature end of file as corruption.
+fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> Result<()> {
+  reader.read_exact(buffer).map_err(|error| {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+      corrupted("file is truncated")
+    } else {
+      Error::from(error)
+    }
+  })
+}
+
+/// Reads `len` bytes, growing the buffer as they arrive so that a damaged
+/// header cannot make it allocate more memory than the file holds.
+fn read_bytes(reader: &mut impl Read, len: usize) -> Result<Vec<u8>> {
+  let mut bytes = Vec::new();
+  reader.take(len as u64).read_to_end(&mut bytes)?;
+  if bytes.len() < len {
+    return Err(corrupted("file is truncated"));
+  }
+  Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ndarray::arr2;
+  use std::io::Cursor;
+
+  /// Builds a `.npy` file the way numpy would.
+  fn npy(
+    descr: &str,
+    fortran_order: bool,
+    shape: &str,
+    data: &[u8],
+  ) -> Vec<u8> {
+    let order = if fortran_order { "True" } else { "False" };
+    let mut header = format!(
+      "{{'descr': '{descr}', 'fortran_order': {order}, 'shape': {shape}, }}"
+    );
+    while (10 + header.len() + 1) % 64 != 0 {
+      header.push(' ');
+    }
+    header.push('\n');
+    let mut file = MAGIC.to_vec();
+    file.extend_from_slice(&[1, 0]);
+    file.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    file.extend_from_slice(header.as_bytes());
+    file.extend_from_slice(data);
+    file
+  }
+
+  #[test]
+  fn test_read_float_types_and_orders() {
+    let expected = arr2(&[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+    let c_order = [1.0_f32, 2.0, 3.0, 4.0, 5.0, 6.0];
+    let fortran_order = [1.0_f32, 4.0, 2.0, 5.0, 3.0, 6.0];
+
+    let le: Vec<u8> = c_order.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let file = npy("<f4", false, "(2, 3)", &le);
+    assert_eq!(read_npy(file.as_slice()).unwrap(), expected);
+
+    let be: Vec<u8> =
+      fortran_order.iter().flat_map(|x| x.to_be_bytes()).collect();
+    let file = npy(">f4", true, "(2, 3)", &be);
+    assert_eq!(read_npy(file.as_slice()).unwrap(), expected);
+
+    let f64s: Vec<u8> = fortran_order
+      .iter()
+      .flat_map(|&x| f64::from(x).to_le_bytes())
+      .collect();
+    let file = npy("<f8", true, "(2, 3)", &f64s);
+    assert_eq!(read_npy(file.as_slice()).unwrap(), expected);
+
+    let file = npy("<f8", false, "(3,)", &f64s[..24]);
+    assert_eq!(read_npy(file.as_slice()).unwrap(), arr2(&[[1.0, 4.0, 2.0]]));
+  }
+
+  #[test]
+  fn test_write_round_trip() {
+    let matrix = arr2(&[[0.5, -1.0], [1e300, 0.0], [3.0, 4.0]]);
+    let mut file = Vec::new();
+    write_npy(&mut file, matrix.view()).unwrap();
+    let header_len = u16::from_le_bytes([file[8], file[9]]) as usize;
+    assert_eq!((10 + header_len) % 64, 0);
+    assert_eq!(file.len(), 10 + header_len + 6 * 8);
+    assert_eq!(read_npy(file.as_slice()).unwrap(), matrix);
+
+    let transposed = matrix.t();
+    let mut file = Vec::new();
+    write_npy(&mut file, transposed).unwrap();
+    assert_eq!(read_npy(file.as_slice()).unwrap(), transposed);
+  }
+
+  #[test]
+  fn test_invalid_files_are_rejected() {
+    let data = [0; 8];
+    for (descr, shape) in [("<i4", "(2,)"), ("<f8", "(1, 1, 1)")] {
+      assert!(matches!(
+        read_npy(npy(descr, false, shape, &data).as_slice()),
+        Err(Error::InvalidValue(_))
+      ));
+    }
+    assert_eq!(
+      read_npy(npy("<f8", false, "(2, 1)", &data).as_slice()),
+      Err(corrupted("file is truncated"))
+    );
+    let huge = npy("<f8", false, "(100000000, 1000)", &data);
+    assert_eq!(
+      read_npy(huge.as_slice()),
+      Err(corrupted("file is truncated"))
+    );
+    for fortran_order in [false, true] {
+      let empty = npy("<f8", fortran_order, "(18446744073709551615, 0)", &[]);
+      assert_eq!(
+        read_npy(empty.as_slice()),
+        Err(corrupted("array too large"))
+      );
+    }
+    let mut huge_header = npy("<f8", false, "(1,)", &data);
+    huge_header[6] = 2;
+    huge_header.splice(8..10, u32::MAX.to_le_bytes());
+    assert_eq!(
+      read_npy(huge_header.as_slice()),
+      Err(corrupted("file is truncated"))
+    );
+    assert_eq!(
+      read_npy(&b"PK\x03\x04 not npy"[..]),
+      Err(corrupted("not a .npy file"))
+    );
+  }
+
+  #[test]
+  fn test_npz_archives() {
+    let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, matrix, method) in [
+      (
+        "train",
+        arr2(&[[1.0, 2.0], [3.0, 4.0]]),
+        zip::CompressionMethod::Stored,
+      ),
+      (
+        "test",
+        arr2(&[[5.0, 6.0]]),
+        zip::CompressionMethod::Deflated,
+      ),
+    ] {
+      let options =
+        zip::write::SimpleFileOptions::default().compression_method(method);
+      archive.start_file(format!("{name}.npy"), options).unwrap();
+      write_npy(&mut archive, matrix.view()).unwrap();
+    }
+    let file = archive.finish().unwrap().into_inner();
+
+    let matrices = read_npz(Cursor::new(&file)).unwrap();
+    assert_eq!(matrices.len(), 2);
+    assert_eq!(matrices[1], ("test".to_string(), arr2(&[[5.0, 6.0]])));
+
+    let mut db = VectorDatabase::new(2).unwrap();
+    assert_eq!(
+      load_npz(&mut db, Cursor::new(&file), "train").unwrap(),
+      [0, 1]
+    );
+    assert_eq!(db.nearest(&[3.0, 4.0]).unwrap(), Some(1));
+    assert!(matches!(
+      load_npz(&mut db, Cursor::new(&file), "missing"),
+      Err(Error::InvalidValue(_))
+    ));
+    assert!(matches!(
+      read_npz(Cursor::new(b"not a zip")),
+      Err(Error::Corrupted(_))
+    ));
+  }
+
+  #[test]
+  fn test_load_rejects_wrong_dimension() {
+    let mut file = Vec::new();
+    write_npy(&mut file, arr2(&[[1.0, 2.0, 3.0]]).view()).unwrap();
+    let mut db = VectorDatabase::new(2).unwrap();
+    assert_eq!(
+      load_npy(&mut db, file.as_slice()),
+      Err(Error::DimensionMismatch {
+        expected: 2,
+        found: 3
+      })
+    );
+    assert!(db.is_empty());
+  }
+}
@@ -0,0 +1,136 @@
+//! Apache Parquet files.
+//!
+//! Tables are mapped to vectors and payloads as described in the
+//! [`arrow`](super::arrow) module, and read and written one record batch at
+//! a time. Only the columns chosen by the [`ImportConfig`] are read.
+//!
+//! # Examples
+//!
+//! ```
+//! use rustyvectors::formats::arrow::{ExportConfig, ImportConfig};
+//! use rustyvectors::formats::parquet;
+//! use rustyvectors::vector_database::VectorDatabase;
+//!
+//! let mut db = VectorDatabase::new(2)?;
+//! db.add(&[1.0, 2.0])?;
+//! let mut file = tempfile::tempfile()?;
+//! parquet::export_parquet(&db, file.try_clone()?, &ExportConfig::default())?;
+//!
+//! let mut copy = VectorDatabase::new(2)?;
+//! parquet::import_parquet(&mut copy, file, &ImportConfig::default())?;
+//! assert_eq!(copy.get(0), db.get(0));
+//! # Ok::<(), rustyvectors::Error>(())
+//! ```
+
+use super::arrow::{self, ExportConfig, ImportConfig};
+use crate::error::{Error, Result};
+use crate::vector_database::{VectorDatabase, VectorId};
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::arrow::{ArrowWriter, ProjectionMask};
+use ::parquet::errors::ParquetError;
+use ::parquet::file::reader::ChunkReader;
+use std::io::Write;
+
+/// Adds the rows of a Parquet file to `db`, in order, and returns their IDs.
+///
+/// Returns [`Error::Corrupted`] if the file is malformed, besides the errors
+/// of [`arrow::import_batches`].
+pub fn import_parquet(
+  db: &mut VectorDatabase,
+  file: impl ChunkReader + 'static,
+  config: &ImportConfig,
+) -> Result<Vec<VectorId>> {
+  let builder =
+    ParquetRecordBatchReaderBuilder::try_new(file).map_err(parquet_error)?;
+  let schema = builder.schema().clone();
+  let mut columns = Vec::new();
+  for name in config.columns(&schema) {
+    let (column, _) = schema.column_with_name(name).ok_or_else(|| {
+      Error::InvalidValue(format!("the table has no column named {name}"))
+    })?;
+    columns.push(column);
+  }
+  let mask = ProjectionMask::roots(builder.parquet_schema(), columns);
+  let reader = builder
+    .with_projection(mask)
+    .build()
+    .map_err(parquet_error)?;
+  arrow::import_batches(db, reader, config)
+}
+
+/// Writes `db` as a Parquet file, one record batch at a time.
+///
+/// Returns the errors of [`arrow::record_batches`], and [`Error::Io`] if
+/// writing fails.
+pub fn export_parquet(
+  db: &VectorDatabase,
+  writer: impl Write + Send,
+  config: &ExportConfig,
+) -> Result<()> {
+  let batches = arrow::record_batches(db, config)?;
+  let mut writer = ArrowWriter::try_new(writer, batches.schema(), None)
+    .map_err(parquet_error)?;
+  for batch in batches {
+    writer.write(&batch).map_err(parquet_error)?;
+  }
+  writer.close().map_err(parquet_error)?;
+  Ok(())
+}
+
+fn parquet_error(error: ParquetError) -> Error {
+  match error {
+    ParquetError::External(error) => match error.downcast::<std::io::Error>() {
+      Ok(error) => (*error).into(),
+      Err(error) => Error::Corrupted(format!("invalid Parquet file: {error}")),
+    },
+    error => Error::Corrupted(format!("invalid Parquet file: {error}")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::metadata::{Metadata, Value};
+
+  #[test]
+  fn test_parquet_round_trip() {
+    let mut db = VectorDatabase::new(3).unwrap();
+    for i in 0..10 {
+      let mut metadata = Metadata::new();
+      metadata.insert("i".to_string(), Value::from(i));
+      metadata.insert("odd".to_string(), Value::from(i % 2 == 1));
+      db.add_with_metadata(&[i as f64, 0.5, -1.0], metadata)
+        .unwrap();
+    }
+    db.remove(4);
+    let file = tempfile::tempfile().unwrap();
+    let config = ExportConfig {
+      batch_size: 4,
+      ..ExportConfig::default()
+    };
+    export_parquet(&db, file.try_clone().unwrap(), &config).unwrap();
+
+    let config = ImportConfig {
+      id_column: Some("id".to_string()),
+      metadata_columns: Some(vec!["odd".to_string()]),
+      ..ImportConfig::default()
+    };
+    let mut copy = VectorDatabase::new(3).unwrap();
+    let ids =
+      import_parquet(&mut copy, file.try_clone().unwrap(), &config).unwrap();
+    assert_eq!(ids, [0, 1, 2, 3, 5, 6, 7, 8, 9]);
+    assert_eq!(copy.get(7), db.get(7));
+    let mut metadata = Metadata::new();
+    metadata.insert("odd".to_string(), Value::from(true));
+    assert_eq!(copy.metadata(7), Some(&metadata));
+
+    let config = ImportConfig {
+      vector_column: "missing".to_string(),
+      ..ImportConfig::default()
+    };
+    assert!(matches!(
+      import_parquet(&mut copy, file, &config),
+      Err(Error::InvalidValue(_))
+    ));
+  }
+}
@@ -0,0 +1,400 @@
+//! The `.fvecs`, `.ivecs` and `.bvecs` formats, in which the SIFT1M, GIST1M
+//! and Deep1B benchmark datasets and their ground truth are distributed.
+//!
+//! Such a file is a plain sequence of vectors, each stored as its number of
+//! components as a little-endian `i32` followed by the components: `f32`s in
+//! `.fvecs` files, `i32`s in `.ivecs` files and bytes in `.bvecs` files. All
+//! the vectors of a file have the same number of components.
+//!
+//! The benchmark datasets identify vectors by their position in the base
+//! file. Loading a base file into an empty [`VectorDatabase`] with
+//! [`load_fvecs`] or [`load_bvecs`] assigns IDs in the same order, so the
+//! neighbor lists read by [`read_ground_truth`] can be compared with search
+//! results directly, for instance with [`recall`].
+//!
+//! # Examples
+//!
+//! ```
+//! use rustyvectors::formats::vecs;
+//! use rustyvectors::vector_database::VectorDatabase;
+//! use ndarray::arr2;
+//!
+//! let mut file = Vec::new();
+//! vecs::write_fvecs(&mut file, arr2(&[[0.0, 0.0], [1.0, 1.0], [5.0, 5.0]]).view())?;
+//!
+//! let mut db = VectorDatabase::new(2)?;
+//! let ids = vecs::load_fvecs(&mut db, file.as_slice())?;
+//! assert_eq!(ids, [0, 1, 2]);
+//! let found: Vec<_> = db.k_nearest(&[0.9, 0.9], 2)?.iter().map(|&(id, _)| id).collect();
+//! assert_eq!(vecs::recall(&[1, 0], &found, 2), 1.0);
+//! # Ok::<(), rustyvectors::Error>(())
+//! ```
+
+use crate::error::{Error, Result};
+use crate::vector_database::{VectorDatabase, VectorId};
+use ndarray::{Array2, ArrayView2};
+use std::collections::HashSet;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// A component type of one of the formats.
+trait Component: Copy + Default {
+  const SIZE: usize;
+
+  fn from_le_bytes(bytes: &[u8]) -> Self;
+
+  fn write_le_bytes(self, out: &mut Vec<u8>);
+}
+
+impl Component for f32 {
+  const SIZE: usize = 4;
+
+  fn from_le_bytes(bytes: &[u8]) -> Self {
+    f32::from_le_bytes(bytes.try_into().unwrap())
+  }
+
+  fn write_le_bytes(self, out: &mut Vec<u8>) {
+    out.extend_from_slice(&self.to_le_bytes());
+  }
+}
+
+impl Component for i32 {
+  const SIZE: usize = 4;
+
+  fn from_le_bytes(bytes: &[u8]) -> Self {
+    i32::from_le_bytes(bytes.try_into().unwrap())
+  }
+
+  fn write_le_bytes(self, out: &mut Vec<u8>) {
+    out.extend_from_slice(&self.to_le_bytes());
+  }
+}
+
+impl Component for u8 {
+  const SIZE: usize = 1;
+
+  fn from_le_bytes(bytes: &[u8]) -> Self {
+    bytes[0]
+  }
+
+  fn write_le_bytes(self, out: &mut Vec<u8>) {
+    out.push(self);
+  }
+}
+
+/// Reads the vectors of an `.fvecs` file, one per row.
+///
+/// Returns [`Error::Corrupted`] if the file is truncated or its vectors do
+/// not all have the same number of components, and [`Error::Io`] if reading
+/// fails.
+pub fn read_fvecs(reader: impl Read) -> Result<Array2<f32>> {
+  read_vecs(reader)
+}
+
+/// Reads the vectors of an `.ivecs` file, one per row.
+///
+/// See [`read_fvecs`] for the possible errors.
+pub fn read_ivecs(reader: impl Read) -> Result<Array2<i32>> {
+  read_vecs(reader)
+}
+
+/// Reads the vectors of a `.bvecs` file, one per row.
+///
+/// See [`read_fvecs`] for the possible errors.
+pub fn read_bvecs(reader: impl Read) -> Result<Array2<u8>> {
+  read_vecs(reader)
+}
+
+/// Writes the rows of `vectors` as an `.fvecs` file.
+pub fn write_fvecs(writer: impl Write, vectors: ArrayView2<f32>) -> Result<()> {
+  write_vecs(writer, vectors)
+}
+
+/// Writes the rows of `vectors` as an `.ivecs` file.
+pub fn write_ivecs(writer: impl Write, vectors: ArrayView2<i32>) -> Result<()> {
+  write_vecs(writer, vectors)
+}
+
+/// Writes the rows of `vectors` as a `.bvecs` file.
+pub fn write_bvecs(writer: impl Write, vectors: ArrayView2<u8>) -> Result<()> {
+  write_vecs(writer, vectors)
+}
+
+/// Adds the vectors of an `.fvecs` file to `db`, in file order, and returns
+/// their IDs.
+///
+/// The file is read one vector at a time, so it is never held in memory as a
+/// whole. Besides the errors of [`read_fvecs`], returns the errors of
+/// [`VectorDatabase::add`] if a vector cannot be added; the vectors read
+/// before it stay in the database.
+pub fn load_fvecs(
+  db: &mut VectorDatabase,
+  reader: impl Read,
+) -> Result<Vec<VectorId>> {
+  load_vecs::<f32>(db, reader, |x| x.into())
+}
+
+/// Adds the vectors of a `.bvecs` file to `db`, in file order, and returns
+/// their IDs.
+///
+/// See [`load_fvecs`].
+pub fn load_bvecs(
+  db: &mut VectorDatabase,
+  reader: impl Read,
+) -> Result<Vec<VectorId>> {
+  load_vecs::<u8>(db, reader, |x| x.into())
+}
+
+/// Writes the vectors of `db` as an `.fvecs` file, by ascending ID, and
+/// returns their IDs in file order.
+///
+/// Components are rounded to the nearest `f32`.
+pub fn export_fvecs(
+  db: &VectorDatabase,
+  writer: impl Write,
+) -> Result<Vec<VectorId>> {
+  let mut ids: Vec<_> = db.ids().collect();
+  ids.sort_unstable();
+  let mut writer = BufWriter::new(writer);
+  let mut bytes = Vec::new();
+  for vector in ids.iter().filter_map(|&id| db.get(id)) {
+    bytes.clear();
+    write_row(&mut bytes, vector.iter().map(|&x| x as f32));
+    writer.write_all(&bytes)?;
+  }
+  writer.flush()?;
+  Ok(ids)
+}
+
+/// Reads the neighbor lists of an `.ivecs` ground truth file: for each query,
+/// the positions in the base file of its nearest neighbors, closest first.
+///
+/// Returns [`Error::Corrupted`] if a position is negative, besides the
+/// errors of [`read_fvecs`].
+pub fn read_ground_truth(reader: impl Read) -> Result<Vec<Vec<VectorId>>> {
+  read_ivecs(reader)?
+    .outer_iter()
+    .map(|row| {
+      row
+        .iter()
+        .map(|&position| {
+          VectorId::try_from(position).map_err(|_| {
+            Error::Corrupted(format!("negative neighbor position {position}"))
+          })
+        })
+        .collect()
+    })
+    .collect()
+}
+
+/// Returns the fraction of the first `k` entries of `ground_truth` found
+/// among the first `k` entries of `found`, the usual recall@k measure.
+///
+/// Returns 1 if `k` or `ground_truth` is empty.
+pub fn recall(ground_truth: &[VectorId], found: &[VectorId], k: usize) -> f64 {
+  let expected: HashSet<_> = ground_truth.iter().take(k).collect();
+  if expected.is_empty() {
+    return 1.0;
+  }
+  let hits = found.iter().take(k).filter(|id| expected.contains(id));
+  hits.count() as f64 / expected.len() as f64
+}
+
+/// Reads vectors one at a time, calling `f` with the components of each.
+fn for_each_vector<T: Component>(
+  reader: impl Read,
+  mut f: impl FnMut(&[T]) -> Result<()>,
+) -> Result<()> {
+  let mut reader = BufReader::new(reader);
+  let mut dimension = None;
+  let mut bytes = Vec::new();
+  let mut row = Vec::new();
+  let mut header = [0; 4];
+  loop {
+    // A file may only end between two vectors.
+    match reader.read(&mut header[..1]) {
+      Ok(0) => return Ok(()),
+      Ok(_) => read_exact(&mut reader, &mut header[1..])?,
+      Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+      Err(error) => return Err(error.into()),
+    }
+    let found = i32::from_le_bytes(header);
+    let Ok(found) = usize::try_from(found) else {
+      return Err(Error::Corrupted(format!("negative dimension {found}")));
+    };
+    let expected = *dimension.get_or_insert(found);
+    if found != expected {
+      return Err(Error::Corrupted(format!(
+        "inconsistent dimensions: expected {expected} components, found \
+         {found}"
+      )));
+    }
+    // Grow the buffer as bytes arrive, so that a damaged dimension cannot
+    // allocate more memory than the file holds.
+    let len = found * T::SIZE;
+    bytes.clear();
+    (&mut reader).take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+      return Err(Error::Corrupted("file is truncated".to_string()));
+    }
+    row.clear();
+    row.extend(bytes.chunks_exact(T::SIZE).map(T::from_le_bytes));
+    f(&row)?;
+  }
+}
+
+fn read_vecs<T: Component>(reader: impl Read) -> Result<Array2<T>> {
+  let mut dimension = 0;
+  let mut components = Vec::new();
+  for_each_vector(reader, |row: &[T]| {
+    dimension = row.len();
+    components.extend_from_slice(row);
+    Ok(())
+  })?;
+  let rows = components.len().checked_div(dimension).unwrap_or(0);
+  Ok(Array2::from_shape_vec((rows, dimension), components).unwrap())
+}
+
+fn load_vecs<T: Component>(
+  db: &mut VectorDatabase,
+  reader: impl Read,
+  to_f64: impl Fn(T) -> f64,
+) -> Result<Vec<VectorId>> {
+  let mut ids = Vec::new();
+  let mut vector = Vec::new();
+  for_each_vector(reader, |row: &[T]| {
+    vector.clear();
+    vector.extend(row.iter().map(|&x| to_f64(x)));
+    ids.push(db.add(&vector)?);
+    Ok(())
+  })?;
+  Ok(ids)
+}
+
+fn write_vecs<T: Component>(
+  writer: impl Write,
+  vectors: ArrayView2<T>,
+) -> Result<()> {
+  let mut writer = BufWriter::new(writer);
+  let mut bytes = Vec::new();
+  for row in vectors.outer_iter() {
+    bytes.clear();
+    write_row(&mut bytes, row.iter().copied());
+    writer.write_all(&bytes)?;
+  }
+  writer.flush()?;
+  Ok(())
+}
+
+fn write_row<T: Component>(
+  out: &mut Vec<u8>,
+  row: impl ExactSizeIterator<Item = T>,
+) {
+  let dimension = i32::try_from(row.len()).expect("vector too long");
+  out.extend_from_slice(&dimension.to_le_bytes());
+  for component in row {
+    component.write_le_bytes(out);
+  }
+}
+
+/// Fills `buffer`, reporting a premature end of file as corruption.
+fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> Result<()> {
+  reader.read_exact(buffer).map_err(|error| {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+      Error::Corrupted("file is truncated".to_string())
+    } else {
+      Error::from(error)
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ndarray::arr2;
+
+  #[test]
+  fn test_round_trips() {
+    let floats = arr2(&[[1.5_f32, -2.0, 0.0], [4.0, 5.0, 6.25]]);
+    let mut file = Vec::new();
+    write_fvecs(&mut file, floats.view()).unwrap();
+    assert_eq!(file.len(), 2 * (4 + 3 * 4));
+    assert_eq!(&file[..4], &3_i32.to_le_bytes());
+    assert_eq!(read_fvecs(file.as_slice()).unwrap(), floats);
+
+    let integers = arr2(&[[7, -1], [0, 100_000]]);
+    let mut file = Vec::new();
+    write_ivecs(&mut file, integers.view()).unwrap();
+    assert_eq!(read_ivecs(file.as_slice()).unwrap(), integers);
+
+    let bytes = arr2(&[[0_u8, 255, 3, 4]]);
+    let mut file = Vec::new();
+    write_bvecs(&mut file, bytes.view()).unwrap();
+    assert_eq!(file.len(), 8);
+    assert_eq!(read_bvecs(file.as_slice()).unwrap(), bytes);
+
+    assert_eq!(read_fvecs(&[][..]).unwrap().dim(), (0, 0));
+  }
+
+  #[test]
+  fn test_invalid_files_are_rejected() {
+    let mut file = Vec::new();
+    write_ivecs(&mut file, arr2(&[[1, 2]]).view()).unwrap();
+    write_ivecs(&mut file, arr2(&[[1, 2, 3]]).view()).unwrap();
+    assert!(matches!(
+      read_ivecs(file.as_slice()),
+      Err(Error::Corrupted(_))
+    ));
+    assert_eq!(
+      read_ivecs(&file[..10]),
+      Err(Error::Corrupted("file is truncated".to_string()))
+    );
+    assert!(matches!(
+      read_ivecs(&(-1_i32).to_le_bytes()[..]),
+      Err(Error::Corrupted(_))
+    ));
+    assert_eq!(
+      read_fvecs(&i32::MAX.to_le_bytes()[..]),
+      Err(Error::Corrupted("file is truncated".to_string()))
+    );
+  }
+
+  #[test]
+  fn test_load_and_export_database() {
+    let mut file = Vec::new();
+    let vectors = arr2(&[[0_u8, 0], [10, 10], [3, 4]]);
+    write_bvecs(&mut file, vectors.view()).unwrap();
+    let mut db = VectorDatabase::new(2).unwrap();
+    assert_eq!(load_bvecs(&mut db, file.as_slice()).unwrap(), [0, 1, 2]);
+    assert_eq!(db.nearest(&[3.0, 3.0]).unwrap(), Some(2));
+
+    db.remove(1);
+    let mut exported = Vec::new();
+    assert_eq!(export_fvecs(&db, &mut exported).unwrap(), [0, 2]);
+    let mut copy = VectorDatabase::new(2).unwrap();
+    load_fvecs(&mut copy, exported.as_slice()).unwrap();
+    assert_eq!(copy.get(1), db.get(2));
+
+    let mut wrong_dimension = VectorDatabase::new(3).unwrap();
+    assert!(matches!(
+      load_fvecs(&mut wrong_dimension, exported.as_slice()),
+      Err(Error::DimensionMismatch { .. })
+    ));
+  }
+
+  #[test]
+  fn test_ground_truth_and_recall() {
+    let mut file = Vec::new();
+    write_ivecs(&mut file, arr2(&[[3, 1, 2], [0, 4, 5]]).view()).unwrap();
+    let ground_truth = read_ground_truth(file.as_slice()).unwrap();
+    assert_eq!(ground_truth, [vec![3, 1, 2], vec![0, 4, 5]]);
+
+    assert_eq!(recall(&ground_truth[0], &[1, 3, 9], 2), 1.0);
+    assert_eq!(recall(&ground_truth[0], &[1, 9, 3], 2), 0.5);
+    assert_eq!(recall(&ground_truth[1], &[], 3), 0.0);
+    assert_eq!(recall(&[], &[1], 3), 1.0);
+
+    let mut negative = Vec::new();
+    write_ivecs(&mut negative, arr2(&[[1, -1]]).view()).unwrap();
+    assert!(read_ground_truth(negative.as_slice()).is_err());
+  }
+}
@@ -0,0 +1,767 @@
+#[cfg(feature = "rayon")]
+use super::check_batch;
+use super::{check_dimension, VectorIndex};
+use crate::distance::{Metric, MetricKind};
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::neighbors::{cmp_keys, TopK};
+use crate::random::Rng;
+use crate::vector_database::{VectorDatabase, VectorId};
+#[cfg(feature = "rayon")]
+use ndarray::ArrayView2;
+use ndarray::{Array1, Array2, ArrayView1};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// The largest number of vectors whose candidate neighbors are searched for
+/// in parallel by [`Hnsw::insert_batch`](VectorIndex::insert_batch).
+#[cfg(feature = "rayon")]
+const MAX_CHUNK: usize = 256;
+
+/// The parameters of an [`Hnsw`] index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HnswConfig {
+  /// The number of neighbors each vector is linked to on every layer above
+  /// the bottom one, which allows twice as many. Higher values improve recall
+  /// at the cost of memory and insertion time.
+  pub m: usize,
+  /// The size of the candidate list kept while inserting a vector.
+  pub ef_construction: usize,
+  /// The size of the candidate list kept while searching. Searches for more
+  /// than `ef_search` neighbors use `k` instead.
+  pub ef_search: usize,
+  /// The seed used to draw the layer of each inserted vector.
+  pub seed: u64,
+}
+
+impl Default for HnswConfig {
+  fn default() -> Self {
+    Self {
+      m: 16,
+      ef_construction: 200,
+      ef_search: 64,
+      seed: 42,
+    }
+  }
+}
+
+/// A Hierarchical Navigable Small World graph index.
+///
+/// Vectors are linked to their approximate nearest neighbors in a stack of
+/// proximity graphs, each layer holding an exponentially smaller subset of the
+/// vectors. Searches descend greedily from the sparsest layer and explore the
+/// bottom one with a candidate list of `ef_search` entries.
+///
+/// Removed vectors are only marked as deleted: they keep routing searches
+/// through the graph but are never returned, and searches widen their
+/// candidate list until they find enough live vectors. Rebuilding the index
+/// with [`Hnsw::build`] reclaims them. Inserting a vector under an ID that is
+/// already indexed moves its node in place instead, so updates leave no
+/// deleted nodes behind.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::index::{Hnsw, HnswConfig, VectorIndex};
+/// use rustyvectors::vector_database::VectorDatabase;
+///
+/// let mut db = VectorDatabase::new(2)?;
+/// for i in 0..100 {
+///   db.add(&[i as f64, (i % 10) as f64])?;
+/// }
+/// let hnsw = Hnsw::build(&db, HnswConfig::default())?;
+/// db.set_index(hnsw)?;
+/// let neighbors = db.k_nearest_approx(&[42.0, 2.0], 5)?;
+/// assert_eq!(neighbors[0], (42, 0.0));
+/// # Ok::<(), rustyvectors::Error>(())
+/// ```
+pub struct Hnsw {
+  config: HnswConfig,
+  dimension: usize,
+  metric: MetricKind,
+  nodes: Vec<Node>,
+  /// The live node holding each indexed ID.
+  nodes_by_id: HashMap<VectorId, usize>,
+  entry_point: Option<usize>,
+  /// Scales the exponential distribution layers are drawn from.
+  level_multiplier: f64,
+  rng: Rng,
+}
+
+struct Node {
+  id: VectorId,
+  vector: Array1<f64>,
+  /// The neighbors of the node on each layer it belongs to, bottom first.
+  neighbors: Vec<Vec<usize>>,
+  deleted: bool,
+}
+
+/// A node scored against some vector, ordered by closeness.
+#[derive(Clone, Copy)]
+struct Scored {
+  key: f64,
+  node: usize,
+}
+
+impl Ord for Scored {
+  fn cmp(&self, other: &Self) -> Ordering {
+    cmp_keys(self.key, other.key).then_with(|| self.node.cmp(&other.node))
+  }
+}
+
+impl PartialOrd for Scored {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for Scored {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Scored {}
+
+impl Hnsw {
+  /// Creates an empty index for vectors of `dimension` components compared
+  /// with `metric`.
+  ///
+  /// Returns [`Error::InvalidValue`] if `config.m` is less than 2 or if
+  /// either candidate list size is zero.
+  pub fn new(
+    dimension: usize,
+    metric: MetricKind,
+    config: HnswConfig,
+  ) -> Result<Self> {
+    if config.m < 2 {
+      return Err(Error::InvalidValue(format!(
+        "HNSW m must be at least 2, got {}",
+        config.m
+      )));
+    }
+    if config.ef_construction == 0 || config.ef_search == 0 {
+      return Err(Error::InvalidValue(
+        "HNSW candidate list sizes must be at least 1".to_string(),
+      ));
+    }
+
+    Ok(Self {
+      config,
+      dimension,
+      metric,
+      nodes: Vec::new(),
+      nodes_by_id: HashMap::new(),
+      entry_point: None,
+      level_multiplier: 1.0 / (config.m as f64).ln(),
+      rng: Rng::new(config.seed),
+    })
+  }
+
+  /// Builds an index over all the vectors of `db`, using its dimension and
+  /// metric.
+  pub fn build<T: Element>(
+    db: &VectorDatabase<T>,
+    config: HnswConfig,
+  ) -> Result<Self> {
+    let mut hnsw = Self::new(db.dimension(), db.metric(), config)?;
+    let mut ids: Vec<_> = db.ids().collect();
+    ids.sort_unstable();
+    let mut vectors = Array2::zeros((ids.len(), db.dimension()));
+    for (&id, mut row) in ids.iter().zip(vectors.outer_iter_mut()) {
+      let vector = db.get(id).expect("the id was just listed");
+      row.zip_mut_with(&vector, |x, &y| *x = y.to_f64());
+    }
+    hnsw.insert_batch(&ids, vectors.view())?;
+    Ok(hnsw)
+  }
+
+  /// Returns the parameters of the index.
+  pub fn config(&self) -> HnswConfig {
+    self.config
+  }
+
+  /// Changes the size of the candidate list kept while searching, trading
+  /// speed for recall. Values below 1 are treated as 1.
+  pub fn set_ef_search(&mut self, ef_search: usize) {
+    self.config.ef_search = ef_search.max(1);
+  }
+
+  /// Returns the number of removed vectors still present in the graph.
+  pub fn deleted_count(&self) -> usize {
+    self.nodes.len() - self.nodes_by_id.len()
+  }
+
+  fn key(&self, a: &ArrayView1<f64>, b: &ArrayView1<f64>) -> f64 {
+    self.metric.order().key(self.metric.score(a, b))
+  }
+
+  fn key_to_node(&self, query: &ArrayView1<f64>, node: usize) -> Scored {
+    Scored {
+      key: self.key(query, &self.nodes[node].vector.view()),
+      node,
+    }
+  }
+
+  fn max_connections(&self, level: usize) -> usize {
+    if level == 0 {
+      2 * self.config.m
+    } else {
+      self.config.m
+    }
+  }
+
+  fn random_level(&mut self) -> usize {
+    let uniform = 1.0 - self.rng.next_f64();
+    (-uniform.ln() * self.level_multiplier) as usize
+  }
+
+  fn top_level(&self, node: usize) -> usize {
+    self.nodes[node].neighbors.len() - 1
+  }
+
+  /// Greedily descends from the entry point to `level`, returning the closest
+  /// node found on the layer above it.
+  fn descend(&self, query: &ArrayView1<f64>, level: usize) -> Vec<Scored> {
+    let Some(entry) = self.entry_point else {
+      return Vec::new();
+    };
+    let mut entry_points = vec![self.key_to_node(query, entry)];
+    for layer in (level + 1..=self.top_level(entry)).rev() {
+      entry_points = self.search_layer(query, &entry_points, 1, layer);
+    }
+    entry_points
+  }
+
+  /// Explores one layer from `entry_points`, returning up to `ef` of the
+  /// closest nodes found, closest first.
+  fn search_layer(
+    &self,
+    query: &ArrayView1<f64>,
+    entry_points: &[Scored],
+    ef: usize,
+    level: usize,
+  ) -> Vec<Scored> {
+    let mut visited: HashSet<usize> =
+      entry_points.iter().map(|s| s.node).collect();
+    let mut candidates: BinaryHeap<Reverse<Scored>> =
+      entry_points.iter().copied().map(Reverse).collect();
+    let mut results: BinaryHeap<Scored> =
+      entry_points.iter().copied().collect();
+    while results.len() > ef {
+      results.pop();
+    }
+
+    while let Some(Reverse(candidate)) = candidates.pop() {
+      if let Some(worst) = results.peek() {
+        if results.len() >= ef && candidate > *worst {
+          break;
+        }
+      }
+      for &neighbor in &self.nodes[candidate.node].neighbors[level] {
+        if !visited.insert(neighbor) {
+          continue;
+        }
+        let scored = self.key_to_node(query, neighbor);
+        let admit = results.len() < ef
+          || results.peek().is_some_and(|worst| scored < *worst);
+        if admit {
+          candidates.push(Reverse(scored));
+          results.push(scored);
+          if results.len() > ef {
+            results.pop();
+          }
+        }
+      }
+    }
+
+    results.into_sorted_vec()
+  }
+
+  /// Picks up to `m` neighbors among `candidates` (sorted closest first),
+  /// preferring ones that are closer to the base vector than to the
+  /// neighbors already picked so that links spread in all directions.
+  fn select_neighbors(&self, candidates: &[Scored], m: usize) -> Vec<usize> {
+    let mut selected: Vec<Scored> = Vec::with_capacity(m);
+    let mut pruned = Vec::new();
+    for &candidate in candidates {
+      if selected.len() >= m {
+        break;
+      }
+      let vector = self.nodes[candidate.node].vector.view();
+      let diverse = selected.iter().all(|chosen| {
+        let between = self.key(&vector, &self.nodes[chosen.node].vector.view());
+        cmp_keys(candidate.key, between) == Ordering::Less
+      });
+      if diverse {
+        selected.push(candidate);
+      } else {
+        pruned.push(candidate);
+      }
+    }
+    // Fill up with the closest pruned candidates to keep the graph well
+    // connected.
+    let missing = m.saturating_sub(selected.len());
+    selected.extend(pruned.into_iter().take(missing));
+    selected.into_iter().map(|s| s.node).collect()
+  }
+
+  /// Searches the graph for candidate neighbors of a vector inserted up to
+  /// `level`, returning them closest first for every layer, bottom first.
+  fn search_candidates(
+    &self,
+    vector: &ArrayView1<f64>,
+    level: usize,
+  ) -> Vec<Vec<Scored>> {
+    let mut candidates = vec![Vec::new(); level + 1];
+    if let Some(entry) = self.entry_point {
+      let mut entry_points = self.descend(vector, level);
+      for layer in (0..=level.min(self.top_level(entry))).rev() {
+        let found = self.search_layer(
+          vector,
+          &entry_points,
+          self.config.ef_construction,
+          layer,
+        );
+        candidates[layer].clone_from(&found);
+        entry_points = found;
+      }
+    }
+    candidates
+  }
+
+  /// Adds an unlinked node holding `vector` on the layers up to `level`.
+  fn push_node(
+    &mut self,
+    id: VectorId,
+    vector: ArrayView1<f64>,
+    level: usize,
+  ) -> usize {
+    let node = self.nodes.len();
+    self.nodes.push(Node {
+      id,
+      vector: vector.to_owned(),
+      neighbors: vec![Vec::new(); level + 1],
+      deleted: false,
+    });
+    self.nodes_by_id.insert(id, node);
+    node
+  }
+
+  /// Links `node` to neighbors picked among `candidates`, given closest first
+  /// for every layer of the node, and makes it the entry point if it reaches
+  /// higher than the current one.
+  fn link(&mut self, node: usize, candidates: Vec<Vec<Scored>>) {
+    for (layer, candidates) in candidates.iter().enumerate() {
+      let neighbors = self.select_neighbors(candidates, self.config.m);
+      for &neighbor in &neighbors {
+        if !self.nodes[neighbor].neighbors[layer].contains(&node) {
+          self.nodes[neighbor].neighbors[layer].push(node);
+          self.shrink_connections(neighbor, layer);
+        }
+      }
+      self.nodes[node].neighbors[layer] = neighbors;
+    }
+
+    let raises_top = match self.entry_point {
+      Some(entry) => self.top_level(node) > self.top_level(entry),
+      None => true,
+    };
+    if raises_top {
+      self.entry_point = Some(node);
+    }
+  }
+
+  /// Moves `node` to `vector`: its former neighbors pick new links among each
+  /// other, and the node is then linked again as if it were inserted anew.
+  fn relink(&mut self, node: usize, vector: ArrayView1<f64>) {
+    self.nodes[node].vector.assign(&vector);
+    // Search while the node still links to its former neighbors, which also
+    // stay candidates, so that the graph cannot come apart around it.
+    let mut candidates = self.search_candidates(&vector, self.top_level(node));
+    let former = std::mem::take(&mut self.nodes[node].neighbors);
+    for (layer, candidates) in candidates.iter_mut().enumerate() {
+      candidates.retain(|scored| scored.node != node);
+      candidates
+        .extend(former[layer].iter().map(|&n| self.key_to_node(&vector, n)));
+      candidates.sort_unstable();
+      candidates.dedup();
+    }
+
+    for (layer, former) in former.iter().enumerate() {
+      for &neighbor in former {
+        let base = self.nodes[neighbor].vector.view();
+        let mut candidates: Vec<Scored> = self.nodes[neighbor].neighbors[layer]
+          .iter()
+          .chain(former)
+          .filter(|&&other| other != neighbor && other != node)
+          .map(|&other| self.key_to_node(&base, other))
+          .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        let selected =
+          self.select_neighbors(&candidates, self.max_connections(layer));
+        self.nodes[neighbor].neighbors[layer] = selected;
+      }
+    }
+    self.nodes[node].neighbors = vec![Vec::new(); former.len()];
+    self.link(node, candidates);
+  }
+
+  /// Trims the links of `node` on `level` back to the allowed maximum.
+  fn shrink_connections(&mut self, node: usize, level: usize) {
+    let max = self.max_connections(level);
+    if self.nodes[node].neighbors[level].len() <= max {
+      return;
+    }
+    let vector = self.nodes[node].vector.view();
+    let mut candidates: Vec<Scored> = self.nodes[node].neighbors[level]
+      .iter()
+      .map(|&neighbor| self.key_to_node(&vector, neighbor))
+      .collect();
+    candidates.sort_unstable();
+    let selected = self.select_neighbors(&candidates, max);
+    self.nodes[node].neighbors[level] = selected;
+  }
+}
+
+impl VectorIndex for Hnsw {
+  fn dimension(&self) -> usize {
+    self.dimension
+  }
+
+  fn metric(&self) -> MetricKind {
+    self.metric
+  }
+
+  fn len(&self) -> usize {
+    self.nodes_by_id.len()
+  }
+
+  fn contains(&self, id: VectorId) -> bool {
+    self.nodes_by_id.contains_key(&id)
+  }
+
+  fn insert(&mut self, id: VectorId, vector: ArrayView1<f64>) -> Result<()> {
+    check_dimension(&vector, self.dimension)?;
+    if let Some(&node) = self.nodes_by_id.get(&id) {
+      self.relink(node, vector);
+      return Ok(());
+    }
+    let level = self.random_level();
+    let candidates = self.search_candidates(&vector, level);
+    let node = self.push_node(id, vector, level);
+    self.link(node, candidates);
+    Ok(())
+  }
+
+  /// Inserts the vectors a chunk at a time: the graph is searched for the
+  /// candidate neighbors of every vector of a chunk in parallel, and the
+  /// vectors are then linked one after the other. The vectors of a chunk do
+  /// not find each other in the graph, so they are compared with each other
+  /// exactly instead. Vectors whose ID is already indexed are moved in place
+  /// first, one at a time.
+  #[cfg(feature = "rayon")]
+  fn insert_batch(
+    &mut self,
+    ids: &[VectorId],
+    vectors: ArrayView2<f64>,
+  ) -> Result<()> {
+    use rayon::prelude::*;
+
+    check_batch(ids, &vectors, self.dimension)?;
+    let mut rows = Vec::with_capacity(ids.len());
+    for (row, &id) in ids.iter().enumerate() {
+      match self.nodes_by_id.get(&id) {
+        Some(&node) => self.relink(node, vectors.row(row)),
+        None => rows.push(row),
+      }
+    }
+    let levels: Vec<usize> = rows.iter().map(|_| self.random_level()).collect();
+    let mut start = 0;
+    while start < rows.len() {
+      // Chunks grow with the graph, so that it is never mostly made of
+      // vectors that were linked without seeing each other.
+      let size = self.nodes.len().clamp(1, MAX_CHUNK).min(rows.len() - start);
+      let chunk = start..start + size;
+      let found: Vec<_> = chunk
+        .clone()
+        .into_par_iter()
+        .map(|i| self.search_candidates(&vectors.row(rows[i]), levels[i]))
+        .collect();
+      let first = self.nodes.len();
+      for i in chunk.clone() {
+        self.push_node(ids[rows[i]], vectors.row(rows[i]), levels[i]);
+      }
+      let this = &*self;
+      let candidates: Vec<_> = chunk
+        .into_par_iter()
+        .zip(found)
+        .map(|(i, mut candidates)| {
+          let vector = vectors.row(rows[i]);
+          for (offset, &level) in levels[start..i].iter().enumerate() {
+            let other = vectors.row(rows[start + offset]);
+            let scored = Scored {
+              key: this.key(&vector, &other),
+              node: first + offset,
+            };
+            for layer in candidates.iter_mut().take(level + 1) {
+              layer.push(scored);
+            }
+          }
+          for layer in &mut candidates {
+            layer.sort_unstable();
+          }
+          candidates
+        })
+        .collect();
+      for (offset, candidates) in candidates.into_iter().enumerate() {
+        self.link(first + offset, candidates);
+      }
+      start += size;
+    }
+    Ok(())
+  }
+
+  fn remove(&mut self, id: VectorId) -> bool {
+    match self.nodes_by_id.remove(&id) {
+      Some(node) => {
+        self.nodes[node].deleted = true;
+        true
+      }
+      None => false,
+    }
+  }
+
+  fn search(
+    &self,
+    query: ArrayView1<f64>,
+    k: usize,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    check_dimension(&query, self.dimension)?;
+    if k == 0 {
+      return Ok(Vec::new());
+    }
+
+    let entry_points = self.descend(&query, 0);
+    let mut ef = self.config.ef_search.max(k);
+    let found = loop {
+      let found = self.search_layer(&query, &entry_points, ef, 0);
+      let live = found
+        .iter()
+        .filter(|scored| !self.nodes[scored.node].deleted)
+        .count();
+      // Deleted nodes take up room in the candidate list, so widen it until
+      // it holds enough live ones or the whole reachable graph.
+      if live >= k || found.len() < ef || ef >= self.nodes.len() {
+        break found;
+      }
+      ef = ef.saturating_mul(2).min(self.nodes.len());
+    };
+    let order = self.metric.order();
+    let mut top_k = TopK::new(k, order);
+    for scored in found {
+      let node = &self.nodes[scored.node];
+      if !node.deleted {
+        top_k.push(node.id, order.key(scored.key));
+      }
+    }
+    Ok(top_k.into_sorted_vec())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn random_db(
+    count: usize,
+    dimension: usize,
+    metric: MetricKind,
+  ) -> VectorDatabase {
+    let mut rng = Rng::new(7);
+    let mut db = VectorDatabase::with_metric(dimension, metric).unwrap();
+    for _ in 0..count {
+      let vector: Vec<f64> = (0..dimension).map(|_| rng.next_f64()).collect();
+      db.add(&vector).unwrap();
+    }
+    db
+  }
+
+  fn recall(db: &VectorDatabase, hnsw: &Hnsw, k: usize) -> f64 {
+    let mut rng = Rng::new(11);
+    let mut found = 0;
+    for _ in 0..50 {
+      let query: Vec<f64> =
+        (0..db.dimension()).map(|_| rng.next_f64()).collect();
+      let exact: HashSet<_> = db
+        .k_nearest(&query, k)
+        .unwrap()
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+      found += hnsw
+        .search(ArrayView1::from(&query), k)
+        .unwrap()
+        .iter()
+        .filter(|(id, _)| exact.contains(id))
+        .count();
+    }
+    found as f64 / (50 * k) as f64
+  }
+
+  #[test]
+  fn test_recall() {
+    for metric in [
+      MetricKind::Euclidean,
+      MetricKind::Cosine,
+      MetricKind::Manhattan,
+    ] {
+      let db = random_db(500, 8, metric);
+      let hnsw = Hnsw::build(&db, HnswConfig::default()).unwrap();
+      assert_eq!(hnsw.len(), 500);
+      assert!(recall(&db, &hnsw, 10) >= 0.95, "{metric:?}");
+    }
+  }
+
+  #[test]
+  fn test_scores_match_exact_search() {
+    let db = random_db(200, 4, MetricKind::DotProduct);
+    let hnsw = Hnsw::build(&db, HnswConfig::default()).unwrap();
+    let query = [0.5, 0.1, 0.9, 0.3];
+    let exact = db.k_nearest(&query, 3).unwrap();
+    let approx = hnsw.search(ArrayView1::from(&query), 3).unwrap();
+    assert_eq!(approx, exact);
+  }
+
+  #[test]
+  fn test_remove_and_reinsert() {
+    let mut db = random_db(300, 4, MetricKind::Euclidean);
+    let mut hnsw = Hnsw::build(&db, HnswConfig::default()).unwrap();
+    let target = db.get(10).unwrap().to_owned();
+
+    assert!(hnsw.remove(10));
+    assert!(!hnsw.remove(10));
+    assert!(!hnsw.contains(10));
+    assert_eq!(hnsw.deleted_count(), 1);
+    let neighbors = hnsw.search(target.view(), 5).unwrap();
+    assert!(neighbors.iter().all(|&(id, _)| id != 10));
+
+    hnsw.insert(10, target.view()).unwrap();
+    assert_eq!(hnsw.search(target.view(), 1).unwrap(), vec![(10, 0.0)]);
+
+    db.remove(10);
+    let id = db.add(&[2.0, 2.0, 2.0, 2.0]).unwrap();
+    hnsw
+      .insert(id, ArrayView1::from(&[2.0, 2.0, 2.0, 2.0]))
+      .unwrap();
+    assert_eq!(
+      hnsw.search(ArrayView1::from(&[2.0; 4]), 1).unwrap()[0].0,
+      id
+    );
+  }
+
+  #[test]
+  fn test_repeated_updates() {
+    let mut db = random_db(50, 2, MetricKind::Euclidean);
+    let config = HnswConfig {
+      ef_search: 16,
+      ..HnswConfig::default()
+    };
+    let mut hnsw = Hnsw::build(&db, config).unwrap();
+    let mut rng = Rng::new(3);
+    for _ in 0..20 {
+      for id in 0..50 {
+        let vector = [rng.next_f64() * 20.0, rng.next_f64()];
+        db.update(id, &vector).unwrap();
+        hnsw.insert(id, ArrayView1::from(&vector)).unwrap();
+      }
+    }
+    assert_eq!(hnsw.len(), 50);
+    assert_eq!(hnsw.deleted_count(), 0);
+    let query = ArrayView1::from(&[10.0, 0.0]);
+    let results = hnsw.search(query, 10).unwrap();
+    assert_eq!(results.len(), 10);
+    assert_eq!(results[0], db.k_nearest(&[10.0, 0.0], 1).unwrap()[0]);
+
+    for id in 0..40 {
+      hnsw.remove(id);
+    }
+    let results = hnsw.search(query, 10).unwrap();
+    let ids: HashSet<_> = results.iter().map(|&(id, _)| id).collect();
+    assert_eq!(ids, (40..50).collect());
+
+    // Moving any node, the entry point included, keeps the others reachable.
+    for moved in 0..3 {
+      let mut hnsw =
+        Hnsw::new(2, MetricKind::Euclidean, HnswConfig::default()).unwrap();
+      for id in 0..3 {
+        hnsw
+          .insert(id, ArrayView1::from(&[id as f64, 0.0]))
+          .unwrap();
+      }
+      hnsw.insert(moved, ArrayView1::from(&[50.0, 50.0])).unwrap();
+      assert_eq!(hnsw.search(query, 3).unwrap().len(), 3);
+    }
+  }
+
+  #[test]
+  fn test_batch_insertion() {
+    let db = random_db(500, 8, MetricKind::Euclidean);
+    let mut ids: Vec<_> = db.ids().collect();
+    ids.sort_unstable();
+    let mut sequential =
+      Hnsw::new(8, db.metric(), HnswConfig::default()).unwrap();
+    for &id in &ids {
+      sequential.insert(id, db.get(id).unwrap()).unwrap();
+    }
+    let batch = Hnsw::build(&db, HnswConfig::default()).unwrap();
+    assert_eq!(batch.len(), 500);
+    assert!(recall(&db, &batch, 10) >= recall(&db, &sequential, 10) - 0.02);
+
+    let mut hnsw =
+      Hnsw::new(2, MetricKind::Euclidean, HnswConfig::default()).unwrap();
+    let vectors = ndarray::arr2(&[[0.0, 0.0], [0.1, 0.0], [5.0, 5.0]]);
+    assert!(matches!(
+      hnsw.insert_batch(&[7, 7, 8], vectors.view()),
+      Err(Error::InvalidValue(_))
+    ));
+    assert!(hnsw.is_empty());
+    hnsw.insert_batch(&[7, 8, 9], vectors.view()).unwrap();
+    let moved = ndarray::arr2(&[[5.0, 5.1], [9.0, 9.0]]);
+    hnsw.insert_batch(&[7, 10], moved.view()).unwrap();
+    assert_eq!(hnsw.len(), 4);
+    assert_eq!(hnsw.deleted_count(), 0);
+    let results = hnsw.search(ArrayView1::from(&[5.0, 5.0]), 4).unwrap();
+    let ids: Vec<_> = results.iter().map(|&(id, _)| id).collect();
+    assert_eq!(ids, [9, 7, 10, 8]);
+  }
+
+  #[test]
+  fn test_invalid_input() {
+    assert!(Hnsw::new(
+      2,
+      MetricKind::Euclidean,
+      HnswConfig {
+        m: 1,
+        ..HnswConfig::default()
+      }
+    )
+    .is_err());
+
+    let mut hnsw =
+      Hnsw::new(2, MetricKind::Euclidean, HnswConfig::default()).unwrap();
+    assert!(hnsw
+      .search(ArrayView1::from(&[1.0, 2.0]), 3)
+      .unwrap()
+      .is_empty());
+    assert_eq!(
+      hnsw.insert(0, ArrayView1::from(&[1.0])),
+      Err(Error::DimensionMismatch {
+        expected: 2,
+        found: 1
+      })
+    );
+  }
+}
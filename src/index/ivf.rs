@@ -0,0 +1,385 @@
+use super::{check_dimension, VectorIndex};
+use crate::distance::{Metric, MetricKind};
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::kmeans::{kmeans, nearest_centroid};
+use crate::neighbors::{cmp_keys, TopK};
+use crate::random::Rng;
+use crate::vector_database::{VectorDatabase, VectorId};
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2};
+use std::collections::HashMap;
+
+/// The parameters of an [`Ivf`] index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IvfConfig {
+  /// The number of inverted lists, i.e. of k-means centroids.
+  pub nlist: usize,
+  /// The number of lists scanned by a search. Higher values improve recall
+  /// at the cost of speed.
+  pub nprobe: usize,
+  /// The maximum number of k-means iterations run during training.
+  pub max_iterations: usize,
+  /// The seed used to initialize the k-means centroids.
+  pub seed: u64,
+}
+
+impl Default for IvfConfig {
+  fn default() -> Self {
+    Self {
+      nlist: 100,
+      nprobe: 8,
+      max_iterations: 25,
+      seed: 42,
+    }
+  }
+}
+
+/// Summary statistics of the sizes of the inverted lists of an [`Ivf`] index.
+///
+/// Well balanced lists keep search times predictable; many empty or very
+/// large lists suggest that `nlist` does not fit the data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IvfStats {
+  pub nlist: usize,
+  pub min: usize,
+  pub max: usize,
+  pub mean: f64,
+  pub std_dev: f64,
+  pub empty_lists: usize,
+}
+
+/// An inverted file index.
+///
+/// A coarse quantizer made of `nlist` k-means centroids partitions the vector
+/// space, and each vector is stored in the list of its nearest centroid.
+/// Searches only scan the `nprobe` lists whose centroids are closest to the
+/// query.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::index::{Ivf, IvfConfig, VectorIndex};
+/// use rustyvectors::vector_database::VectorDatabase;
+///
+/// let mut db = VectorDatabase::new(2)?;
+/// for i in 0..100 {
+///   db.add(&[i as f64, (i % 10) as f64])?;
+/// }
+/// let config = IvfConfig { nlist: 10, nprobe: 3, ..IvfConfig::default() };
+/// let ivf = Ivf::build(&db, config)?;
+/// assert_eq!(ivf.stats().nlist, 10);
+/// db.set_index(ivf)?;
+/// let neighbors = db.k_nearest_approx(&[42.0, 2.0], 5)?;
+/// assert_eq!(neighbors[0], (42, 0.0));
+/// # Ok::<(), rustyvectors::Error>(())
+/// ```
+pub struct Ivf {
+  config: IvfConfig,
+  metric: MetricKind,
+  centroids: Array2<f64>,
+  lists: Vec<InvertedList>,
+  /// The list and position within it of each indexed ID.
+  locations: HashMap<VectorId, (usize, usize)>,
+}
+
+#[derive(Default)]
+struct InvertedList {
+  ids: Vec<VectorId>,
+  vectors: Vec<Array1<f64>>,
+}
+
+impl Ivf {
+  /// Trains the coarse quantizer on the rows of `samples` and returns an
+  /// empty index for vectors of the same dimension compared with `metric`.
+  ///
+  /// Returns [`Error::InvalidValue`] if `nlist` or `nprobe` is zero or if
+  /// there are fewer samples than lists.
+  pub fn train(
+    samples: ArrayView2<f64>,
+    metric: MetricKind,
+    config: IvfConfig,
+  ) -> Result<Self> {
+    if config.nlist == 0 || config.nprobe == 0 {
+      return Err(Error::InvalidValue(
+        "IVF nlist and nprobe must be at least 1".to_string(),
+      ));
+    }
+    if samples.nrows() < config.nlist {
+      return Err(Error::InvalidValue(format!(
+        "IVF training needs at least {} vectors, got {}",
+        config.nlist,
+        samples.nrows()
+      )));
+    }
+
+    let mut rng = Rng::new(config.seed);
+    let centroids = kmeans(
+      samples,
+      config.nlist,
+      metric,
+      config.max_iterations,
+      &mut rng,
+    );
+    Ok(Self {
+      config,
+      metric,
+      centroids,
+      lists: (0..config.nlist).map(|_| InvertedList::default()).collect(),
+      locations: HashMap::new(),
+    })
+  }
+
+  /// Trains an index on all the vectors of `db` and adds them to it, using
+  /// the database's metric.
+  pub fn build<T: Element>(
+    db: &VectorDatabase<T>,
+    config: IvfConfig,
+  ) -> Result<Self> {
+    let mut ids: Vec<_> = db.ids().collect();
+    ids.sort_unstable();
+    let mut samples = Array2::zeros((ids.len(), db.dimension()));
+    for (mut row, &id) in samples.outer_iter_mut().zip(&ids) {
+      if let Some(vector) = db.get(id) {
+        row.zip_mut_with(&vector, |x, &y| *x = y.to_f64());
+      }
+    }
+
+    let mut ivf = Self::train(samples.view(), db.metric(), config)?;
+    for (row, &id) in samples.outer_iter().zip(&ids) {
+      ivf.insert(id, row)?;
+    }
+    Ok(ivf)
+  }
+
+  /// Returns the parameters of the index.
+  pub fn config(&self) -> IvfConfig {
+    self.config
+  }
+
+  /// Changes the number of lists scanned by a search, trading speed for
+  /// recall. Values are clamped between 1 and `nlist`.
+  pub fn set_nprobe(&mut self, nprobe: usize) {
+    self.config.nprobe = nprobe.clamp(1, self.config.nlist);
+  }
+
+  /// Returns the trained centroids, one per row.
+  pub fn centroids(&self) -> ArrayView2<'_, f64> {
+    self.centroids.view()
+  }
+
+  /// Returns the number of vectors in each inverted list.
+  pub fn list_sizes(&self) -> Vec<usize> {
+    self.lists.iter().map(|list| list.ids.len()).collect()
+  }
+
+  /// Returns statistics about the sizes of the inverted lists.
+  pub fn stats(&self) -> IvfStats {
+    let sizes = self.list_sizes();
+    let nlist = sizes.len();
+    let mean = sizes.iter().sum::<usize>() as f64 / nlist as f64;
+    let variance = sizes
+      .iter()
+      .map(|&size| (size as f64 - mean).powi(2))
+      .sum::<f64>()
+      / nlist as f64;
+    IvfStats {
+      nlist,
+      min: sizes.iter().copied().min().unwrap_or(0),
+      max: sizes.iter().copied().max().unwrap_or(0),
+      mean,
+      std_dev: variance.sqrt(),
+      empty_lists: sizes.iter().filter(|&&size| size == 0).count(),
+    }
+  }
+
+  /// Returns the `nprobe` lists whose centroids are closest to `query`.
+  fn probed_lists(&self, query: &ArrayView1<f64>) -> Vec<usize> {
+    let order = self.metric.order();
+    let mut lists: Vec<(usize, f64)> = self
+      .centroids
+      .outer_iter()
+      .map(|centroid| order.key(self.metric.score(&centroid, query)))
+      .enumerate()
+      .collect();
+    lists.sort_unstable_by(|a, b| cmp_keys(a.1, b.1));
+    lists.truncate(self.config.nprobe);
+    lists.into_iter().map(|(list, _)| list).collect()
+  }
+}
+
+impl VectorIndex for Ivf {
+  fn dimension(&self) -> usize {
+    self.centroids.ncols()
+  }
+
+  fn metric(&self) -> MetricKind {
+    self.metric
+  }
+
+  fn len(&self) -> usize {
+    self.locations.len()
+  }
+
+  fn contains(&self, id: VectorId) -> bool {
+    self.locations.contains_key(&id)
+  }
+
+  fn insert(&mut self, id: VectorId, vector: ArrayView1<f64>) -> Result<()> {
+    check_dimension(&vector, self.dimension())?;
+    self.remove(id);
+
+    let list = nearest_centroid(self.centroids.view(), vector, self.metric);
+    let entries = &mut self.lists[list];
+    self.locations.insert(id, (list, entries.ids.len()));
+    entries.ids.push(id);
+    entries.vectors.push(vector.to_owned());
+    Ok(())
+  }
+
+  fn remove(&mut self, id: VectorId) -> bool {
+    let Some((list, position)) = self.locations.remove(&id) else {
+      return false;
+    };
+    let entries = &mut self.lists[list];
+    entries.ids.swap_remove(position);
+    entries.vectors.swap_remove(position);
+    if let Some(&moved) = entries.ids.get(position) {
+      self.locations.insert(moved, (list, position));
+    }
+    true
+  }
+
+  fn search(
+    &self,
+    query: ArrayView1<f64>,
+    k: usize,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    check_dimension(&query, self.dimension())?;
+    let mut top_k = TopK::new(k, self.metric.order());
+    for list in self.probed_lists(&query) {
+      let entries = &self.lists[list];
+      for (&id, vector) in entries.ids.iter().zip(&entries.vectors) {
+        top_k.push(id, self.metric.score(&vector.view(), &query));
+      }
+    }
+    Ok(top_k.into_sorted_vec())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn clustered_db(metric: MetricKind) -> VectorDatabase {
+    let mut rng = Rng::new(3);
+    let mut db = VectorDatabase::with_metric(4, metric).unwrap();
+    for i in 0..1000 {
+      let center = (i % 20) as f64;
+      let vector: Vec<f64> = (0..4)
+        .map(|d| center * (d + 1) as f64 + rng.next_f64())
+        .collect();
+      db.add(&vector).unwrap();
+    }
+    db
+  }
+
+  #[test]
+  fn test_lists_cover_all_vectors() {
+    let db = clustered_db(MetricKind::Euclidean);
+    let config = IvfConfig {
+      nlist: 20,
+      ..IvfConfig::default()
+    };
+    let ivf = Ivf::build(&db, config).unwrap();
+    let stats = ivf.stats();
+
+    assert_eq!(ivf.len(), 1000);
+    assert_eq!(ivf.list_sizes().iter().sum::<usize>(), 1000);
+    assert_eq!(stats.nlist, 20);
+    assert_eq!(stats.mean, 50.0);
+    assert!(stats.min <= 50 && stats.max >= 50);
+    assert_eq!(ivf.centroids().dim(), (20, 4));
+  }
+
+  #[test]
+  fn test_search_matches_exact_when_probing_all_lists() {
+    for metric in [MetricKind::Euclidean, MetricKind::Cosine] {
+      let db = clustered_db(metric);
+      let config = IvfConfig {
+        nlist: 16,
+        nprobe: 16,
+        ..IvfConfig::default()
+      };
+      let ivf = Ivf::build(&db, config).unwrap();
+      let query = [3.3, 6.1, 9.4, 12.2];
+      assert_eq!(
+        ivf.search(ArrayView1::from(&query), 10).unwrap(),
+        db.k_nearest(&query, 10).unwrap()
+      );
+    }
+  }
+
+  #[test]
+  fn test_recall_with_few_probes() {
+    let db = clustered_db(MetricKind::Euclidean);
+    let config = IvfConfig {
+      nlist: 20,
+      nprobe: 3,
+      ..IvfConfig::default()
+    };
+    let ivf = Ivf::build(&db, config).unwrap();
+    let query = [7.5, 15.0, 22.5, 30.0];
+    let exact = db.k_nearest(&query, 10).unwrap();
+    let approx = ivf.search(ArrayView1::from(&query), 10).unwrap();
+    let found = approx
+      .iter()
+      .filter(|result| exact.contains(result))
+      .count();
+    assert!(found >= 9);
+  }
+
+  #[test]
+  fn test_insert_after_training_and_remove() {
+    let db = clustered_db(MetricKind::Euclidean);
+    let config = IvfConfig {
+      nlist: 20,
+      ..IvfConfig::default()
+    };
+    let mut ivf = Ivf::build(&db, config).unwrap();
+    let vector = [100.0, 200.0, 300.0, 400.0];
+
+    ivf.insert(5000, ArrayView1::from(&vector)).unwrap();
+    assert_eq!(ivf.len(), 1001);
+    assert_eq!(
+      ivf.search(ArrayView1::from(&vector), 1).unwrap(),
+      vec![(5000, 0.0)]
+    );
+    assert!(ivf.remove(5000));
+    assert!(ivf.remove(0));
+    assert!(!ivf.remove(0));
+    assert_eq!(ivf.len(), 999);
+    assert_eq!(ivf.list_sizes().iter().sum::<usize>(), 999);
+  }
+
+  #[test]
+  fn test_invalid_training() {
+    let db = clustered_db(MetricKind::Euclidean);
+    let too_many_lists = IvfConfig {
+      nlist: 2000,
+      ..IvfConfig::default()
+    };
+    assert!(matches!(
+      Ivf::build(&db, too_many_lists),
+      Err(Error::InvalidValue(_))
+    ));
+    let no_probe = IvfConfig {
+      nprobe: 0,
+      ..IvfConfig::default()
+    };
+    assert!(matches!(
+      Ivf::build(&db, no_probe),
+      Err(Error::InvalidValue(_))
+    ));
+  }
+}
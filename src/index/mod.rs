@@ -0,0 +1,172 @@
+//! Approximate nearest neighbor indexes.
+//!
+//! An index keeps its own copy of the vectors it is built from, organized so
+//! that searches only visit a small part of them. Indexes can be used on their
+//! own or attached to a [`VectorDatabase`] with
+//! [`VectorDatabase::set_index`], which then keeps them up to date.
+//!
+//! [`VectorDatabase`]: crate::vector_database::VectorDatabase
+//! [`VectorDatabase::set_index`]: crate::vector_database::VectorDatabase::set_index
+
+mod hnsw;
+mod ivf;
+mod pq;
+
+pub use hnsw::{Hnsw, HnswConfig};
+pub use ivf::{Ivf, IvfConfig, IvfStats};
+pub use pq::Pq;
+
+use crate::distance::{Metric, MetricKind};
+use crate::error::{Error, Result};
+use crate::neighbors::cmp_keys;
+use crate::vector_database::VectorId;
+use ndarray::{ArrayView1, ArrayView2};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// The number of results a radius search without a limit asks an index for
+/// at first.
+const RADIUS_SEARCH_SIZE: usize = 16;
+
+/// An approximate nearest neighbor index.
+pub trait VectorIndex: Send + Sync {
+  /// Returns the number of components of the indexed vectors.
+  fn dimension(&self) -> usize;
+
+  /// Returns the metric used to compare vectors.
+  fn metric(&self) -> MetricKind;
+
+  /// Returns the number of indexed vectors.
+  fn len(&self) -> usize;
+
+  /// Returns `true` if no vector is indexed.
+  fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Returns `true` if a vector with the given ID is indexed.
+  fn contains(&self, id: VectorId) -> bool;
+
+  /// Indexes `vector` under `id`, replacing any vector already indexed under
+  /// the same ID.
+  fn insert(&mut self, id: VectorId, vector: ArrayView1<f64>) -> Result<()>;
+
+  /// Indexes each row of `vectors` under the ID at the same position in
+  /// `ids`, as if inserting them one after the other.
+  ///
+  /// Returns [`Error::InvalidValue`] if there are not as many IDs as rows or
+  /// if an ID is repeated, and [`Error::DimensionMismatch`] if the rows do
+  /// not have the index's
+  /// dimension, before indexing any vector. The provided implementation calls
+  /// [`Self::insert`] for each vector; indexes that can be built faster from
+  /// many vectors at once override it.
+  fn insert_batch(
+    &mut self,
+    ids: &[VectorId],
+    vectors: ArrayView2<f64>,
+  ) -> Result<()> {
+    check_batch(ids, &vectors, self.dimension())?;
+    for (&id, vector) in ids.iter().zip(vectors.outer_iter()) {
+      self.insert(id, vector)?;
+    }
+    Ok(())
+  }
+
+  /// Removes the vector indexed under `id`, returning whether it existed.
+  fn remove(&mut self, id: VectorId) -> bool;
+
+  /// Finds approximately the `k` vectors closest to `query`.
+  ///
+  /// Results are `(id, distance)` pairs ordered as in
+  /// [`VectorDatabase::k_nearest`](crate::vector_database::VectorDatabase::k_nearest).
+  fn search(
+    &self,
+    query: ArrayView1<f64>,
+    k: usize,
+  ) -> Result<Vec<(VectorId, f64)>>;
+
+  /// Finds approximately the vectors within `radius` of `query`, or the
+  /// closest `limit` of them if there is a limit.
+  ///
+  /// Results are ordered as in [`Self::search`]. For similarities, vectors
+  /// are within the radius if their score is at least `radius`. Returns
+  /// [`Error::InvalidValue`] if `radius` is `NaN`.
+  ///
+  /// The provided implementation calls [`Self::search`] for a number of
+  /// results that doubles until one of them is outside the radius or the
+  /// index has no more, so any index supports radius searches.
+  fn search_within(
+    &self,
+    query: ArrayView1<f64>,
+    radius: f64,
+    limit: Option<usize>,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    check_radius(radius)?;
+    let order = self.metric().order();
+    let outside = |&(_, score): &(VectorId, f64)| {
+      cmp_keys(order.key(score), order.key(radius)) == Ordering::Greater
+    };
+    let mut k = limit.unwrap_or(RADIUS_SEARCH_SIZE).min(self.len());
+    loop {
+      let mut results = self.search(query, k)?;
+      let exhausted = results.len() < k || k >= self.len();
+      if exhausted || limit.is_some() || results.last().is_some_and(outside) {
+        let within = results.partition_point(|result| !outside(result));
+        results.truncate(within);
+        return Ok(results);
+      }
+      k = k.saturating_mul(2).min(self.len());
+    }
+  }
+}
+
+/// Checks that there is one distinct ID per row of `vectors` and that the
+/// rows have `dimension` components.
+pub(crate) fn check_batch(
+  ids: &[VectorId],
+  vectors: &ArrayView2<f64>,
+  dimension: usize,
+) -> Result<()> {
+  if ids.len() != vectors.nrows() {
+    return Err(Error::InvalidValue(format!(
+      "got {} ids for {} vectors",
+      ids.len(),
+      vectors.nrows()
+    )));
+  }
+  let mut seen = HashSet::with_capacity(ids.len());
+  if let Some(id) = ids.iter().find(|&&id| !seen.insert(id)) {
+    return Err(Error::InvalidValue(format!(
+      "id {id} is repeated in the batch"
+    )));
+  }
+  if vectors.ncols() != dimension {
+    return Err(Error::DimensionMismatch {
+      expected: dimension,
+      found: vectors.ncols(),
+    });
+  }
+  Ok(())
+}
+
+/// Checks that `radius` is a valid radius for a radius search.
+pub(crate) fn check_radius(radius: f64) -> Result<()> {
+  if radius.is_nan() {
+    return Err(Error::InvalidValue("radius must not be NaN".to_string()));
+  }
+  Ok(())
+}
+
+/// Checks that `vector` has `dimension` components.
+pub(crate) fn check_dimension(
+  vector: &ArrayView1<f64>,
+  dimension: usize,
+) -> Result<()> {
+  if vector.len() != dimension {
+    return Err(Error::DimensionMismatch {
+      expected: dimension,
+      found: vector.len(),
+    });
+  }
+  Ok(())
+}
@@ -0,0 +1,249 @@
+use super::{check_dimension, VectorIndex};
+use crate::distance::{Metric, MetricKind};
+use crate::element::Element;
+use crate::error::Result;
+use crate::neighbors::TopK;
+use crate::quantization::{normalized, PqConfig, ProductQuantizer};
+use crate::vector_database::{VectorDatabase, VectorId};
+use ndarray::{Array2, ArrayView1, ArrayView2};
+use std::collections::HashMap;
+
+/// An index storing product-quantized codes instead of full vectors.
+///
+/// Each vector takes `num_subspaces` bytes, and searches scan all the codes
+/// with asymmetric distances, so the returned scores are approximations. When
+/// the index is attached to a database,
+/// [`VectorDatabase::k_nearest_reranked`] re-scores the best candidates
+/// exactly against the stored vectors.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::index::{Pq, VectorIndex};
+/// use rustyvectors::quantization::PqConfig;
+/// use rustyvectors::vector_database::VectorDatabase;
+///
+/// let mut db = VectorDatabase::new(4)?;
+/// for i in 0..100 {
+///   db.add(&[i as f64, (i % 10) as f64, (i % 7) as f64, 1.0])?;
+/// }
+/// let config = PqConfig { num_subspaces: 2, codebook_size: 32, ..PqConfig::default() };
+/// let pq = Pq::build(&db, config)?;
+/// assert_eq!(pq.memory_per_vector(), 2);
+/// db.set_index(pq)?;
+/// let neighbors = db.k_nearest_reranked(&[42.0, 2.0, 0.0, 1.0], 3, 20)?;
+/// assert_eq!(neighbors[0], (42, 0.0));
+/// # Ok::<(), rustyvectors::Error>(())
+/// ```
+pub struct Pq {
+  quantizer: ProductQuantizer,
+  metric: MetricKind,
+  /// The codes of all vectors, `code_size` bytes each.
+  codes: Vec<u8>,
+  /// The ID of the vector whose code is at the same position in `codes`.
+  ids: Vec<VectorId>,
+  positions: HashMap<VectorId, usize>,
+}
+
+impl Pq {
+  /// Trains the quantizer on the rows of `samples` and returns an empty index
+  /// for vectors compared with `metric`.
+  ///
+  /// See [`ProductQuantizer::train`] for the possible errors.
+  pub fn train(
+    samples: ArrayView2<f64>,
+    metric: MetricKind,
+    config: PqConfig,
+  ) -> Result<Self> {
+    let quantizer = match metric {
+      MetricKind::Cosine => {
+        let mut unit = samples.to_owned();
+        for mut row in unit.outer_iter_mut() {
+          let normalized_row = normalized(row.view());
+          row.assign(&normalized_row);
+        }
+        ProductQuantizer::train(unit.view(), config)?
+      }
+      _ => ProductQuantizer::train(samples, config)?,
+    };
+    Ok(Self {
+      quantizer,
+      metric,
+      codes: Vec::new(),
+      ids: Vec::new(),
+      positions: HashMap::new(),
+    })
+  }
+
+  /// Trains an index on all the vectors of `db` and adds them to it, using
+  /// the database's metric.
+  pub fn build<T: Element>(
+    db: &VectorDatabase<T>,
+    config: PqConfig,
+  ) -> Result<Self> {
+    let mut ids: Vec<_> = db.ids().collect();
+    ids.sort_unstable();
+    let mut samples = Array2::zeros((ids.len(), db.dimension()));
+    for (mut row, &id) in samples.outer_iter_mut().zip(&ids) {
+      if let Some(vector) = db.get(id) {
+        row.zip_mut_with(&vector, |x, &y| *x = y.to_f64());
+      }
+    }
+
+    let mut pq = Self::train(samples.view(), db.metric(), config)?;
+    for (row, &id) in samples.outer_iter().zip(&ids) {
+      pq.insert(id, row)?;
+    }
+    Ok(pq)
+  }
+
+  /// Returns the trained quantizer.
+  pub fn quantizer(&self) -> &ProductQuantizer {
+    &self.quantizer
+  }
+
+  /// Returns the number of bytes used to store each vector's code.
+  pub fn memory_per_vector(&self) -> usize {
+    self.quantizer.code_size()
+  }
+
+  fn code(&self, position: usize) -> &[u8] {
+    let size = self.quantizer.code_size();
+    &self.codes[position * size..(position + 1) * size]
+  }
+}
+
+impl VectorIndex for Pq {
+  fn dimension(&self) -> usize {
+    self.quantizer.dimension()
+  }
+
+  fn metric(&self) -> MetricKind {
+    self.metric
+  }
+
+  fn len(&self) -> usize {
+    self.ids.len()
+  }
+
+  fn contains(&self, id: VectorId) -> bool {
+    self.positions.contains_key(&id)
+  }
+
+  fn insert(&mut self, id: VectorId, vector: ArrayView1<f64>) -> Result<()> {
+    check_dimension(&vector, self.dimension())?;
+    let code = match self.metric {
+      MetricKind::Cosine => self.quantizer.encode(normalized(vector).view())?,
+      _ => self.quantizer.encode(vector)?,
+    };
+    self.remove(id);
+    self.positions.insert(id, self.ids.len());
+    self.ids.push(id);
+    self.codes.extend(code);
+    Ok(())
+  }
+
+  fn remove(&mut self, id: VectorId) -> bool {
+    let Some(position) = self.positions.remove(&id) else {
+      return false;
+    };
+    let size = self.quantizer.code_size();
+    let last = self.ids.len() - 1;
+    if position != last {
+      self
+        .codes
+        .copy_within(last * size..(last + 1) * size, position * size);
+    }
+    self.codes.truncate(last * size);
+    self.ids.swap_remove(position);
+    if let Some(&moved) = self.ids.get(position) {
+      self.positions.insert(moved, position);
+    }
+    true
+  }
+
+  fn search(
+    &self,
+    query: ArrayView1<f64>,
+    k: usize,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    let table = self.quantizer.distance_table(query, self.metric)?;
+    let mut top_k = TopK::new(k, self.metric.order());
+    for (position, &id) in self.ids.iter().enumerate() {
+      top_k.push(id, table.score(self.code(position)));
+    }
+    Ok(top_k.into_sorted_vec())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::random::Rng;
+
+  /// Builds a database of 20 clusters of 20 vectors around random centers.
+  fn db(metric: MetricKind) -> VectorDatabase {
+    let mut rng = Rng::new(9);
+    let mut db = VectorDatabase::with_metric(8, metric).unwrap();
+    let centers: Vec<Vec<f64>> = (0..20)
+      .map(|_| (0..8).map(|_| rng.next_f64() * 20.0 - 10.0).collect())
+      .collect();
+    for i in 0..400 {
+      let vector: Vec<f64> = centers[i % 20]
+        .iter()
+        .map(|&x| x + rng.next_f64() * 0.3)
+        .collect();
+      db.add(&vector).unwrap();
+    }
+    db
+  }
+
+  fn config() -> PqConfig {
+    PqConfig {
+      num_subspaces: 4,
+      codebook_size: 64,
+      ..PqConfig::default()
+    }
+  }
+
+  #[test]
+  fn test_codes_are_compact() {
+    let pq = Pq::build(&db(MetricKind::Euclidean), config()).unwrap();
+    assert_eq!(pq.len(), 400);
+    assert_eq!(pq.memory_per_vector(), 4);
+    assert_eq!(pq.codes.len(), 1600);
+  }
+
+  #[test]
+  fn test_reranking_recovers_exact_results() {
+    for metric in [MetricKind::Euclidean, MetricKind::Cosine] {
+      let mut db = db(metric);
+      let pq = Pq::build(&db, config()).unwrap();
+      db.set_index(pq).unwrap();
+      let query: Vec<f64> =
+        db.get(7).unwrap().iter().map(|&x| x + 0.05).collect();
+
+      let exact = db.k_nearest(&query, 5).unwrap();
+      let approx = db.k_nearest_approx(&query, 50).unwrap();
+      let found = exact
+        .iter()
+        .filter(|(id, _)| approx.iter().any(|(candidate, _)| candidate == id));
+      assert_eq!(found.count(), 5, "{metric:?}");
+      assert_eq!(db.k_nearest_reranked(&query, 5, 50).unwrap(), exact);
+    }
+  }
+
+  #[test]
+  fn test_remove_keeps_codes_aligned() {
+    let db = db(MetricKind::Euclidean);
+    let mut pq = Pq::build(&db, config()).unwrap();
+    let last = db.get(399).unwrap().to_owned();
+
+    assert!(pq.remove(0));
+    assert!(!pq.remove(0));
+    assert_eq!(pq.len(), 399);
+    assert_eq!(pq.codes.len(), 399 * 4);
+    let moved = pq.positions[&399];
+    assert_eq!(pq.code(moved), pq.quantizer.encode(last.view()).unwrap());
+  }
+}
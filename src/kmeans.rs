@@ -0,0 +1,170 @@
+use crate::distance::{Metric, MetricKind};
+use crate::neighbors::cmp_keys;
+use crate::random::Rng;
+use ndarray::{Array2, ArrayView1, ArrayView2, Axis};
+
+/// Returns the row of `centroids` closest to `vector` under `metric`.
+pub(crate) fn nearest_centroid(
+  centroids: ArrayView2<f64>,
+  vector: ArrayView1<f64>,
+  metric: MetricKind,
+) -> usize {
+  let order = metric.order();
+  centroids
+    .outer_iter()
+    .map(|centroid| order.key(metric.score(&centroid, &vector)))
+    .enumerate()
+    .min_by(|(_, a), (_, b)| cmp_keys(*a, *b))
+    .map_or(0, |(i, _)| i)
+}
+
+/// Clusters the rows of `data` into `k` groups with Lloyd's algorithm and
+/// returns the centroids, one per row.
+///
+/// Centroids are seeded with k-means++ and moved to the mean of their members
+/// until assignments stop changing or `max_iterations` is reached.
+/// With [`MetricKind::Cosine`] centroids are normalized after each update
+/// (spherical k-means). A centroid left without members is moved to a random
+/// row. `data` must hold at least `k` rows.
+pub(crate) fn kmeans(
+  data: ArrayView2<f64>,
+  k: usize,
+  metric: MetricKind,
+  max_iterations: usize,
+  rng: &mut Rng,
+) -> Array2<f64> {
+  let (rows, dimension) = data.dim();
+  let mut centroids = seed_centroids(data, k, rng);
+  normalize_if_cosine(&mut centroids, metric);
+
+  let mut assignments = vec![usize::MAX; rows];
+  for _ in 0..max_iterations {
+    let mut changed = false;
+    for (row, assignment) in data.outer_iter().zip(&mut assignments) {
+      let nearest = nearest_centroid(centroids.view(), row, metric);
+      changed |= nearest != *assignment;
+      *assignment = nearest;
+    }
+    if !changed {
+      break;
+    }
+
+    let mut sums = Array2::<f64>::zeros((k, dimension));
+    let mut counts = vec![0usize; k];
+    for (row, &assignment) in data.outer_iter().zip(&assignments) {
+      sums.row_mut(assignment).scaled_add(1.0, &row);
+      counts[assignment] += 1;
+    }
+    for (centroid, &count) in counts.iter().enumerate() {
+      if count == 0 {
+        let row = rng.below(rows);
+        centroids.row_mut(centroid).assign(&data.row(row));
+      } else {
+        let mean = &sums.row(centroid) / count as f64;
+        centroids.row_mut(centroid).assign(&mean);
+      }
+    }
+    normalize_if_cosine(&mut centroids, metric);
+  }
+  centroids
+}
+
+/// Picks `k` rows as initial centroids with k-means++: each new centroid is
+/// drawn with a probability proportional to its squared Euclidean distance to
+/// the closest centroid picked so far, which spreads them across the data.
+fn seed_centroids(
+  data: ArrayView2<f64>,
+  k: usize,
+  rng: &mut Rng,
+) -> Array2<f64> {
+  let (rows, dimension) = data.dim();
+  let mut centroids = Array2::zeros((k, dimension));
+  let first = rng.below(rows);
+  centroids.row_mut(0).assign(&data.row(first));
+  let mut weights: Vec<f64> = data
+    .outer_iter()
+    .map(|row| squared_distance(row, data.row(first)))
+    .collect();
+
+  for centroid in 1..k {
+    let total: f64 = weights.iter().sum();
+    let chosen = if total > 0.0 {
+      let mut target = rng.next_f64() * total;
+      weights
+        .iter()
+        .position(|&weight| {
+          target -= weight;
+          target < 0.0
+        })
+        .unwrap_or(rows - 1)
+    } else {
+      // Every row coincides with a centroid already, so any row will do.
+      rng.below(rows)
+    };
+    centroids.row_mut(centroid).assign(&data.row(chosen));
+    for (weight, row) in weights.iter_mut().zip(data.outer_iter()) {
+      *weight = weight.min(squared_distance(row, data.row(chosen)));
+    }
+  }
+  centroids
+}
+
+fn squared_distance(a: ArrayView1<f64>, b: ArrayView1<f64>) -> f64 {
+  a.iter().zip(b).map(|(&x, &y)| (x - y).powi(2)).sum()
+}
+
+fn normalize_if_cosine(centroids: &mut Array2<f64>, metric: MetricKind) {
+  if metric != MetricKind::Cosine {
+    return;
+  }
+  for mut centroid in centroids.axis_iter_mut(Axis(0)) {
+    let norm = centroid.dot(&centroid).sqrt();
+    if norm > 0.0 {
+      centroid /= norm;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ndarray::arr2;
+
+  #[test]
+  fn test_separates_clusters() {
+    let data = arr2(&[
+      [0.0, 0.0],
+      [0.1, 0.0],
+      [0.0, 0.1],
+      [10.0, 10.0],
+      [10.1, 10.0],
+      [10.0, 10.1],
+    ]);
+    let centroids =
+      kmeans(data.view(), 2, MetricKind::Euclidean, 20, &mut Rng::new(1));
+
+    let near_origin =
+      nearest_centroid(centroids.view(), data.row(0), MetricKind::Euclidean);
+    let far =
+      nearest_centroid(centroids.view(), data.row(3), MetricKind::Euclidean);
+    assert_ne!(near_origin, far);
+    for row in 1..3 {
+      assert_eq!(
+        nearest_centroid(
+          centroids.view(),
+          data.row(row),
+          MetricKind::Euclidean
+        ),
+        near_origin
+      );
+      assert_eq!(
+        nearest_centroid(
+          centroids.view(),
+          data.row(row + 3),
+          MetricKind::Euclidean
+        ),
+        far
+      );
+    }
+  }
+}
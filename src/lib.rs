@@ -1,2 +1,19 @@
 pub mod distance;
+pub mod element;
+pub mod error;
+pub mod filter;
+pub mod formats;
+pub mod index;
+mod kmeans;
+mod matrix;
+pub mod metadata;
+mod neighbors;
+pub mod quantization;
+mod random;
+#[cfg(feature = "serde")]
+mod serialization;
+mod simd;
+pub mod storage;
 pub mod vector_database;
+
+pub use error::{Error, Result};
@@ -0,0 +1,130 @@
+use ndarray::{Array1, ArrayView1, ArrayView2};
+
+/// Vectors of the same dimension stored one after the other in a single
+/// row-major buffer.
+///
+/// Keeping the vectors contiguous, instead of in separate allocations, lets
+/// scans read them in order from memory. The buffer grows like a `Vec`, so
+/// adding a row takes amortized constant time.
+#[derive(Debug, Clone)]
+pub(crate) struct Matrix<T> {
+  data: Vec<T>,
+  columns: usize,
+}
+
+impl<T: Copy> Matrix<T> {
+  /// Creates an empty matrix of rows of `columns` components, which must be
+  /// at least 1.
+  pub fn new(columns: usize) -> Self {
+    assert!(columns > 0, "rows must have at least one component");
+    Self {
+      data: Vec::new(),
+      columns,
+    }
+  }
+
+  /// Returns the number of rows.
+  pub fn len(&self) -> usize {
+    self.data.len() / self.columns
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.data.is_empty()
+  }
+
+  /// Returns the number of rows the matrix can hold without reallocating.
+  pub fn capacity(&self) -> usize {
+    self.data.capacity() / self.columns
+  }
+
+  /// Reserves room for at least `additional` more rows.
+  pub fn reserve(&mut self, additional: usize) {
+    let additional = additional
+      .checked_mul(self.columns)
+      .expect("capacity overflow");
+    self.data.reserve(additional);
+  }
+
+  /// Appends a row, which must have as many components as the others.
+  pub fn push(&mut self, row: &[T]) {
+    assert_eq!(row.len(), self.columns, "row has the wrong length");
+    self.data.extend_from_slice(row);
+  }
+
+  /// Appends the rows stored one after the other in `rows`, which must hold
+  /// whole rows.
+  pub fn extend(&mut self, rows: &[T]) {
+    assert_eq!(rows.len() % self.columns, 0, "rows have the wrong length");
+    self.data.extend_from_slice(rows);
+  }
+
+  /// Returns the row at `position`, which must be in bounds.
+  pub fn row(&self, position: usize) -> ArrayView1<'_, T> {
+    ArrayView1::from(self.row_slice(position))
+  }
+
+  pub fn row_slice(&self, position: usize) -> &[T] {
+    &self.data[position * self.columns..(position + 1) * self.columns]
+  }
+
+  /// Overwrites the row at `position`, which must be in bounds, and returns
+  /// its previous contents.
+  pub fn replace(&mut self, position: usize, row: &[T]) -> Array1<T> {
+    assert_eq!(row.len(), self.columns, "row has the wrong length");
+    let start = position * self.columns;
+    let previous = Array1::from(self.row_slice(position).to_vec());
+    self.data[start..start + self.columns].copy_from_slice(row);
+    previous
+  }
+
+  /// Removes the row at `position` and returns it, moving the last row in
+  /// its place.
+  pub fn swap_remove(&mut self, position: usize) -> Array1<T> {
+    let start = position * self.columns;
+    let removed = Array1::from(self.row_slice(position).to_vec());
+    let last = self.data.len() - self.columns;
+    if start != last {
+      self.data.copy_within(last.., start);
+    }
+    self.data.truncate(last);
+    removed
+  }
+
+  /// Returns all the rows as a matrix view.
+  pub fn view(&self) -> ArrayView2<'_, T> {
+    ArrayView2::from_shape((self.len(), self.columns), &self.data)
+      .expect("the buffer holds whole rows")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ndarray::arr2;
+
+  #[test]
+  fn test_push_and_swap_remove() {
+    let mut matrix = Matrix::new(2);
+    assert!(matrix.is_empty());
+    matrix.reserve(3);
+    assert!(matrix.capacity() >= 3);
+    for row in [[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]] {
+      matrix.push(&row);
+    }
+    assert_eq!(matrix.len(), 3);
+    assert_eq!(matrix.row(1).to_vec(), [3.0, 4.0]);
+    matrix.extend(&[7.0, 8.0, 9.0, 10.0]);
+    assert_eq!(matrix.row(4).to_vec(), [9.0, 10.0]);
+    matrix.swap_remove(4);
+    matrix.swap_remove(3);
+    assert_eq!(matrix.replace(1, &[-3.0, -4.0]).to_vec(), [3.0, 4.0]);
+    assert_eq!(matrix.replace(1, &[3.0, 4.0]).to_vec(), [-3.0, -4.0]);
+
+    assert_eq!(matrix.swap_remove(0).to_vec(), [1.0, 2.0]);
+    assert_eq!(matrix.view(), arr2(&[[5.0, 6.0], [3.0, 4.0]]));
+    assert_eq!(matrix.swap_remove(1).to_vec(), [3.0, 4.0]);
+    assert_eq!(matrix.view(), arr2(&[[5.0, 6.0]]));
+    matrix.swap_remove(0);
+    assert!(matrix.is_empty());
+  }
+}
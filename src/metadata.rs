@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+
+/// The payload stored alongside a vector: a map from field names to values.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::metadata::{Metadata, Value};
+///
+/// let mut metadata = Metadata::new();
+/// metadata.insert("title".to_string(), Value::from("Vector databases"));
+/// metadata.insert("year".to_string(), Value::from(2023));
+/// metadata.insert("tags".to_string(), Value::from(vec!["rust", "search"]));
+/// ```
+pub type Metadata = BTreeMap<String, Value>;
+
+/// A JSON-like value held in a [`Metadata`] field.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+  #[default]
+  Null,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  String(String),
+  List(Vec<Value>),
+  Map(Metadata),
+}
+
+impl Value {
+  /// Returns the boolean if this is a `Bool`.
+  pub fn as_bool(&self) -> Option<bool> {
+    match *self {
+      Value::Bool(value) => Some(value),
+      _ => None,
+    }
+  }
+
+  /// Returns the integer if this is an `Int`.
+  pub fn as_i64(&self) -> Option<i64> {
+    match *self {
+      Value::Int(value) => Some(value),
+      _ => None,
+    }
+  }
+
+  /// Returns the number as a float if this is an `Int` or a `Float`.
+  pub fn as_f64(&self) -> Option<f64> {
+    match *self {
+      Value::Int(value) => Some(value as f64),
+      Value::Float(value) => Some(value),
+      _ => None,
+    }
+  }
+
+  /// Returns the string slice if this is a `String`.
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Value::String(value) => Some(value),
+      _ => None,
+    }
+  }
+
+  /// Returns the elements if this is a `List`.
+  pub fn as_list(&self) -> Option<&[Value]> {
+    match self {
+      Value::List(values) => Some(values),
+      _ => None,
+    }
+  }
+
+  /// Returns the nested map if this is a `Map`.
+  pub fn as_map(&self) -> Option<&Metadata> {
+    match self {
+      Value::Map(map) => Some(map),
+      _ => None,
+    }
+  }
+
+  /// Returns `true` if this is `Null`.
+  pub fn is_null(&self) -> bool {
+    matches!(self, Value::Null)
+  }
+}
+
+impl From<bool> for Value {
+  fn from(value: bool) -> Self {
+    Value::Bool(value)
+  }
+}
+
+impl From<i32> for Value {
+  fn from(value: i32) -> Self {
+    Value::Int(value.into())
+  }
+}
+
+impl From<i64> for Value {
+  fn from(value: i64) -> Self {
+    Value::Int(value)
+  }
+}
+
+impl From<f64> for Value {
+  fn from(value: f64) -> Self {
+    Value::Float(value)
+  }
+}
+
+impl From<&str> for Value {
+  fn from(value: &str) -> Self {
+    Value::String(value.to_string())
+  }
+}
+
+impl From<String> for Value {
+  fn from(value: String) -> Self {
+    Value::String(value)
+  }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+  fn from(values: Vec<T>) -> Self {
+    Value::List(values.into_iter().map(Into::into).collect())
+  }
+}
+
+impl From<Metadata> for Value {
+  fn from(map: Metadata) -> Self {
+    Value::Map(map)
+  }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+  fn from(value: Option<T>) -> Self {
+    value.map_or(Value::Null, Into::into)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_conversions() {
+    assert_eq!(Value::from(true).as_bool(), Some(true));
+    assert_eq!(Value::from(3).as_i64(), Some(3));
+    assert_eq!(Value::from(3).as_f64(), Some(3.0));
+    assert_eq!(Value::from(1.5).as_f64(), Some(1.5));
+    assert_eq!(Value::from(1.5).as_i64(), None);
+    assert_eq!(Value::from("a").as_str(), Some("a"));
+    assert_eq!(
+      Value::from(vec![1, 2]).as_list(),
+      Some(&[Value::Int(1), Value::Int(2)][..])
+    );
+    assert!(Value::from(None::<i64>).is_null());
+  }
+
+  #[test]
+  fn test_nested_map() {
+    let mut inner = Metadata::new();
+    inner.insert("url".to_string(), Value::from("https://example.com"));
+    let value = Value::from(inner.clone());
+
+    assert_eq!(value.as_map(), Some(&inner));
+    assert_eq!(value.as_str(), None);
+  }
+}
@@ -0,0 +1,503 @@
+use crate::distance::{Metric, MetricKind, Order};
+use crate::element::Element;
+use crate::vector_database::VectorId;
+use ndarray::{
+  Array1, Array2, ArrayView1, ArrayView2, Axis, CowArray, Ix2, Slice,
+};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Scores `rows` against `query` with `metric` and returns the best `k` as
+/// `(id, score)` pairs, from best to worst.
+pub(crate) fn top_k<'a, T: Element>(
+  metric: &impl Metric,
+  query: ArrayView1<T>,
+  k: usize,
+  rows: impl Iterator<Item = (VectorId, ArrayView1<'a, T>)>,
+) -> Vec<(VectorId, f64)> {
+  if k == 0 {
+    return Vec::new();
+  }
+  let mut top_k = TopK::new(k, metric.order());
+  for (id, row) in rows {
+    top_k.push(id, metric.score(&row, &query));
+  }
+  top_k.into_sorted_vec()
+}
+
+/// The size in bytes of the blocks of rows scored at a time by [`scan`], small
+/// enough for a block to stay in the L1 cache while it is scored.
+const BLOCK_BYTES: usize = 32 * 1024;
+
+/// Scores every row of `rows` against `query` with `metric` and returns the
+/// best `k` as `(id, score)` pairs, from best to worst. `id` gives the ID of
+/// the row at a position.
+///
+/// Rows are read in order a block at a time, and the scores of a block are
+/// computed before any of them is offered to the heap, which keeps the
+/// scoring loop free of branches on the heap.
+///
+/// With the `rayon` feature, rows spanning more than one block are scanned in
+/// parallel on the current thread pool: every thread keeps its own heap of
+/// the best `k` rows of the blocks it scored, and the heaps are merged at the
+/// end. The result is the same as that of a sequential scan.
+pub(crate) fn scan<T: Element>(
+  metric: &(impl Metric + Sync),
+  query: ArrayView1<T>,
+  k: usize,
+  rows: ArrayView2<T>,
+  id: impl Fn(usize) -> VectorId + Sync,
+) -> Vec<(VectorId, f64)> {
+  if k == 0 {
+    return Vec::new();
+  }
+  let order = metric.order();
+  scan_into(metric, query, rows, id, || TopK::new(k, order))
+}
+
+/// Scores every row of `rows` against `query` with `metric` like [`scan`],
+/// and returns those whose score is within `radius` of it, from best to
+/// worst, keeping only the best `limit` if there is a limit.
+///
+/// For similarities, a score is within the radius if it is at least
+/// `radius`.
+pub(crate) fn scan_within<T: Element>(
+  metric: &(impl Metric + Sync),
+  query: ArrayView1<T>,
+  radius: f64,
+  limit: Option<usize>,
+  rows: ArrayView2<T>,
+  id: impl Fn(usize) -> VectorId + Sync,
+) -> Vec<(VectorId, f64)> {
+  if limit == Some(0) {
+    return Vec::new();
+  }
+  let order = metric.order();
+  scan_into(metric, query, rows, id, || {
+    TopK::within(limit, order, radius)
+  })
+}
+
+/// The scan of [`scan`] and [`scan_within`], offering the scores to heaps
+/// made by `top_k`.
+fn scan_into<T: Element>(
+  metric: &(impl Metric + Sync),
+  query: ArrayView1<T>,
+  rows: ArrayView2<T>,
+  id: impl Fn(usize) -> VectorId + Sync,
+  top_k: impl Fn() -> TopK + Sync,
+) -> Vec<(VectorId, f64)> {
+  let row_bytes = rows.ncols().max(1) * std::mem::size_of::<T>();
+  let block_rows = (BLOCK_BYTES / row_bytes).max(1);
+  #[cfg(feature = "rayon")]
+  if rows.nrows() > block_rows {
+    return par_scan(metric, query, rows, id, top_k, block_rows);
+  }
+  let mut top_k = top_k();
+  let mut scores = Vec::with_capacity(block_rows.min(rows.nrows()));
+  for (i, block) in rows.axis_chunks_iter(Axis(0), block_rows).enumerate() {
+    let start = i * block_rows;
+    score_block(metric, &query, block, start, &id, &mut scores, &mut top_k);
+  }
+  top_k.into_sorted_vec()
+}
+
+/// The parallel part of [`scan_into`], splitting `rows` into blocks of
+/// `block_rows` rows.
+#[cfg(feature = "rayon")]
+fn par_scan<T: Element>(
+  metric: &(impl Metric + Sync),
+  query: ArrayView1<T>,
+  rows: ArrayView2<T>,
+  id: impl Fn(usize) -> VectorId + Sync,
+  top_k: impl Fn() -> TopK + Sync,
+  block_rows: usize,
+) -> Vec<(VectorId, f64)> {
+  use rayon::prelude::*;
+
+  let blocks = rows.nrows().div_ceil(block_rows);
+  (0..blocks)
+    .into_par_iter()
+    .fold(
+      || (top_k(), Vec::with_capacity(block_rows)),
+      |(mut top_k, mut scores), i| {
+        let start = i * block_rows;
+        let end = (start + block_rows).min(rows.nrows());
+        let block = rows.slice_axis(Axis(0), Slice::from(start..end));
+        score_block(metric, &query, block, start, &id, &mut scores, &mut top_k);
+        (top_k, scores)
+      },
+    )
+    .map(|(top_k, _)| top_k)
+    .reduce(&top_k, TopK::merge)
+    .into_sorted_vec()
+}
+
+/// Scores the rows of `block`, the first of which is at position `start`,
+/// into `scores`, and then offers them to `top_k`.
+fn score_block<T: Element>(
+  metric: &impl Metric,
+  query: &ArrayView1<T>,
+  block: ArrayView2<T>,
+  start: usize,
+  id: impl Fn(usize) -> VectorId,
+  scores: &mut Vec<f64>,
+  top_k: &mut TopK,
+) {
+  scores.clear();
+  scores.extend(block.outer_iter().map(|row| metric.score(&row, query)));
+  for (offset, &score) in scores.iter().enumerate() {
+    top_k.push(id(start + offset), score);
+  }
+}
+
+/// The number of queries scored together by [`scan_batch`].
+const QUERY_BLOCK: usize = 128;
+
+/// The number of rows scored together by [`scan_batch`], enough for the
+/// matrix product of a block of queries and a block of rows to make good use
+/// of the cache.
+const ROW_BLOCK: usize = 1024;
+
+/// Scores every row of `rows` against each row of `queries` with `metric`
+/// and returns the best `k` for each query, as [`scan`] does.
+///
+/// For the Euclidean distance, the inner product and the cosine similarity,
+/// the inner products of a block of queries with a block of rows are computed
+/// as one matrix product, and the Euclidean distance is derived from them as
+/// `sqrt(|q|² + |x|² - 2 q·x)`. Because of that, scores may differ from
+/// those of [`scan`] by rounding errors, which for the Euclidean distance are
+/// relative to the norms of the vectors rather than to the distance. Other
+/// metrics scan the rows once per query.
+///
+/// With the `rayon` feature, blocks of queries are scored in parallel on the
+/// current thread pool.
+pub(crate) fn scan_batch<T: Element>(
+  metric: MetricKind,
+  queries: ArrayView2<T>,
+  k: usize,
+  rows: ArrayView2<T>,
+  id: impl Fn(usize) -> VectorId + Sync,
+) -> Vec<Vec<(VectorId, f64)>> {
+  let score_block = |i: usize| {
+    let start = i * QUERY_BLOCK;
+    let end = (start + QUERY_BLOCK).min(queries.nrows());
+    let queries = queries.slice_axis(Axis(0), Slice::from(start..end));
+    match metric {
+      MetricKind::Euclidean | MetricKind::Cosine | MetricKind::DotProduct => {
+        product_scan(metric, queries, k, rows, &id)
+      }
+      _ => queries
+        .outer_iter()
+        .map(|query| scan(&metric, query, k, rows, &id))
+        .collect(),
+    }
+  };
+  let blocks = queries.nrows().div_ceil(QUERY_BLOCK);
+  #[cfg(feature = "rayon")]
+  let results: Vec<Vec<_>> = {
+    use rayon::prelude::*;
+    (0..blocks).into_par_iter().map(score_block).collect()
+  };
+  #[cfg(not(feature = "rayon"))]
+  let results: Vec<Vec<_>> = (0..blocks).map(score_block).collect();
+  results.into_iter().flatten().collect()
+}
+
+/// The part of [`scan_batch`] computing scores from matrix products.
+fn product_scan<T: Element>(
+  metric: MetricKind,
+  queries: ArrayView2<T>,
+  k: usize,
+  rows: ArrayView2<T>,
+  id: impl Fn(usize) -> VectorId,
+) -> Vec<Vec<(VectorId, f64)>> {
+  let queries = widen_rows(queries);
+  let query_norms = squared_norms(&queries);
+  let mut top_ks: Vec<_> = (0..queries.nrows())
+    .map(|_| TopK::new(k, metric.order()))
+    .collect();
+  if k == 0 {
+    return top_ks.into_iter().map(TopK::into_sorted_vec).collect();
+  }
+  for (i, block) in rows.axis_chunks_iter(Axis(0), ROW_BLOCK).enumerate() {
+    let block = widen_rows(block);
+    let row_norms = squared_norms(&block);
+    let products = queries.dot(&block.t());
+    let start = i * ROW_BLOCK;
+    for ((top_k, products), &query_norm) in top_ks
+      .iter_mut()
+      .zip(products.outer_iter())
+      .zip(&query_norms)
+    {
+      for (offset, (&product, &row_norm)) in
+        products.iter().zip(&row_norms).enumerate()
+      {
+        let score = match metric {
+          MetricKind::Euclidean => {
+            (query_norm + row_norm - 2.0 * product).max(0.0).sqrt()
+          }
+          MetricKind::Cosine => {
+            let norms = query_norm.sqrt() * row_norm.sqrt();
+            if norms == 0.0 {
+              0.0
+            } else {
+              product / norms
+            }
+          }
+          _ => product,
+        };
+        top_k.push(id(start + offset), score);
+      }
+    }
+  }
+  top_ks.into_iter().map(TopK::into_sorted_vec).collect()
+}
+
+/// Returns `rows` with `f64` components, without copying them if they
+/// already are and are contiguous.
+fn widen_rows<T: Element>(rows: ArrayView2<'_, T>) -> CowArray<'_, f64, Ix2> {
+  let shape = rows.raw_dim();
+  match rows.to_slice().map(T::widen) {
+    Some(Cow::Borrowed(values)) => ArrayView2::from_shape(shape, values)
+      .expect("the rows are in standard layout")
+      .into(),
+    Some(Cow::Owned(values)) => Array2::from_shape_vec(shape, values)
+      .expect("the rows are in standard layout")
+      .into(),
+    None => rows.mapv(T::to_f64).into(),
+  }
+}
+
+/// Returns the squared norm of every row of `rows`.
+fn squared_norms(rows: &CowArray<f64, Ix2>) -> Array1<f64> {
+  rows.map_axis(Axis(1), |row| row.dot(&row))
+}
+
+/// Compares two keys where smaller means closer, putting `NaN` last.
+pub(crate) fn cmp_keys(a: f64, b: f64) -> Ordering {
+  match (a.is_nan(), b.is_nan()) {
+    (false, false) => a.total_cmp(&b),
+    (a, b) => a.cmp(&b),
+  }
+}
+
+/// A scored vector kept in a [`TopK`] heap.
+///
+/// The ordering puts closer vectors first, `NaN` scores last and breaks ties
+/// by ID, so the heap's maximum is always the worst candidate.
+struct Candidate {
+  /// The score as reported by the metric.
+  score: f64,
+  /// The score oriented so that smaller always means closer.
+  key: f64,
+  id: VectorId,
+}
+
+impl Ord for Candidate {
+  fn cmp(&self, other: &Self) -> Ordering {
+    cmp_keys(self.key, other.key).then_with(|| self.id.cmp(&other.id))
+  }
+}
+
+impl PartialOrd for Candidate {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for Candidate {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Candidate {}
+
+/// Collects the `k` best scored vectors in a bounded heap.
+///
+/// Equal scores are ordered by ascending ID and `NaN` scores rank after any
+/// other score.
+pub(crate) struct TopK {
+  k: usize,
+  order: Order,
+  /// The key of the farthest score to keep, if scores are bounded.
+  radius: Option<f64>,
+  heap: BinaryHeap<Candidate>,
+}
+
+impl TopK {
+  pub(crate) fn new(k: usize, order: Order) -> Self {
+    Self {
+      k,
+      order,
+      radius: None,
+      heap: BinaryHeap::with_capacity(k.saturating_add(1).min(1024)),
+    }
+  }
+
+  /// Creates a heap keeping only scores within `radius`, as many as `limit`
+  /// if there is a limit. A `NaN` score is never within the radius.
+  pub(crate) fn within(
+    limit: Option<usize>,
+    order: Order,
+    radius: f64,
+  ) -> Self {
+    Self {
+      radius: Some(order.key(radius)),
+      ..Self::new(limit.unwrap_or(usize::MAX), order)
+    }
+  }
+
+  /// Offers a vector with the given score, keeping it if it ranks among the
+  /// best `k` seen so far.
+  pub(crate) fn push(&mut self, id: VectorId, score: f64) {
+    let candidate = Candidate {
+      score,
+      key: self.order.key(score),
+      id,
+    };
+    self.push_candidate(candidate);
+  }
+
+  fn push_candidate(&mut self, candidate: Candidate) {
+    let outside = |radius| cmp_keys(candidate.key, radius) == Ordering::Greater;
+    if self.k == 0 || self.radius.is_some_and(outside) {
+      return;
+    }
+    if self.heap.len() < self.k {
+      self.heap.push(candidate);
+    } else if let Some(mut worst) = self.heap.peek_mut() {
+      if candidate < *worst {
+        *worst = candidate;
+      }
+    }
+  }
+
+  /// Combines two heaps of the same size and order into one keeping the best
+  /// `k` vectors of both.
+  #[cfg(feature = "rayon")]
+  fn merge(mut self, other: Self) -> Self {
+    if self.heap.len() < other.heap.len() {
+      return other.merge(self);
+    }
+    for candidate in other.heap {
+      self.push_candidate(candidate);
+    }
+    self
+  }
+
+  /// Returns the kept `(id, score)` pairs from best to worst.
+  pub(crate) fn into_sorted_vec(self) -> Vec<(VectorId, f64)> {
+    self
+      .heap
+      .into_sorted_vec()
+      .into_iter()
+      .map(|c| (c.id, c.score))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_merge_ranks_ties_and_nan_like_push() {
+    let heap = |k, order, scores: &[(VectorId, f64)]| {
+      let mut top_k = TopK::new(k, order);
+      for &(id, score) in scores {
+        top_k.push(id, score);
+      }
+      top_k
+    };
+    let order = Order::SmallerIsBetter;
+    let a = [(5, 1.0), (1, f64::NAN), (3, 2.0)];
+    let b = [(4, 1.0), (2, 2.0), (0, f64::NAN), (6, 0.5)];
+    for (first, second) in [(&a[..], &b[..]), (&b[..], &a[..])] {
+      let merged = heap(4, order, first).merge(heap(4, order, second));
+      assert_eq!(
+        merged.into_sorted_vec(),
+        [(6, 0.5), (4, 1.0), (5, 1.0), (2, 2.0)]
+      );
+    }
+
+    // NaN scores are only kept when there are too few other scores.
+    let nan = heap(3, order, &[(1, f64::NAN)]);
+    let merged = nan.merge(heap(3, order, &[(0, f64::NAN), (2, 3.0)]));
+    let merged = merged.into_sorted_vec();
+    assert_eq!(
+      merged.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+      [2, 0, 1]
+    );
+    assert!(merged[1].1.is_nan() && merged[2].1.is_nan());
+
+    let order = Order::LargerIsBetter;
+    let small = heap(3, order, &[(3, 0.9), (1, 0.5)]);
+    let merged =
+      small.merge(heap(3, order, &[(2, 0.9), (0, f64::NAN), (4, 0.7)]));
+    assert_eq!(merged.into_sorted_vec(), [(2, 0.9), (3, 0.9), (4, 0.7)]);
+  }
+
+  #[test]
+  fn test_scan_batch_crosses_query_and_row_blocks() {
+    // Small integer components make every score exact, and repeat the same
+    // vectors so that ties span the blocks.
+    let grid = |len: usize, seed: usize| {
+      Array2::from_shape_fn((len, 3), |(i, j)| ((i * (j + seed)) % 5) as f64)
+    };
+    let rows = grid(2 * ROW_BLOCK + 1, 1);
+    let queries = grid(2 * QUERY_BLOCK + 1, 2);
+    let id = |i: usize| 3 * i as VectorId;
+    for metric in [
+      MetricKind::Euclidean,
+      MetricKind::DotProduct,
+      MetricKind::Manhattan,
+    ] {
+      for k in [0, 20] {
+        let batch = scan_batch(metric, queries.view(), k, rows.view(), id);
+        assert_eq!(batch.len(), queries.nrows());
+        for (query, neighbors) in queries.outer_iter().zip(batch) {
+          assert_eq!(neighbors, scan(&metric, query, k, rows.view(), id));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_scan_within_similarity_radius() {
+    // Enough rows for several scan blocks, and the parallel scan with rayon.
+    let mut rows =
+      Array2::from_shape_fn((5000, 2), |(i, j)| [(i % 10) as f64, 1.0][j]);
+    rows[[4999, 0]] = f64::NAN;
+    let query = ndarray::arr1(&[1.0, 0.0]);
+    let id = |i: usize| i as VectorId;
+
+    let metric = MetricKind::DotProduct;
+    assert_eq!(metric.order(), Order::LargerIsBetter);
+    let within = scan_within(&metric, query.view(), 7.0, None, rows.view(), id);
+    assert_eq!(within.len(), 1499);
+    assert_eq!(within[..3], [(9, 9.0), (19, 9.0), (29, 9.0)]);
+    assert_eq!(within[1498], (4997, 7.0));
+
+    for (metric, radius) in [
+      (MetricKind::DotProduct, 7.0),
+      (MetricKind::Cosine, 0.99),
+      (MetricKind::Cosine, -1.0),
+    ] {
+      let everything = scan(&metric, query.view(), 5000, rows.view(), id);
+      let expected: Vec<_> = everything
+        .into_iter()
+        .take_while(|&(_, score)| score >= radius)
+        .collect();
+      for limit in [None, Some(0), Some(5), Some(5000)] {
+        let within =
+          scan_within(&metric, query.view(), radius, limit, rows.view(), id);
+        let len = limit.unwrap_or(usize::MAX).min(expected.len());
+        assert_eq!(within, expected[..len]);
+      }
+    }
+  }
+}
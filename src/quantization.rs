@@ -0,0 +1,351 @@
+//! Product quantization, for storing vectors as compact codes.
+//!
+//! A [`ProductQuantizer`] splits vectors into `num_subspaces` contiguous
+//! chunks and learns a codebook of `codebook_size` centroids for each chunk.
+//! A vector is then encoded as the index of the nearest centroid in each
+//! codebook, one byte per chunk, so a 768-dimensional `f64` vector split into
+//! 96 chunks shrinks from 6 KB to 96 bytes.
+//!
+//! Distances between a query and encoded vectors are computed asymmetrically:
+//! the query is kept exact, and a [`DistanceTable`] holding the partial
+//! distance between each query chunk and each centroid turns scoring a code
+//! into `num_subspaces` table lookups.
+
+use crate::distance::{Metric, MetricKind};
+use crate::error::{Error, Result};
+use crate::kmeans::{kmeans, nearest_centroid};
+use crate::random::Rng;
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2};
+
+/// The parameters of a [`ProductQuantizer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PqConfig {
+  /// The number of chunks vectors are split into, which is also the size of
+  /// a code in bytes. It must divide the dimension.
+  pub num_subspaces: usize,
+  /// The number of centroids per chunk, at most 256.
+  pub codebook_size: usize,
+  /// The maximum number of k-means iterations run to train each codebook.
+  pub max_iterations: usize,
+  /// The seed used to initialize the k-means centroids.
+  pub seed: u64,
+}
+
+impl Default for PqConfig {
+  fn default() -> Self {
+    Self {
+      num_subspaces: 8,
+      codebook_size: 256,
+      max_iterations: 25,
+      seed: 42,
+    }
+  }
+}
+
+/// Encodes vectors into compact codes. See the [module documentation](self).
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::MetricKind;
+/// use rustyvectors::quantization::{PqConfig, ProductQuantizer};
+/// use ndarray::{arr1, Array2};
+///
+/// let samples = Array2::from_shape_fn((100, 4), |(i, j)| (i * j % 7) as f64);
+/// let config = PqConfig { num_subspaces: 2, codebook_size: 16, ..PqConfig::default() };
+/// let pq = ProductQuantizer::train(samples.view(), config)?;
+///
+/// let vector = arr1(&[0.0, 3.0, 6.0, 2.0]);
+/// let codes = pq.encode(vector.view())?;
+/// assert_eq!(codes.len(), 2);
+/// let table = pq.distance_table(vector.view(), MetricKind::Euclidean)?;
+/// let approximate_distance = table.score(&codes);
+/// # Ok::<(), rustyvectors::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProductQuantizer {
+  dimension: usize,
+  /// One `codebook_size × subspace_dimension` matrix per chunk.
+  codebooks: Vec<Array2<f64>>,
+}
+
+impl ProductQuantizer {
+  /// Learns the codebooks from the rows of `samples`.
+  ///
+  /// Returns [`Error::InvalidValue`] if `num_subspaces` does not divide the
+  /// dimension, if `codebook_size` is not between 1 and 256 or if there are
+  /// fewer samples than `codebook_size`.
+  pub fn train(samples: ArrayView2<f64>, config: PqConfig) -> Result<Self> {
+    let dimension = samples.ncols();
+    if config.num_subspaces == 0
+      || !dimension.is_multiple_of(config.num_subspaces)
+    {
+      return Err(Error::InvalidValue(format!(
+        "the number of subspaces must divide the dimension {dimension}, got {}",
+        config.num_subspaces
+      )));
+    }
+    if !(1..=256).contains(&config.codebook_size) {
+      return Err(Error::InvalidValue(format!(
+        "codebook size must be between 1 and 256, got {}",
+        config.codebook_size
+      )));
+    }
+    if samples.nrows() < config.codebook_size {
+      return Err(Error::InvalidValue(format!(
+        "product quantization training needs at least {} vectors, got {}",
+        config.codebook_size,
+        samples.nrows()
+      )));
+    }
+
+    let mut rng = Rng::new(config.seed);
+    let width = dimension / config.num_subspaces;
+    let codebooks = (0..config.num_subspaces)
+      .map(|subspace| {
+        let chunk =
+          samples.slice(s![.., subspace * width..(subspace + 1) * width]);
+        kmeans(
+          chunk,
+          config.codebook_size,
+          MetricKind::Euclidean,
+          config.max_iterations,
+          &mut rng,
+        )
+      })
+      .collect();
+    Ok(Self {
+      dimension,
+      codebooks,
+    })
+  }
+
+  /// Returns the number of components of the encoded vectors.
+  pub fn dimension(&self) -> usize {
+    self.dimension
+  }
+
+  /// Returns the size of a code in bytes.
+  pub fn code_size(&self) -> usize {
+    self.codebooks.len()
+  }
+
+  fn subspace_width(&self) -> usize {
+    self.dimension / self.codebooks.len()
+  }
+
+  fn check_dimension(&self, vector: &ArrayView1<f64>) -> Result<()> {
+    crate::index::check_dimension(vector, self.dimension)
+  }
+
+  /// Encodes `vector` as the index of its nearest centroid in each codebook.
+  pub fn encode(&self, vector: ArrayView1<f64>) -> Result<Vec<u8>> {
+    self.check_dimension(&vector)?;
+    let width = self.subspace_width();
+    Ok(
+      self
+        .codebooks
+        .iter()
+        .enumerate()
+        .map(|(subspace, codebook)| {
+          let chunk =
+            vector.slice(s![subspace * width..(subspace + 1) * width]);
+          nearest_centroid(codebook.view(), chunk, MetricKind::Euclidean) as u8
+        })
+        .collect(),
+    )
+  }
+
+  /// Rebuilds the approximate vector represented by `codes`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `codes` was not produced by this quantizer.
+  pub fn decode(&self, codes: &[u8]) -> Array1<f64> {
+    assert_eq!(codes.len(), self.code_size(), "code size mismatch");
+    let width = self.subspace_width();
+    let mut vector = Array1::zeros(self.dimension);
+    for (subspace, (&code, codebook)) in
+      codes.iter().zip(&self.codebooks).enumerate()
+    {
+      vector
+        .slice_mut(s![subspace * width..(subspace + 1) * width])
+        .assign(&codebook.row(code.into()));
+    }
+    vector
+  }
+
+  /// Precomputes the partial scores between `query` and every centroid, for
+  /// scoring codes with `metric`.
+  ///
+  /// Every built-in metric decomposes over chunks except the cosine
+  /// similarity, which is computed as the dot product of the normalized
+  /// query with the codes; vectors must therefore be normalized before being
+  /// encoded for [`MetricKind::Cosine`].
+  pub fn distance_table(
+    &self,
+    query: ArrayView1<f64>,
+    metric: MetricKind,
+  ) -> Result<DistanceTable> {
+    self.check_dimension(&query)?;
+    let query = match metric {
+      MetricKind::Cosine => normalized(query),
+      _ => query.to_owned(),
+    };
+    let width = self.subspace_width();
+    let codebook_size = self.codebooks[0].nrows();
+    let mut table = Array2::zeros((self.code_size(), codebook_size));
+    for (subspace, codebook) in self.codebooks.iter().enumerate() {
+      let chunk = query.slice(s![subspace * width..(subspace + 1) * width]);
+      for (code, centroid) in codebook.outer_iter().enumerate() {
+        table[[subspace, code]] = partial_score(metric, &chunk, &centroid);
+      }
+    }
+    Ok(DistanceTable { metric, table })
+  }
+}
+
+/// The partial scores between a query and the centroids of a
+/// [`ProductQuantizer`], for scoring codes with table lookups.
+#[derive(Debug, Clone)]
+pub struct DistanceTable {
+  metric: MetricKind,
+  /// The partial score of each chunk (rows) against each centroid (columns).
+  table: Array2<f64>,
+}
+
+impl DistanceTable {
+  /// Returns the approximate score between the query and the vector encoded
+  /// as `codes`.
+  pub fn score(&self, codes: &[u8]) -> f64 {
+    let partials = codes
+      .iter()
+      .enumerate()
+      .map(|(subspace, &code)| self.table[[subspace, code.into()]]);
+    match self.metric {
+      MetricKind::Euclidean => partials.sum::<f64>().sqrt(),
+      MetricKind::Chebyshev => partials.fold(0.0, f64::max),
+      MetricKind::Minkowski(p) if p.is_infinite() => {
+        partials.fold(0.0, f64::max)
+      }
+      MetricKind::Minkowski(p) => partials.sum::<f64>().powf(p.recip()),
+      MetricKind::Cosine | MetricKind::DotProduct | MetricKind::Manhattan => {
+        partials.sum()
+      }
+    }
+  }
+}
+
+/// Returns the contribution of one chunk to the score of a whole vector,
+/// before [`DistanceTable::score`] combines them.
+fn partial_score(
+  metric: MetricKind,
+  a: &ArrayView1<f64>,
+  b: &ArrayView1<f64>,
+) -> f64 {
+  match metric {
+    MetricKind::Euclidean => {
+      a.iter().zip(b).map(|(&x, &y)| (x - y).powi(2)).sum()
+    }
+    MetricKind::Minkowski(p) if !p.is_infinite() => {
+      a.iter().zip(b).map(|(&x, &y)| (x - y).abs().powf(p)).sum()
+    }
+    MetricKind::Minkowski(_) => MetricKind::Chebyshev.score(a, b),
+    MetricKind::Cosine => MetricKind::DotProduct.score(a, b),
+    _ => metric.score(a, b),
+  }
+}
+
+/// Returns `vector` scaled to unit norm, or unchanged if its norm is zero.
+pub(crate) fn normalized(vector: ArrayView1<f64>) -> Array1<f64> {
+  let norm = vector.dot(&vector).sqrt();
+  if norm > 0.0 {
+    &vector / norm
+  } else {
+    vector.to_owned()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn samples() -> Array2<f64> {
+    let mut rng = Rng::new(5);
+    Array2::from_shape_fn((500, 8), |(i, j)| {
+      ((i % 10) * (j + 1)) as f64 + rng.next_f64() * 0.1
+    })
+  }
+
+  fn config() -> PqConfig {
+    PqConfig {
+      num_subspaces: 4,
+      codebook_size: 16,
+      ..PqConfig::default()
+    }
+  }
+
+  #[test]
+  fn test_encode_decode_round_trip() {
+    let samples = samples();
+    let pq = ProductQuantizer::train(samples.view(), config()).unwrap();
+    assert_eq!(pq.code_size(), 4);
+
+    for row in samples.outer_iter().take(20) {
+      let decoded = pq.decode(&pq.encode(row).unwrap());
+      let error = crate::distance::euclidean(&row, &decoded.view());
+      assert!(error < 0.2, "reconstruction error {error}");
+    }
+  }
+
+  #[test]
+  fn test_asymmetric_scores_approximate_exact_ones() {
+    let samples = samples();
+    let pq = ProductQuantizer::train(samples.view(), config()).unwrap();
+    let query = samples.row(3).to_owned() + 0.5;
+
+    for metric in [
+      MetricKind::Euclidean,
+      MetricKind::Manhattan,
+      MetricKind::Chebyshev,
+      MetricKind::DotProduct,
+      MetricKind::Minkowski(3.0),
+    ] {
+      let table = pq.distance_table(query.view(), metric).unwrap();
+      for row in samples.outer_iter().take(20) {
+        let codes = pq.encode(row).unwrap();
+        let decoded = pq.decode(&codes);
+        let expected = metric.score(&query.view(), &decoded.view());
+        assert!((table.score(&codes) - expected).abs() < 1e-9, "{metric:?}");
+      }
+    }
+  }
+
+  #[test]
+  fn test_invalid_configuration() {
+    let samples = samples();
+    let uneven = PqConfig {
+      num_subspaces: 3,
+      ..config()
+    };
+    let too_large = PqConfig {
+      codebook_size: 300,
+      ..config()
+    };
+    let too_few_samples = PqConfig {
+      codebook_size: 256,
+      ..config()
+    };
+    for config in [uneven, too_large] {
+      assert!(matches!(
+        ProductQuantizer::train(samples.view(), config),
+        Err(Error::InvalidValue(_))
+      ));
+    }
+    assert!(matches!(
+      ProductQuantizer::train(samples.slice(s![..100, ..]), too_few_samples),
+      Err(Error::InvalidValue(_))
+    ));
+  }
+}
@@ -0,0 +1,32 @@
+/// A small SplitMix64 pseudo-random generator.
+///
+/// The randomized algorithms of this crate only need reproducible, seedable
+/// randomness, not cryptographic quality, so this avoids a dependency.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub(crate) fn new(seed: u64) -> Self {
+    Self { state: seed }
+  }
+
+  pub(crate) fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  /// Returns a float uniformly distributed in `[0, 1)`.
+  pub(crate) fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// Returns an integer uniformly distributed in `[0, n)`.
+  pub(crate) fn below(&mut self, n: usize) -> usize {
+    ((self.next_f64() * n as f64) as usize).min(n.saturating_sub(1))
+  }
+}
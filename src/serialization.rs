@@ -0,0 +1,358 @@
+//! Serde support for [`VectorDatabase`] and metadata [`Value`]s, with the
+//! `serde` feature.
+//!
+//! A database is serialized as a struct holding its dimension, metric, next
+//! ID, the names of its indexed metadata fields and its records by ascending
+//! ID, each with its ID, vector components and metadata. The attached
+//! approximate nearest neighbor index is not serialized, and must be attached
+//! again after deserializing.
+//!
+//! In human-readable formats such as JSON, values are written as the plain
+//! value they hold, so that metadata reads like the JSON object it came from.
+//! Other formats, such as bincode, cannot tell the type of a value from its
+//! encoding, so values are written as an enum variant there.
+
+use crate::distance::MetricKind;
+use crate::element::Element;
+use crate::metadata::{Metadata, Value};
+use crate::vector_database::{VectorDatabase, VectorId};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// The serialized form of a database, borrowing its contents.
+#[derive(Serialize)]
+#[serde(rename = "VectorDatabase")]
+struct DatabaseRef<'a, T> {
+  dimension: usize,
+  metric: MetricKind,
+  next_id: VectorId,
+  indexes: Vec<&'a str>,
+  records: Vec<RecordRef<'a, T>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Record")]
+struct RecordRef<'a, T> {
+  id: VectorId,
+  vector: &'a [T],
+  metadata: &'a Metadata,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "VectorDatabase")]
+struct Database<T> {
+  dimension: usize,
+  metric: MetricKind,
+  next_id: VectorId,
+  indexes: Vec<String>,
+  records: Vec<Record<T>>,
+}
+
+#[derive(Deserialize)]
+struct Record<T> {
+  id: VectorId,
+  vector: Vec<T>,
+  metadata: Metadata,
+}
+
+impl<T: Element + Serialize> Serialize for VectorDatabase<T> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut records: Vec<_> = self
+      .records()
+      .map(|(id, vector, metadata)| RecordRef {
+        id,
+        vector: vector.to_slice().expect("stored vectors are contiguous"),
+        metadata,
+      })
+      .collect();
+    records.sort_unstable_by_key(|record| record.id);
+    DatabaseRef {
+      dimension: self.dimension(),
+      metric: self.metric(),
+      next_id: self.next_id(),
+      indexes: self.indexed_fields().collect(),
+      records,
+    }
+    .serialize(serializer)
+  }
+}
+
+impl<'de, T: Element + Deserialize<'de>> Deserialize<'de>
+  for VectorDatabase<T>
+{
+  /// Rebuilds a database and its secondary indexes, failing if a record does
+  /// not fit the dimension, or if IDs are repeated or not below the next ID.
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    let data = Database::<T>::deserialize(deserializer)?;
+    let mut db = VectorDatabase::with_element_type(data.dimension, data.metric)
+      .map_err(de::Error::custom)?;
+    for field in &data.indexes {
+      db.create_index(field);
+    }
+    for record in data.records {
+      if record.id >= data.next_id {
+        return Err(de::Error::custom(format!(
+          "id {} is not below the next id {}",
+          record.id, data.next_id
+        )));
+      }
+      db.insert_with_id(record.id, &record.vector, record.metadata)
+        .map_err(de::Error::custom)?;
+    }
+    db.advance_next_id(data.next_id);
+    Ok(db)
+  }
+}
+
+/// The variants of [`Value`], as written by formats that are not
+/// human-readable.
+#[derive(Serialize)]
+#[serde(rename = "Value")]
+enum TaggedRef<'a> {
+  Null,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  String(&'a str),
+  List(&'a [Value]),
+  Map(&'a Metadata),
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Value")]
+enum Tagged {
+  Null,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  String(String),
+  List(Vec<Value>),
+  Map(Metadata),
+}
+
+impl Serialize for Value {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    if !serializer.is_human_readable() {
+      let tagged = match self {
+        Value::Null => TaggedRef::Null,
+        Value::Bool(value) => TaggedRef::Bool(*value),
+        Value::Int(value) => TaggedRef::Int(*value),
+        Value::Float(value) => TaggedRef::Float(*value),
+        Value::String(value) => TaggedRef::String(value),
+        Value::List(values) => TaggedRef::List(values),
+        Value::Map(map) => TaggedRef::Map(map),
+      };
+      return tagged.serialize(serializer);
+    }
+    match self {
+      Value::Null => serializer.serialize_unit(),
+      Value::Bool(value) => serializer.serialize_bool(*value),
+      Value::Int(value) => serializer.serialize_i64(*value),
+      Value::Float(value) => serializer.serialize_f64(*value),
+      Value::String(value) => serializer.serialize_str(value),
+      Value::List(values) => {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+          seq.serialize_element(value)?;
+        }
+        seq.end()
+      }
+      Value::Map(map) => {
+        let mut entries = serializer.serialize_map(Some(map.len()))?;
+        for (field, value) in map {
+          entries.serialize_entry(field, value)?;
+        }
+        entries.end()
+      }
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Value {
+  fn deserialize<D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    if !deserializer.is_human_readable() {
+      return Ok(match Tagged::deserialize(deserializer)? {
+        Tagged::Null => Value::Null,
+        Tagged::Bool(value) => Value::Bool(value),
+        Tagged::Int(value) => Value::Int(value),
+        Tagged::Float(value) => Value::Float(value),
+        Tagged::String(value) => Value::String(value),
+        Tagged::List(values) => Value::List(values),
+        Tagged::Map(map) => Value::Map(map),
+      });
+    }
+    deserializer.deserialize_any(ValueVisitor)
+  }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+  type Value = Value;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a metadata value")
+  }
+
+  fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_some<D: Deserializer<'de>>(
+    self,
+    deserializer: D,
+  ) -> Result<Value, D::Error> {
+    Value::deserialize(deserializer)
+  }
+
+  fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+    Ok(Value::Bool(value))
+  }
+
+  fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+    Ok(Value::Int(value))
+  }
+
+  /// Integers beyond the range of `i64` become floats.
+  fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+    Ok(match i64::try_from(value) {
+      Ok(value) => Value::Int(value),
+      Err(_) => Value::Float(value as f64),
+    })
+  }
+
+  fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+    Ok(Value::Float(value))
+  }
+
+  fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+    Ok(Value::String(value.to_string()))
+  }
+
+  fn visit_string<E: de::Error>(self, value: String) -> Result<Value, E> {
+    Ok(Value::String(value))
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+    let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(value) = seq.next_element()? {
+      values.push(value);
+    }
+    Ok(Value::List(values))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(
+    self,
+    mut entries: A,
+  ) -> Result<Value, A::Error> {
+    let mut map = Metadata::new();
+    while let Some((field, value)) = entries.next_entry()? {
+      map.insert(field, value);
+    }
+    Ok(Value::Map(map))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::element::f16;
+  use crate::vector_database::SearchResult;
+
+  fn sample() -> VectorDatabase {
+    let mut db =
+      VectorDatabase::with_metric(2, MetricKind::Minkowski(3.0)).unwrap();
+    db.create_index("kind");
+    let mut metadata = Metadata::new();
+    metadata.insert("kind".to_string(), Value::from("a"));
+    metadata.insert("score".to_string(), Value::from(1.5));
+    metadata.insert(
+      "tags".to_string(),
+      Value::List(vec![Value::from(1), Value::Null, Value::from(true)]),
+    );
+    db.add_with_metadata(&[1.0, 2.0], metadata).unwrap();
+    let removed = db.add(&[3.0, 4.0]).unwrap();
+    db.add(&[5.0, 6.0]).unwrap();
+    db.remove(removed);
+    db
+  }
+
+  fn assert_same(a: &VectorDatabase, b: &VectorDatabase) {
+    assert_eq!(a.metric(), b.metric());
+    assert_eq!(a.ids().collect::<Vec<_>>(), b.ids().collect::<Vec<_>>());
+    for id in a.ids() {
+      assert_eq!(a.get(id), b.get(id));
+      assert_eq!(a.metadata(id), b.metadata(id));
+    }
+    assert!(b.has_index("kind"));
+  }
+
+  #[test]
+  fn test_json_round_trip() {
+    let db = sample();
+    let json = serde_json::to_value(&db).unwrap();
+    assert_eq!(json["next_id"], 3);
+    assert_eq!(json["metric"], serde_json::json!({"Minkowski": 3.0}));
+    assert_eq!(
+      json["records"][0]["metadata"],
+      serde_json::json!({"kind": "a", "score": 1.5, "tags": [1, null, true]})
+    );
+
+    let mut copy: VectorDatabase = serde_json::from_value(json).unwrap();
+    assert_same(&db, &copy);
+    assert_eq!(copy.add(&[0.0, 0.0]).unwrap(), 3);
+  }
+
+  #[test]
+  fn test_bincode_round_trip() {
+    let db = sample();
+    let bytes = bincode::serialize(&db).unwrap();
+    let copy: VectorDatabase = bincode::deserialize(&bytes).unwrap();
+    assert_same(&db, &copy);
+
+    let results = db.search(&[1.0, 2.0], 2).unwrap();
+    let bytes = bincode::serialize(&results).unwrap();
+    assert_eq!(
+      bincode::deserialize::<Vec<SearchResult>>(&bytes).unwrap(),
+      results
+    );
+
+    let mut db =
+      VectorDatabase::<f16>::with_element_type(2, MetricKind::Cosine).unwrap();
+    db.add(&[f16::from_f32(0.5), f16::from_f32(-2.0)]).unwrap();
+    let bytes = bincode::serialize(&db).unwrap();
+    let copy: VectorDatabase<f16> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(copy.get(0), db.get(0));
+    assert_eq!(copy.metric(), MetricKind::Cosine);
+  }
+
+  #[test]
+  fn test_invalid_databases_are_rejected() {
+    let json = serde_json::json!({
+      "dimension": 2,
+      "metric": "Euclidean",
+      "next_id": 1,
+      "indexes": [],
+      "records": [{"id": 1, "vector": [1.0, 2.0], "metadata": {}}],
+    });
+    assert!(serde_json::from_value::<VectorDatabase>(json).is_err());
+    let json = serde_json::json!({
+      "dimension": 2,
+      "metric": "Euclidean",
+      "next_id": 1,
+      "indexes": [],
+      "records": [{"id": 0, "vector": [1.0], "metadata": {}}],
+    });
+    assert!(serde_json::from_value::<VectorDatabase>(json).is_err());
+  }
+}
@@ -0,0 +1,566 @@
+//! Distance kernels written with explicit SIMD instructions.
+//!
+//! The squared Euclidean distance, the inner product and the terms of the
+//! cosine similarity are computed by kernels compiled for several instruction
+//! sets: SSE2, AVX2 with FMA and AVX-512 on x86-64, chosen at runtime from what
+//! the CPU supports, and NEON on AArch64. Other targets, the `f16` and `bf16`
+//! element types and vectors that are not contiguous in memory use a portable
+//! scalar loop instead.
+//!
+//! `f32` components are widened to `f64` as they are loaded, so every kernel
+//! accumulates in `f64` like the scalar loop. The kernels of an instruction
+//! set add the same terms in the same order for `f32` and `f64`, so that both
+//! give the same score for the same values. They may round differently from
+//! the scalar loop, which adds the terms one at a time.
+
+use crate::element::{bf16, f16, Element};
+use ndarray::ArrayView1;
+use std::sync::OnceLock;
+
+/// Returns the inner product of `a` and `b`.
+pub(crate) fn dot<T: Element>(a: &ArrayView1<T>, b: &ArrayView1<T>) -> f64 {
+  match slices(a, b) {
+    // SAFETY: the kernels are for an instruction set the CPU supports, and
+    // the slices have the same length.
+    Some((a, b)) => unsafe { (T::kernels().dot)(a, b) },
+    None => scalar::dot(a, b),
+  }
+}
+
+/// Returns the squared Euclidean distance between `a` and `b`.
+pub(crate) fn squared_euclidean<T: Element>(
+  a: &ArrayView1<T>,
+  b: &ArrayView1<T>,
+) -> f64 {
+  match slices(a, b) {
+    // SAFETY: as in `dot`.
+    Some((a, b)) => unsafe { (T::kernels().squared_euclidean)(a, b) },
+    None => scalar::squared_euclidean(a, b),
+  }
+}
+
+/// Returns the inner product of `a` and `b`, and the squared norms of `a` and
+/// `b`, in one pass.
+pub(crate) fn cosine<T: Element>(
+  a: &ArrayView1<T>,
+  b: &ArrayView1<T>,
+) -> [f64; 3] {
+  match slices(a, b) {
+    // SAFETY: as in `dot`.
+    Some((a, b)) => unsafe { (T::kernels().cosine)(a, b) },
+    None => scalar::cosine(a, b),
+  }
+}
+
+/// Returns the components of `a` and `b` as slices of the same length, or
+/// `None` if either is not contiguous.
+fn slices<'a, T>(
+  a: &'a ArrayView1<T>,
+  b: &'a ArrayView1<T>,
+) -> Option<(&'a [T], &'a [T])> {
+  let (a, b) = (a.as_slice()?, b.as_slice()?);
+  let len = a.len().min(b.len());
+  Some((&a[..len], &b[..len]))
+}
+
+/// An instruction set the kernels are compiled for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+  Scalar,
+  #[cfg(target_arch = "x86_64")]
+  Sse2,
+  #[cfg(target_arch = "x86_64")]
+  Avx2,
+  #[cfg(target_arch = "x86_64")]
+  Avx512,
+  #[cfg(target_arch = "aarch64")]
+  Neon,
+}
+
+impl Level {
+  /// Returns the instruction sets the running CPU supports, from the slowest
+  /// to the fastest.
+  pub fn supported() -> Vec<Level> {
+    #[allow(unused_mut)]
+    let mut levels = vec![Level::Scalar];
+    #[cfg(target_arch = "x86_64")]
+    {
+      // SSE2 is part of the x86-64 baseline.
+      levels.push(Level::Sse2);
+      if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+        levels.push(Level::Avx2);
+      }
+      if is_x86_feature_detected!("avx512f") {
+        levels.push(Level::Avx512);
+      }
+    }
+    #[cfg(target_arch = "aarch64")]
+    levels.push(Level::Neon);
+    levels
+  }
+
+  /// Returns the fastest instruction set the running CPU supports.
+  fn best() -> Level {
+    *Self::supported()
+      .last()
+      .expect("scalar code is always supported")
+  }
+}
+
+/// The kernels for one element type and instruction set.
+///
+/// The kernels are unsafe to call: the CPU must support their instruction set
+/// and both slices must have the same length.
+#[derive(Clone, Copy)]
+pub struct KernelSet<T> {
+  pub dot: unsafe fn(&[T], &[T]) -> f64,
+  pub squared_euclidean: unsafe fn(&[T], &[T]) -> f64,
+  pub cosine: unsafe fn(&[T], &[T]) -> [f64; 3],
+}
+
+impl<T: Element> KernelSet<T> {
+  fn scalar() -> Self {
+    Self {
+      dot: |a, b| scalar::dot(a, b),
+      squared_euclidean: |a, b| scalar::squared_euclidean(a, b),
+      cosine: |a, b| scalar::cosine(a, b),
+    }
+  }
+}
+
+/// Chooses the kernels of an element type.
+///
+/// Every [`Element`] implements this trait, which is not reachable from
+/// outside the crate.
+pub trait Simd: Sized + 'static {
+  /// Returns the kernels for `level`, which the CPU must support.
+  fn kernels_for(level: Level) -> KernelSet<Self>;
+
+  /// Returns the kernels for the fastest instruction set the CPU supports.
+  fn kernels() -> KernelSet<Self>;
+}
+
+impl Simd for f64 {
+  fn kernels_for(level: Level) -> KernelSet<f64> {
+    match level {
+      Level::Scalar => KernelSet::scalar(),
+      #[cfg(target_arch = "x86_64")]
+      Level::Sse2 => sse2::F64,
+      #[cfg(target_arch = "x86_64")]
+      Level::Avx2 => avx2::F64,
+      #[cfg(target_arch = "x86_64")]
+      Level::Avx512 => avx512::F64,
+      #[cfg(target_arch = "aarch64")]
+      Level::Neon => neon::F64,
+    }
+  }
+
+  fn kernels() -> KernelSet<f64> {
+    static KERNELS: OnceLock<KernelSet<f64>> = OnceLock::new();
+    *KERNELS.get_or_init(|| Self::kernels_for(Level::best()))
+  }
+}
+
+impl Simd for f32 {
+  fn kernels_for(level: Level) -> KernelSet<f32> {
+    match level {
+      Level::Scalar => KernelSet::scalar(),
+      #[cfg(target_arch = "x86_64")]
+      Level::Sse2 => sse2::F32,
+      #[cfg(target_arch = "x86_64")]
+      Level::Avx2 => avx2::F32,
+      #[cfg(target_arch = "x86_64")]
+      Level::Avx512 => avx512::F32,
+      #[cfg(target_arch = "aarch64")]
+      Level::Neon => neon::F32,
+    }
+  }
+
+  fn kernels() -> KernelSet<f32> {
+    static KERNELS: OnceLock<KernelSet<f32>> = OnceLock::new();
+    *KERNELS.get_or_init(|| Self::kernels_for(Level::best()))
+  }
+}
+
+/// Half-precision components have no SIMD kernels, since widening them takes
+/// instructions that not every CPU of an architecture has.
+macro_rules! scalar_only {
+  ($($element:ty),*) => {
+    $(
+      impl Simd for $element {
+        fn kernels_for(_: Level) -> KernelSet<$element> {
+          KernelSet::scalar()
+        }
+
+        fn kernels() -> KernelSet<$element> {
+          KernelSet::scalar()
+        }
+      }
+    )*
+  };
+}
+
+scalar_only!(f16, bf16);
+
+mod scalar {
+  use crate::element::Element;
+
+  pub fn dot<'a, T: Element>(
+    a: impl IntoIterator<Item = &'a T>,
+    b: impl IntoIterator<Item = &'a T>,
+  ) -> f64 {
+    a.into_iter()
+      .zip(b)
+      .map(|(&x, &y)| x.to_f64() * y.to_f64())
+      .sum()
+  }
+
+  pub fn squared_euclidean<'a, T: Element>(
+    a: impl IntoIterator<Item = &'a T>,
+    b: impl IntoIterator<Item = &'a T>,
+  ) -> f64 {
+    a.into_iter()
+      .zip(b)
+      .map(|(&x, &y)| (x.to_f64() - y.to_f64()).powi(2))
+      .sum()
+  }
+
+  pub fn cosine<'a, T: Element>(
+    a: impl IntoIterator<Item = &'a T>,
+    b: impl IntoIterator<Item = &'a T>,
+  ) -> [f64; 3] {
+    a.into_iter()
+      .zip(b)
+      .fold([0.0; 3], |[ab, aa, bb], (&x, &y)| {
+        let (x, y) = (x.to_f64(), y.to_f64());
+        [ab + x * y, aa + x * x, bb + y * y]
+      })
+  }
+}
+
+/// Builds the [`KernelSet`] of an element type for the instruction set
+/// enabled by `$feature`.
+///
+/// The module invoking it provides `LANES`, the number of `f64` lanes of a
+/// vector register, `zero`, `sub`, `fma(a, b, acc)` computing `a * b + acc`
+/// and `sum` over such registers, and `$load`, which loads `LANES` components
+/// of the element type from a pointer into a register. The components left
+/// over after the last whole register are handled by the scalar loop.
+macro_rules! kernels {
+  ($feature:literal, $element:ty, $load:ident) => {{
+    #[target_feature(enable = $feature)]
+    unsafe fn dot(a: &[$element], b: &[$element]) -> f64 {
+      let split = a.len() - a.len() % LANES;
+      let mut acc = zero();
+      for i in (0..split).step_by(LANES) {
+        let (x, y) = ($load(a.as_ptr().add(i)), $load(b.as_ptr().add(i)));
+        acc = fma(x, y, acc);
+      }
+      sum(acc) + scalar::dot(&a[split..], &b[split..])
+    }
+
+    #[target_feature(enable = $feature)]
+    unsafe fn squared_euclidean(a: &[$element], b: &[$element]) -> f64 {
+      let split = a.len() - a.len() % LANES;
+      let mut acc = zero();
+      for i in (0..split).step_by(LANES) {
+        let (x, y) = ($load(a.as_ptr().add(i)), $load(b.as_ptr().add(i)));
+        let difference = sub(x, y);
+        acc = fma(difference, difference, acc);
+      }
+      sum(acc) + scalar::squared_euclidean(&a[split..], &b[split..])
+    }
+
+    #[target_feature(enable = $feature)]
+    unsafe fn cosine(a: &[$element], b: &[$element]) -> [f64; 3] {
+      let split = a.len() - a.len() % LANES;
+      let (mut ab, mut aa, mut bb) = (zero(), zero(), zero());
+      for i in (0..split).step_by(LANES) {
+        let (x, y) = ($load(a.as_ptr().add(i)), $load(b.as_ptr().add(i)));
+        ab = fma(x, y, ab);
+        aa = fma(x, x, aa);
+        bb = fma(y, y, bb);
+      }
+      let [ab_rest, aa_rest, bb_rest] =
+        scalar::cosine(&a[split..], &b[split..]);
+      [sum(ab) + ab_rest, sum(aa) + aa_rest, sum(bb) + bb_rest]
+    }
+
+    KernelSet {
+      dot,
+      squared_euclidean,
+      cosine,
+    }
+  }};
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+  use super::{scalar, KernelSet};
+  use std::arch::x86_64::*;
+
+  const LANES: usize = 2;
+
+  #[inline]
+  #[target_feature(enable = "sse2")]
+  unsafe fn zero() -> __m128d {
+    _mm_setzero_pd()
+  }
+
+  #[inline]
+  #[target_feature(enable = "sse2")]
+  unsafe fn sub(a: __m128d, b: __m128d) -> __m128d {
+    _mm_sub_pd(a, b)
+  }
+
+  /// SSE2 has no fused multiply-add.
+  #[inline]
+  #[target_feature(enable = "sse2")]
+  unsafe fn fma(a: __m128d, b: __m128d, acc: __m128d) -> __m128d {
+    _mm_add_pd(_mm_mul_pd(a, b), acc)
+  }
+
+  #[inline]
+  #[target_feature(enable = "sse2")]
+  unsafe fn sum(v: __m128d) -> f64 {
+    _mm_cvtsd_f64(_mm_add_sd(v, _mm_unpackhi_pd(v, v)))
+  }
+
+  #[inline]
+  #[target_feature(enable = "sse2")]
+  unsafe fn load_f64(p: *const f64) -> __m128d {
+    _mm_loadu_pd(p)
+  }
+
+  /// Loads two `f32` as the low 64 bits of a register and widens them.
+  #[inline]
+  #[target_feature(enable = "sse2")]
+  unsafe fn load_f32(p: *const f32) -> __m128d {
+    _mm_cvtps_pd(_mm_castsi128_ps(_mm_loadl_epi64(p.cast())))
+  }
+
+  pub(super) const F64: KernelSet<f64> = kernels!("sse2", f64, load_f64);
+  pub(super) const F32: KernelSet<f32> = kernels!("sse2", f32, load_f32);
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+  use super::{scalar, KernelSet};
+  use std::arch::x86_64::*;
+
+  const LANES: usize = 4;
+
+  #[inline]
+  #[target_feature(enable = "avx2,fma")]
+  unsafe fn zero() -> __m256d {
+    _mm256_setzero_pd()
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx2,fma")]
+  unsafe fn sub(a: __m256d, b: __m256d) -> __m256d {
+    _mm256_sub_pd(a, b)
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx2,fma")]
+  unsafe fn fma(a: __m256d, b: __m256d, acc: __m256d) -> __m256d {
+    _mm256_fmadd_pd(a, b, acc)
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx2,fma")]
+  unsafe fn sum(v: __m256d) -> f64 {
+    let v = _mm_add_pd(_mm256_castpd256_pd128(v), _mm256_extractf128_pd(v, 1));
+    _mm_cvtsd_f64(_mm_add_sd(v, _mm_unpackhi_pd(v, v)))
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx2,fma")]
+  unsafe fn load_f64(p: *const f64) -> __m256d {
+    _mm256_loadu_pd(p)
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx2,fma")]
+  unsafe fn load_f32(p: *const f32) -> __m256d {
+    _mm256_cvtps_pd(_mm_loadu_ps(p))
+  }
+
+  pub(super) const F64: KernelSet<f64> = kernels!("avx2,fma", f64, load_f64);
+  pub(super) const F32: KernelSet<f32> = kernels!("avx2,fma", f32, load_f32);
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+  use super::{scalar, KernelSet};
+  use std::arch::x86_64::*;
+
+  const LANES: usize = 8;
+
+  #[inline]
+  #[target_feature(enable = "avx512f")]
+  unsafe fn zero() -> __m512d {
+    _mm512_setzero_pd()
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx512f")]
+  unsafe fn sub(a: __m512d, b: __m512d) -> __m512d {
+    _mm512_sub_pd(a, b)
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx512f")]
+  unsafe fn fma(a: __m512d, b: __m512d, acc: __m512d) -> __m512d {
+    _mm512_fmadd_pd(a, b, acc)
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx512f")]
+  unsafe fn sum(v: __m512d) -> f64 {
+    _mm512_reduce_add_pd(v)
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx512f")]
+  unsafe fn load_f64(p: *const f64) -> __m512d {
+    _mm512_loadu_pd(p)
+  }
+
+  #[inline]
+  #[target_feature(enable = "avx512f")]
+  unsafe fn load_f32(p: *const f32) -> __m512d {
+    _mm512_cvtps_pd(_mm256_loadu_ps(p))
+  }
+
+  pub(super) const F64: KernelSet<f64> = kernels!("avx512f", f64, load_f64);
+  pub(super) const F32: KernelSet<f32> = kernels!("avx512f", f32, load_f32);
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+  use super::{scalar, KernelSet};
+  use std::arch::aarch64::*;
+
+  const LANES: usize = 2;
+
+  #[inline]
+  #[target_feature(enable = "neon")]
+  unsafe fn zero() -> float64x2_t {
+    vdupq_n_f64(0.0)
+  }
+
+  #[inline]
+  #[target_feature(enable = "neon")]
+  unsafe fn sub(a: float64x2_t, b: float64x2_t) -> float64x2_t {
+    vsubq_f64(a, b)
+  }
+
+  #[inline]
+  #[target_feature(enable = "neon")]
+  unsafe fn fma(
+    a: float64x2_t,
+    b: float64x2_t,
+    acc: float64x2_t,
+  ) -> float64x2_t {
+    vfmaq_f64(acc, a, b)
+  }
+
+  #[inline]
+  #[target_feature(enable = "neon")]
+  unsafe fn sum(v: float64x2_t) -> f64 {
+    vaddvq_f64(v)
+  }
+
+  #[inline]
+  #[target_feature(enable = "neon")]
+  unsafe fn load_f64(p: *const f64) -> float64x2_t {
+    vld1q_f64(p)
+  }
+
+  #[inline]
+  #[target_feature(enable = "neon")]
+  unsafe fn load_f32(p: *const f32) -> float64x2_t {
+    vcvt_f64_f32(vld1_f32(p))
+  }
+
+  pub(super) const F64: KernelSet<f64> = kernels!("neon", f64, load_f64);
+  pub(super) const F32: KernelSet<f32> = kernels!("neon", f32, load_f32);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::random::Rng;
+
+  fn assert_close(actual: f64, expected: f64) {
+    let tolerance = 1e-12 * expected.abs().max(1.0);
+    assert!(
+      (actual - expected).abs() <= tolerance,
+      "{actual} is not close to {expected}"
+    );
+  }
+
+  fn check_agreement<T: Element>() {
+    let mut rng = Rng::new(20);
+    let scalar = T::kernels_for(Level::Scalar);
+    for level in Level::supported() {
+      let kernels = T::kernels_for(level);
+      for len in (0..=70).chain([255, 1000]) {
+        let mut random = || T::from_f64(rng.next_f64() * 20.0 - 10.0);
+        let a: Vec<T> = (0..len).map(|_| random()).collect();
+        let b: Vec<T> = (0..len).map(|_| random()).collect();
+        // SAFETY: the level is supported and the lengths match.
+        unsafe {
+          assert_close((kernels.dot)(&a, &b), (scalar.dot)(&a, &b));
+          assert_close(
+            (kernels.squared_euclidean)(&a, &b),
+            (scalar.squared_euclidean)(&a, &b),
+          );
+          let terms = (kernels.cosine)(&a, &b);
+          for (actual, expected) in
+            terms.into_iter().zip((scalar.cosine)(&a, &b))
+          {
+            assert_close(actual, expected);
+          }
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_all_levels_agree_with_scalar_code() {
+    check_agreement::<f64>();
+    check_agreement::<f32>();
+  }
+
+  #[test]
+  fn test_f32_and_f64_kernels_give_the_same_scores() {
+    let a: Vec<f32> = (0..37).map(|i| i as f32 * 0.37 - 5.0).collect();
+    let b: Vec<f32> = (0..37).map(|i| 3.0 - i as f32 * 0.11).collect();
+    let a64: Vec<f64> = a.iter().map(|&x| x.into()).collect();
+    let b64: Vec<f64> = b.iter().map(|&x| x.into()).collect();
+    for level in Level::supported() {
+      let (narrow, wide) = (f32::kernels_for(level), f64::kernels_for(level));
+      // SAFETY: the level is supported and the lengths match.
+      unsafe {
+        assert_eq!((narrow.dot)(&a, &b), (wide.dot)(&a64, &b64));
+        assert_eq!(
+          (narrow.squared_euclidean)(&a, &b),
+          (wide.squared_euclidean)(&a64, &b64)
+        );
+        assert_eq!((narrow.cosine)(&a, &b), (wide.cosine)(&a64, &b64));
+      }
+    }
+  }
+
+  #[test]
+  fn test_strided_views_use_scalar_code() {
+    let a = ndarray::arr2(&[[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]]);
+    let (x, y) = (a.column(0), a.column(1));
+    assert_eq!(dot(&x, &y), 44.0);
+    assert_eq!(squared_euclidean(&x, &y), 3.0);
+    assert_eq!(cosine(&x, &y), [44.0, 35.0, 56.0]);
+  }
+}
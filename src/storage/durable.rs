@@ -0,0 +1,856 @@
+use super::wal::{RecordRef, Recovery, SyncPolicy, Wal};
+use crate::distance::MetricKind;
+use crate::error::{Error, Result};
+use crate::index::VectorIndex;
+use crate::metadata::Metadata;
+use crate::vector_database::{
+  check_vector, next_id_after, VectorDatabase, VectorId,
+};
+use ndarray::{Array1, ArrayBase, Data, Ix2};
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A [`VectorDatabase`] whose changes are recorded in a write-ahead log, so
+/// that they survive crashes.
+///
+/// The database lives in a directory holding a snapshot, in the format
+/// written by [`VectorDatabase::save`], and a log of the changes made since
+/// the snapshot was taken. Every change is appended to the log before the
+/// method making it returns, and flushed to stable storage according to the
+/// [`SyncPolicy`]. Opening the directory loads the snapshot and replays the
+/// log, restoring the exact state the database was in, IDs included.
+///
+/// The log keeps growing with every change, including the insertion of
+/// vectors that were removed since. [`Self::compact`] rewrites the snapshot
+/// from the current state and starts a new, empty log; it can also run in
+/// the background while changes continue, see [`Self::start_compaction`].
+///
+/// Queries go through [`Deref`] to the in-memory [`VectorDatabase`], while
+/// changes must go through the methods of this type so that they are logged.
+/// Vectors are stored as `f64`, since the log records them as such.
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::distance::MetricKind;
+/// use rustyvectors::storage::{DurableDatabase, SyncPolicy};
+///
+/// # let dir = tempfile::tempdir().unwrap();
+/// # let dir = dir.path().join("vectors");
+/// let mut db =
+///   DurableDatabase::create(&dir, 2, MetricKind::Euclidean, SyncPolicy::Always)?;
+/// let id = db.add(&[1.0, 2.0])?;
+/// drop(db);
+///
+/// let db = DurableDatabase::open(&dir, SyncPolicy::Always)?;
+/// assert_eq!(db.nearest(&[1.0, 2.0])?, Some(id));
+/// # Ok::<(), rustyvectors::Error>(())
+/// ```
+pub struct DurableDatabase {
+  db: VectorDatabase,
+  dir: PathBuf,
+  /// The log changes are appended to.
+  wal: Wal,
+  /// The generation of `wal`.
+  ///
+  /// Each compaction starts a new log whose generation is one more than the
+  /// previous one. The snapshot of generation `n` holds the changes of all the
+  /// logs of lower generations, so the state of the database is that of the
+  /// latest snapshot followed by the logs of the same and higher generations.
+  generation: u64,
+  recovery: Recovery,
+}
+
+impl DurableDatabase {
+  /// Creates an empty database in the directory `dir`, creating the
+  /// directory if needed.
+  ///
+  /// Returns [`Error::Io`] if `dir` already holds a database, and the errors
+  /// of [`VectorDatabase::with_metric`] for an invalid dimension or metric.
+  pub fn create(
+    dir: impl AsRef<Path>,
+    dimension: usize,
+    metric: MetricKind,
+    policy: SyncPolicy,
+  ) -> Result<Self> {
+    let dir = dir.as_ref();
+    let db = VectorDatabase::with_metric(dimension, metric)?;
+    fs::create_dir_all(dir)?;
+    if !generations(dir, SNAPSHOT)?.is_empty() {
+      return Err(Error::from(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("a database already exists in {}", dir.display()),
+      )));
+    }
+    // The log is created first, so that the snapshot only exists once the
+    // directory is complete.
+    let _ = fs::remove_file(wal_path(dir, 0));
+    let wal = Wal::create(&wal_path(dir, 0), policy)?;
+    db.save(snapshot_path(dir, 0))?;
+    Ok(Self {
+      db,
+      dir: dir.to_path_buf(),
+      wal,
+      generation: 0,
+      recovery: Recovery::default(),
+    })
+  }
+
+  /// Opens the database in the directory `dir`, replaying the changes logged
+  /// since its snapshot.
+  ///
+  /// An incomplete record at the end of the log, left by a crash in the
+  /// middle of a write, is discarded and reported by [`Self::recovery`].
+  /// Files left behind by a compaction interrupted by a crash are cleaned
+  /// up. Returns [`Error::Io`] if `dir` holds no database, and
+  /// [`Error::Corrupted`] if the snapshot or any other log record is invalid.
+  pub fn open(dir: impl AsRef<Path>, policy: SyncPolicy) -> Result<Self> {
+    let dir = dir.as_ref();
+    let Some(&base) = generations(dir, SNAPSHOT)?.iter().max() else {
+      return Err(Error::from(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no database in {}", dir.display()),
+      )));
+    };
+    let mut db = VectorDatabase::open(snapshot_path(dir, base))?;
+
+    let mut recovery = Recovery::default();
+    let mut current = None;
+    let mut generation = base;
+    while wal_path(dir, generation).exists() {
+      let path = wal_path(dir, generation);
+      let (wal, replayed) = Wal::replay(&path, policy, &mut db)?;
+      recovery.replayed += replayed.replayed;
+      recovery.discarded_bytes += replayed.discarded_bytes;
+      current = Some((wal, generation));
+      generation += 1;
+    }
+    let (wal, generation) = match current {
+      Some(current) => current,
+      None => (Wal::create(&wal_path(dir, base), policy)?, base),
+    };
+    remove_obsolete_files(dir, base)?;
+
+    Ok(Self {
+      db,
+      dir: dir.to_path_buf(),
+      wal,
+      generation,
+      recovery,
+    })
+  }
+
+  /// Returns what was found when replaying the log on [`Self::open`].
+  pub fn recovery(&self) -> Recovery {
+    self.recovery
+  }
+
+  /// Changes when the log is flushed to stable storage.
+  pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+    self.wal.set_policy(policy);
+  }
+
+  /// Flushes all logged changes to stable storage, whatever the policy.
+  pub fn sync(&mut self) -> Result<()> {
+    self.wal.sync()
+  }
+
+  /// Adds a vector to the database and returns its ID.
+  ///
+  /// See [`VectorDatabase::add`].
+  pub fn add(&mut self, vector: &[f64]) -> Result<VectorId> {
+    self.add_with_metadata(vector, Metadata::new())
+  }
+
+  /// Adds a vector along with a metadata payload and returns its ID.
+  ///
+  /// See [`VectorDatabase::add_with_metadata`].
+  pub fn add_with_metadata(
+    &mut self,
+    vector: &[f64],
+    metadata: Metadata,
+  ) -> Result<VectorId> {
+    check_vector(vector, self.db.dimension())?;
+    let id = self.db.next_id();
+    next_id_after(id)?;
+    self.wal.append(RecordRef::Insert {
+      id,
+      vector,
+      metadata: &metadata,
+    })?;
+    self.db.insert_with_id(id, vector, metadata)?;
+    Ok(id)
+  }
+
+  /// Adds every row of `vectors` to the database and returns their IDs, in
+  /// the order of the rows.
+  ///
+  /// See [`VectorDatabase::add_batch`]. The vectors are logged as one
+  /// insertion each, written together and flushed at most once, so a crash
+  /// while they are written may keep only the first vectors of the batch.
+  pub fn add_batch<S: Data<Elem = f64>>(
+    &mut self,
+    vectors: &ArrayBase<S, Ix2>,
+  ) -> Result<Vec<VectorId>> {
+    let vectors = vectors.as_standard_layout();
+    let rows = vectors.as_slice().expect("standard layout");
+    self.db.check_rows(rows, vectors.ncols())?;
+    let metadata = Metadata::new();
+    let ids = self.db.next_id()..;
+    let records =
+      ids
+        .zip(rows.chunks_exact(vectors.ncols()))
+        .map(|(id, vector)| RecordRef::Insert {
+          id,
+          vector,
+          metadata: &metadata,
+        });
+    self.wal.append_all(records)?;
+    self.db.add_batch(&vectors)
+  }
+
+  /// Removes a vector from the database by its ID.
+  ///
+  /// See [`VectorDatabase::remove`].
+  pub fn remove(&mut self, id: VectorId) -> Result<Option<Array1<f64>>> {
+    if !self.db.contains(id) {
+      return Ok(None);
+    }
+    self.wal.append(RecordRef::Remove(id))?;
+    Ok(self.db.remove(id))
+  }
+
+  /// Replaces the vector stored under `id` and returns the previous one.
+  ///
+  /// See [`VectorDatabase::update`].
+  pub fn update(
+    &mut self,
+    id: VectorId,
+    vector: &[f64],
+  ) -> Result<Array1<f64>> {
+    check_vector(vector, self.db.dimension())?;
+    next_id_after(id)?;
+    if !self.db.contains(id) {
+      return Err(Error::NotFound(id));
+    }
+    self.wal.append(RecordRef::Update { id, vector })?;
+    self.db.update(id, vector)
+  }
+
+  /// Stores a vector and its payload under `id`, replacing the ones already
+  /// stored under it, and returns whether there were any.
+  ///
+  /// See [`VectorDatabase::upsert`].
+  pub fn upsert(
+    &mut self,
+    id: VectorId,
+    vector: &[f64],
+    metadata: Metadata,
+  ) -> Result<bool> {
+    check_vector(vector, self.db.dimension())?;
+    next_id_after(id)?;
+    self.wal.append(RecordRef::Upsert {
+      id,
+      vector,
+      metadata: &metadata,
+    })?;
+    self.db.upsert(id, vector, metadata)
+  }
+
+  /// Replaces the metadata payload of a vector and returns the previous one.
+  ///
+  /// See [`VectorDatabase::set_metadata`].
+  pub fn set_metadata(
+    &mut self,
+    id: VectorId,
+    metadata: Metadata,
+  ) -> Result<Metadata> {
+    if !self.db.contains(id) {
+      return Err(Error::NotFound(id));
+    }
+    self.wal.append(RecordRef::SetMetadata(id, &metadata))?;
+    self.db.set_metadata(id, metadata)
+  }
+
+  /// Creates a secondary index over a metadata field.
+  ///
+  /// See [`VectorDatabase::create_index`].
+  pub fn create_index(&mut self, field: &str) -> Result<()> {
+    if !self.db.has_index(field) {
+      self.wal.append(RecordRef::CreateIndex(field))?;
+      self.db.create_index(field);
+    }
+    Ok(())
+  }
+
+  /// Drops the secondary index over `field`, returning whether it existed.
+  pub fn drop_index(&mut self, field: &str) -> Result<bool> {
+    if !self.db.has_index(field) {
+      return Ok(false);
+    }
+    self.wal.append(RecordRef::DropIndex(field))?;
+    Ok(self.db.drop_index(field))
+  }
+
+  /// Attaches an approximate nearest neighbor index.
+  ///
+  /// See [`VectorDatabase::set_index`]. The index is not persisted, and must
+  /// be attached again after opening the database.
+  pub fn set_index(&mut self, index: impl VectorIndex + 'static) -> Result<()> {
+    self.db.set_index(index)
+  }
+
+  /// Detaches and returns the approximate nearest neighbor index, if any.
+  pub fn take_index(&mut self) -> Option<Box<dyn VectorIndex>> {
+    self.db.take_index()
+  }
+
+  /// Returns a copy of the current state of the database, without the
+  /// attached index.
+  ///
+  /// The copy is independent of later changes, so it can be saved elsewhere
+  /// with [`VectorDatabase::save`], for instance on another thread, while
+  /// changes continue. IDs are preserved.
+  pub fn snapshot(&self) -> VectorDatabase {
+    self.db.copy_without_index()
+  }
+
+  /// Returns the size in bytes of the log changes are currently appended to.
+  pub fn log_len(&self) -> u64 {
+    self.wal.len()
+  }
+
+  /// Rewrites the snapshot from the current state of the database and
+  /// starts a new, empty log, reclaiming the space used by removed vectors
+  /// and by the history of changes.
+  ///
+  /// This is [`Self::start_compaction`] followed by [`Compaction::run`].
+  pub fn compact(&mut self) -> Result<()> {
+    self.start_compaction()?.run()
+  }
+
+  /// Starts a compaction that can complete in the background.
+  ///
+  /// Changes are logged to a new log from now on, and the returned
+  /// [`Compaction`] holds a copy of the current state, to be written as the
+  /// new snapshot by [`Compaction::run`], possibly on another thread while
+  /// changes continue. Until it completes, opening the database replays the
+  /// previous log as well, so a compaction interrupted by a crash or never
+  /// run loses nothing.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::distance::MetricKind;
+  /// use rustyvectors::storage::{DurableDatabase, SyncPolicy};
+  ///
+  /// # let dir = tempfile::tempdir().unwrap();
+  /// let mut db =
+  ///   DurableDatabase::create(dir.path(), 2, MetricKind::Euclidean, SyncPolicy::Always)?;
+  /// let id = db.add(&[1.0, 2.0])?;
+  /// db.remove(id)?;
+  ///
+  /// let compaction = db.start_compaction()?;
+  /// let worker = std::thread::spawn(move || compaction.run());
+  /// db.add(&[3.0, 4.0])?;
+  /// worker.join().unwrap()?;
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn start_compaction(&mut self) -> Result<Compaction> {
+    self.wal.sync()?;
+    let generation = self.generation + 1;
+    self.wal =
+      Wal::create(&wal_path(&self.dir, generation), self.wal.policy())?;
+    self.generation = generation;
+    Ok(Compaction {
+      snapshot: self.db.copy_without_index(),
+      dir: self.dir.clone(),
+      generation,
+    })
+  }
+}
+
+impl Deref for DurableDatabase {
+  type Target = VectorDatabase;
+
+  fn deref(&self) -> &VectorDatabase {
+    &self.db
+  }
+}
+
+/// A compaction started by [`DurableDatabase::start_compaction`].
+pub struct Compaction {
+  /// The state of the database when the compaction started.
+  snapshot: VectorDatabase,
+  dir: PathBuf,
+  /// The generation of the snapshot to write.
+  generation: u64,
+}
+
+impl Compaction {
+  /// Writes the new snapshot and removes the files it makes obsolete.
+  pub fn run(self) -> Result<()> {
+    self
+      .snapshot
+      .save(snapshot_path(&self.dir, self.generation))?;
+    remove_obsolete_files(&self.dir, self.generation)
+  }
+}
+
+const SNAPSHOT: (&str, &str) = ("snapshot-", ".rvdb");
+const WAL: (&str, &str) = ("wal-", ".log");
+
+fn snapshot_path(dir: &Path, generation: u64) -> PathBuf {
+  file_path(dir, SNAPSHOT, generation)
+}
+
+fn wal_path(dir: &Path, generation: u64) -> PathBuf {
+  file_path(dir, WAL, generation)
+}
+
+fn file_path(
+  dir: &Path,
+  (prefix, suffix): (&str, &str),
+  generation: u64,
+) -> PathBuf {
+  dir.join(format!("{prefix}{generation}{suffix}"))
+}
+
+/// Returns the generations of the files of `dir` whose names have the given
+/// prefix and suffix.
+fn generations(dir: &Path, (prefix, suffix): (&str, &str)) -> Result<Vec<u64>> {
+  let mut generations = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let name = entry?.file_name();
+    let generation = name.to_str().and_then(|name| {
+      name
+        .strip_prefix(prefix)?
+        .strip_suffix(suffix)?
+        .parse::<u64>()
+        .ok()
+    });
+    generations.extend(generation);
+  }
+  Ok(generations)
+}
+
+/// Removes the snapshots and logs of generations below `generation`, whose
+/// changes the snapshot of `generation` holds.
+fn remove_obsolete_files(dir: &Path, generation: u64) -> Result<()> {
+  for kind in [SNAPSHOT, WAL] {
+    for obsolete in generations(dir, kind)? {
+      if obsolete < generation {
+        match fs::remove_file(file_path(dir, kind, obsolete)) {
+          Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            return Err(error.into())
+          }
+          _ => {}
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::metadata::Value;
+  use std::time::Duration;
+
+  fn tagged(value: i64) -> Metadata {
+    let mut metadata = Metadata::new();
+    metadata.insert("tag".to_string(), Value::from(value));
+    metadata
+  }
+
+  /// Makes a few changes of every kind and returns the expected state.
+  fn populate(db: &mut DurableDatabase) -> VectorDatabase {
+    let mut expected = VectorDatabase::new(2).unwrap();
+    expected.create_index("tag");
+    db.create_index("tag").unwrap();
+    for i in 0..10 {
+      let vector = [i as f64, -(i as f64)];
+      db.add_with_metadata(&vector, tagged(i)).unwrap();
+      expected.add_with_metadata(&vector, tagged(i)).unwrap();
+    }
+    for id in [2, 9] {
+      db.remove(id).unwrap();
+      expected.remove(id);
+    }
+    db.set_metadata(4, tagged(40)).unwrap();
+    expected.set_metadata(4, tagged(40)).unwrap();
+    db.update(5, &[50.0, -50.0]).unwrap();
+    expected.update(5, &[50.0, -50.0]).unwrap();
+    for id in [2, 6, 9] {
+      let vector = [id as f64, 0.5];
+      db.upsert(id, &vector, tagged(-1)).unwrap();
+      expected.upsert(id, &vector, tagged(-1)).unwrap();
+    }
+    expected
+  }
+
+  fn assert_same(db: &VectorDatabase, expected: &VectorDatabase) {
+    let mut ids: Vec<_> = db.ids().collect();
+    let mut expected_ids: Vec<_> = expected.ids().collect();
+    ids.sort_unstable();
+    expected_ids.sort_unstable();
+    assert_eq!(ids, expected_ids);
+    for id in ids {
+      assert_eq!(db.get(id), expected.get(id));
+      assert_eq!(db.metadata(id), expected.metadata(id));
+    }
+    assert_eq!(db.has_index("tag"), expected.has_index("tag"));
+    assert_eq!(db.next_id(), expected.next_id());
+  }
+
+  #[test]
+  fn test_replay_restores_the_state() {
+    for policy in [
+      SyncPolicy::Always,
+      SyncPolicy::Batch(4),
+      SyncPolicy::Interval(Duration::from_secs(60)),
+    ] {
+      let dir = tempfile::tempdir().unwrap();
+      let mut db =
+        DurableDatabase::create(dir.path(), 2, MetricKind::Euclidean, policy)
+          .unwrap();
+      let expected = populate(&mut db);
+      drop(db);
+
+      let mut db = DurableDatabase::open(dir.path(), policy).unwrap();
+      assert_same(&db, &expected);
+      assert_eq!(
+        db.recovery(),
+        Recovery {
+          replayed: 18,
+          discarded_bytes: 0
+        }
+      );
+      assert_eq!(db.add(&[0.0, 0.0]).unwrap(), 10);
+    }
+  }
+
+  #[test]
+  fn test_torn_tail_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    let mut expected = populate(&mut db);
+    db.add(&[7.0, 7.0]).unwrap();
+    drop(db);
+    let log = fs::read(wal_path(dir.path(), 0)).unwrap();
+    fs::write(wal_path(dir.path(), 0), &log[..log.len() - 5]).unwrap();
+
+    let mut db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().replayed, 18);
+    assert!(db.recovery().discarded_bytes > 0);
+    assert_same(&db, &expected);
+
+    // The log was cut back, so new records follow the last valid one.
+    let id = db.add(&[8.0, 8.0]).unwrap();
+    expected.add(&[8.0, 8.0]).unwrap();
+    assert_eq!(id, 10);
+    drop(db);
+    let db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().discarded_bytes, 0);
+    assert_same(&db, &expected);
+  }
+
+  #[test]
+  fn test_zero_filled_tail_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    let mut expected = populate(&mut db);
+    drop(db);
+    let mut log = fs::read(wal_path(dir.path(), 0)).unwrap();
+    let len = log.len();
+    log.resize(len + 16, 0);
+    fs::write(wal_path(dir.path(), 0), &log).unwrap();
+
+    let mut db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(
+      db.recovery(),
+      Recovery {
+        replayed: 18,
+        discarded_bytes: 16
+      }
+    );
+    assert_same(&db, &expected);
+    db.add(&[8.0, 8.0]).unwrap();
+    expected.add(&[8.0, 8.0]).unwrap();
+    drop(db);
+    let db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().discarded_bytes, 0);
+    assert_same(&db, &expected);
+  }
+
+  #[test]
+  fn test_add_batch_is_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Batch(2),
+    )
+    .unwrap();
+    let mut expected = populate(&mut db);
+    let log_len = db.log_len();
+    let invalid = ndarray::arr2(&[[1.0, 1.0], [f64::NAN, 0.0]]);
+    assert!(db.add_batch(&invalid).is_err());
+    assert!(db.add_batch(&invalid.t()).is_err());
+    assert!(db.add_batch(&ndarray::arr2(&[[1.0, 2.0, 3.0]])).is_err());
+    assert_eq!(db.log_len(), log_len);
+
+    let vectors = ndarray::arr2(&[[1.0, 1.0], [2.0, 4.0], [3.0, 9.0]]);
+    assert_eq!(db.add_batch(&vectors).unwrap(), [10, 11, 12]);
+    expected.add_batch(&vectors).unwrap();
+    assert_same(&db, &expected);
+    drop(db);
+
+    let db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().replayed, 21);
+    assert_same(&db, &expected);
+  }
+
+  #[test]
+  fn test_damaged_record_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    populate(&mut db);
+    drop(db);
+    let mut log = fs::read(wal_path(dir.path(), 0)).unwrap();
+    log[40] ^= 0x10;
+    fs::write(wal_path(dir.path(), 0), &log).unwrap();
+
+    assert!(matches!(
+      DurableDatabase::open(dir.path(), SyncPolicy::Always),
+      Err(Error::Corrupted(_))
+    ));
+  }
+
+  #[test]
+  fn test_damaged_length_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    db.add(&[1.0, 1.0]).unwrap();
+    db.add(&[2.0, 2.0]).unwrap();
+    drop(db);
+    // The length of the first record now points past the end of the file.
+    let mut log = fs::read(wal_path(dir.path(), 0)).unwrap();
+    log[16..20].copy_from_slice(&1000_u32.to_le_bytes());
+    fs::write(wal_path(dir.path(), 0), &log).unwrap();
+
+    assert!(matches!(
+      DurableDatabase::open(dir.path(), SyncPolicy::Always),
+      Err(Error::Corrupted(_))
+    ));
+    assert_eq!(fs::read(wal_path(dir.path(), 0)).unwrap(), log);
+  }
+
+  #[test]
+  fn test_invalid_changes_are_not_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    assert!(db.add(&[1.0]).is_err());
+    assert!(db.add(&[f64::NAN, 1.0]).is_err());
+    assert_eq!(db.remove(3).unwrap(), None);
+    assert_eq!(db.set_metadata(3, Metadata::new()), Err(Error::NotFound(3)));
+    assert!(!db.drop_index("tag").unwrap());
+    drop(db);
+
+    let db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().replayed, 0);
+    assert!(
+      DurableDatabase::create(dir.path(), 2, MetricKind::Euclidean, {
+        SyncPolicy::Always
+      })
+      .is_err()
+    );
+  }
+
+  #[test]
+  fn test_ids_out_of_range_are_not_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    let last = VectorId::MAX - 1;
+    assert!(!db.upsert(last, &[1.0, 1.0], Metadata::new()).unwrap());
+    let log_len = db.log_len();
+    // No ID is left for the database to assign.
+    assert!(matches!(db.add(&[2.0, 2.0]), Err(Error::InvalidValue(_))));
+    assert!(db.add_batch(&ndarray::arr2(&[[2.0, 2.0]])).is_err());
+    assert!(matches!(
+      db.upsert(VectorId::MAX, &[3.0, 3.0], Metadata::new()),
+      Err(Error::InvalidValue(_))
+    ));
+    assert!(matches!(
+      db.update(VectorId::MAX, &[3.0, 3.0]),
+      Err(Error::InvalidValue(_))
+    ));
+    assert_eq!(db.log_len(), log_len);
+    db.update(last, &[4.0, 4.0]).unwrap();
+    drop(db);
+
+    let mut db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().replayed, 2);
+    assert_eq!(db.ids().collect::<Vec<_>>(), [last]);
+    assert_eq!(db.get(last), Some(ndarray::arr1(&[4.0, 4.0]).view()));
+    assert!(db.add(&[2.0, 2.0]).is_err());
+  }
+
+  fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+      .collect();
+    names.sort();
+    names
+  }
+
+  #[test]
+  fn test_compaction_reclaims_space() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    let mut expected = populate(&mut db);
+    for i in 0..100 {
+      let id = db.add(&[i as f64, 0.0]).unwrap();
+      db.remove(id).unwrap();
+      expected.add(&[i as f64, 0.0]).unwrap();
+      expected.remove(id);
+    }
+    let log_len = db.log_len();
+    db.compact().unwrap();
+    assert!(db.log_len() < log_len);
+    assert_eq!(file_names(dir.path()), ["snapshot-1.rvdb", "wal-1.log"]);
+    let snapshot_len = fs::metadata(dir.path().join("snapshot-1.rvdb"))
+      .unwrap()
+      .len();
+    assert!(snapshot_len < log_len);
+
+    db.add(&[1.0, 1.0]).unwrap();
+    expected.add(&[1.0, 1.0]).unwrap();
+    drop(db);
+    let db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().replayed, 1);
+    assert_same(&db, &expected);
+  }
+
+  #[test]
+  fn test_background_compaction() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    let mut expected = populate(&mut db);
+
+    let compaction = db.start_compaction().unwrap();
+    let worker = std::thread::spawn(move || compaction.run());
+    for i in 0..20 {
+      db.add(&[i as f64, 1.0]).unwrap();
+      expected.add(&[i as f64, 1.0]).unwrap();
+    }
+    db.remove(0).unwrap();
+    expected.remove(0);
+    worker.join().unwrap().unwrap();
+    drop(db);
+
+    let db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_eq!(db.recovery().replayed, 21);
+    assert_same(&db, &expected);
+  }
+
+  #[test]
+  fn test_interrupted_compaction_loses_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path(),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    let mut expected = populate(&mut db);
+    drop(db.start_compaction().unwrap());
+    db.add(&[5.0, 5.0]).unwrap();
+    expected.add(&[5.0, 5.0]).unwrap();
+    drop(db);
+
+    let mut db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_same(&db, &expected);
+    db.add(&[6.0, 6.0]).unwrap();
+    expected.add(&[6.0, 6.0]).unwrap();
+    db.compact().unwrap();
+    drop(db);
+    assert_eq!(file_names(dir.path()), ["snapshot-2.rvdb", "wal-2.log"]);
+    let db = DurableDatabase::open(dir.path(), SyncPolicy::Always).unwrap();
+    assert_same(&db, &expected);
+  }
+
+  #[test]
+  fn test_snapshot_is_a_point_in_time_copy() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = DurableDatabase::create(
+      dir.path().join("live"),
+      2,
+      MetricKind::Euclidean,
+      SyncPolicy::Always,
+    )
+    .unwrap();
+    let expected = populate(&mut db);
+    let snapshot = db.snapshot();
+    db.add(&[9.0, 9.0]).unwrap();
+    db.remove(0).unwrap();
+
+    let path = dir.path().join("backup.rvdb");
+    snapshot.save(&path).unwrap();
+    assert_same(&VectorDatabase::open(&path).unwrap(), &expected);
+  }
+}
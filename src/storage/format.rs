@@ -0,0 +1,445 @@
+//! Encoding and decoding of the header and metadata section of database
+//! files. The layout is described in the [module documentation](super).
+
+use crate::distance::MetricKind;
+use crate::element::{bf16, f16};
+use crate::error::{Error, Result};
+use crate::metadata::{Metadata, Value};
+use crate::vector_database::VectorId;
+
+/// The first bytes of every database file.
+pub const MAGIC: [u8; 8] = *b"RSTYVDB\0";
+
+/// The version of the file format written by this build, and the only one it
+/// reads.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The size of the fixed header at the start of the file.
+pub(crate) const HEADER_LEN: usize = 80;
+
+/// The deepest nesting of lists and maps accepted when decoding metadata, so
+/// that a malicious file cannot overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// The encoding of vector components in database files.
+///
+/// Implemented for the [`Element`](crate::element::Element) types only, all
+/// of which are plain little-endian values for which any bit pattern is
+/// valid, so that mapped files can be read in place.
+pub trait Component: Copy {
+  /// The tag identifying the component type in the header.
+  const TAG: u32;
+  /// The size of an encoded component in bytes.
+  const SIZE: usize;
+
+  fn put(self, bytes: &mut Vec<u8>);
+
+  /// Decodes a component from its `SIZE` little-endian bytes.
+  fn from_le_bytes(bytes: &[u8]) -> Self;
+}
+
+macro_rules! component {
+  ($type:ty, $tag:expr) => {
+    impl Component for $type {
+      const TAG: u32 = $tag;
+      const SIZE: usize = std::mem::size_of::<$type>();
+
+      fn put(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+      }
+
+      fn from_le_bytes(bytes: &[u8]) -> Self {
+        <$type>::from_le_bytes(bytes.try_into().unwrap())
+      }
+    }
+  };
+}
+
+component!(f64, 0);
+component!(f32, 1);
+component!(f16, 2);
+component!(bf16, 3);
+
+/// Returns the name and size of the component type with the given tag.
+pub(crate) fn component_type(tag: u32) -> Option<(&'static str, usize)> {
+  match tag {
+    0 => Some(("f64", 8)),
+    1 => Some(("f32", 4)),
+    2 => Some(("f16", 2)),
+    3 => Some(("bf16", 2)),
+    _ => None,
+  }
+}
+
+/// The fixed-size header of a database file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Header {
+  pub dimension: usize,
+  pub count: usize,
+  pub next_id: VectorId,
+  pub metric: MetricKind,
+  /// The [`Component::TAG`] of the type the vectors are stored as.
+  pub element: u32,
+  /// The CRC-32 of the ID and vector sections.
+  pub vectors_crc: u32,
+  /// The CRC-32 of the metadata section.
+  pub metadata_crc: u32,
+  pub metadata_len: u64,
+}
+
+impl Header {
+  /// Returns the offset of the vector section, which is always a multiple
+  /// of 8 so that it can be viewed as `f64`s in place.
+  pub fn vectors_offset(&self) -> u64 {
+    (HEADER_LEN + self.count * 8) as u64
+  }
+
+  /// Returns the offset of the metadata section.
+  pub fn metadata_offset(&self) -> u64 {
+    let (_, size) = component_type(self.element).expect("a known tag");
+    self.vectors_offset() + (self.count * self.dimension * size) as u64
+  }
+
+  /// Returns the total size of the file described by this header.
+  pub fn file_len(&self) -> u64 {
+    self.metadata_offset() + self.metadata_len
+  }
+
+  pub fn encode(&self) -> [u8; HEADER_LEN] {
+    let (metric_tag, metric_parameter) = match self.metric {
+      MetricKind::Euclidean => (0, 0.0),
+      MetricKind::Cosine => (1, 0.0),
+      MetricKind::DotProduct => (2, 0.0),
+      MetricKind::Manhattan => (3, 0.0),
+      MetricKind::Chebyshev => (4, 0.0),
+      MetricKind::Minkowski(p) => (5, p),
+    };
+    let mut bytes = [0; HEADER_LEN];
+    bytes[0..8].copy_from_slice(&MAGIC);
+    bytes[8..12].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes[16..24].copy_from_slice(&(self.dimension as u64).to_le_bytes());
+    bytes[24..32].copy_from_slice(&(self.count as u64).to_le_bytes());
+    bytes[32..40].copy_from_slice(&self.next_id.to_le_bytes());
+    bytes[40..44].copy_from_slice(&(metric_tag as u32).to_le_bytes());
+    bytes[44..48].copy_from_slice(&self.element.to_le_bytes());
+    bytes[48..56].copy_from_slice(&metric_parameter.to_le_bytes());
+    bytes[56..60].copy_from_slice(&self.vectors_crc.to_le_bytes());
+    bytes[60..64].copy_from_slice(&self.metadata_crc.to_le_bytes());
+    bytes[64..72].copy_from_slice(&self.metadata_len.to_le_bytes());
+    let crc = crc32fast::hash(&bytes[..72]);
+    bytes[72..76].copy_from_slice(&crc.to_le_bytes());
+    bytes
+  }
+
+  /// Decodes and validates a header.
+  ///
+  /// Returns [`Error::Corrupted`] if `bytes` does not hold a valid header and
+  /// [`Error::UnsupportedVersion`] if it was written in another version of
+  /// the format.
+  pub fn decode(bytes: &[u8; HEADER_LEN]) -> Result<Self> {
+    if bytes[0..8] != MAGIC {
+      return Err(corrupted("not a rustyvectors database file"));
+    }
+    let version = u32_at(bytes, 8);
+    if version != FORMAT_VERSION {
+      return Err(Error::UnsupportedVersion {
+        found: version,
+        supported: FORMAT_VERSION,
+      });
+    }
+    if crc32fast::hash(&bytes[..72]) != u32_at(bytes, 72) {
+      return Err(corrupted("header checksum mismatch"));
+    }
+
+    let metric = match u32_at(bytes, 40) {
+      0 => MetricKind::Euclidean,
+      1 => MetricKind::Cosine,
+      2 => MetricKind::DotProduct,
+      3 => MetricKind::Manhattan,
+      4 => MetricKind::Chebyshev,
+      5 => MetricKind::Minkowski(f64_at(bytes, 48)),
+      tag => return Err(corrupted(&format!("unknown metric tag {tag}"))),
+    };
+    let element = u32_at(bytes, 44);
+    let Some((_, size)) = component_type(element) else {
+      return Err(corrupted(&format!("unknown element type tag {element}")));
+    };
+    let dimension = usize::try_from(u64_at(bytes, 16));
+    let count = usize::try_from(u64_at(bytes, 24));
+    let (Ok(dimension), Ok(count)) = (dimension, count) else {
+      return Err(corrupted("vector count or dimension too large"));
+    };
+    let metadata_len = u64_at(bytes, 64);
+    let sizes_fit = count
+      .checked_mul(dimension)
+      .and_then(|components| components.checked_mul(size))
+      .and_then(|len| len.checked_add(count.checked_mul(8)?))
+      .and_then(|len| (len as u64).checked_add(HEADER_LEN as u64))
+      .and_then(|len| len.checked_add(metadata_len))
+      .is_some();
+    if !sizes_fit {
+      return Err(corrupted("vector count or dimension too large"));
+    }
+
+    Ok(Self {
+      dimension,
+      count,
+      next_id: u64_at(bytes, 32),
+      metric,
+      element,
+      vectors_crc: u32_at(bytes, 56),
+      metadata_crc: u32_at(bytes, 60),
+      metadata_len,
+    })
+  }
+}
+
+/// Returns an [`Error::Corrupted`] with the given message.
+pub(crate) fn corrupted(message: &str) -> Error {
+  Error::Corrupted(message.to_string())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+  u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn f64_at(bytes: &[u8], offset: usize) -> f64 {
+  f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Encodes the metadata section: the names of the indexed fields followed by
+/// one payload per vector.
+pub(crate) fn encode_metadata<'a>(
+  indexed_fields: impl ExactSizeIterator<Item = &'a str>,
+  payloads: impl Iterator<Item = &'a Metadata>,
+) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  put_len(&mut bytes, indexed_fields.len());
+  for field in indexed_fields {
+    put_str(&mut bytes, field);
+  }
+  for metadata in payloads {
+    put_map(&mut bytes, metadata);
+  }
+  bytes
+}
+
+fn put_len(bytes: &mut Vec<u8>, len: usize) {
+  let len = u32::try_from(len).expect("metadata collection too large");
+  bytes.extend_from_slice(&len.to_le_bytes());
+}
+
+pub(crate) fn put_str(bytes: &mut Vec<u8>, string: &str) {
+  put_len(bytes, string.len());
+  bytes.extend_from_slice(string.as_bytes());
+}
+
+pub(crate) fn put_map(bytes: &mut Vec<u8>, map: &Metadata) {
+  put_len(bytes, map.len());
+  for (key, value) in map {
+    put_str(bytes, key);
+    put_value(bytes, value);
+  }
+}
+
+fn put_value(bytes: &mut Vec<u8>, value: &Value) {
+  match value {
+    Value::Null => bytes.push(0),
+    Value::Bool(value) => bytes.extend_from_slice(&[1, u8::from(*value)]),
+    Value::Int(value) => {
+      bytes.push(2);
+      bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    Value::Float(value) => {
+      bytes.push(3);
+      bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    Value::String(value) => {
+      bytes.push(4);
+      put_str(bytes, value);
+    }
+    Value::List(values) => {
+      bytes.push(5);
+      put_len(bytes, values.len());
+      for value in values {
+        put_value(bytes, value);
+      }
+    }
+    Value::Map(map) => {
+      bytes.push(6);
+      put_map(bytes, map);
+    }
+  }
+}
+
+/// Decodes a metadata section holding `count` payloads, returning the names
+/// of the indexed fields and the payloads.
+pub(crate) fn decode_metadata(
+  bytes: &[u8],
+  count: usize,
+) -> Result<(Vec<String>, Vec<Metadata>)> {
+  let mut decoder = Decoder::new(bytes);
+  let fields = (0..decoder.len()?)
+    .map(|_| decoder.string())
+    .collect::<Result<_>>()?;
+  let payloads = (0..count)
+    .map(|_| decoder.metadata())
+    .collect::<Result<_>>()?;
+  decoder.finish()?;
+  Ok((fields, payloads))
+}
+
+/// Reads the values written by the `put_*` functions from a byte slice,
+/// reporting malformed input as [`Error::Corrupted`].
+pub(crate) struct Decoder<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+  pub fn new(bytes: &'a [u8]) -> Self {
+    Self { bytes }
+  }
+
+  /// Checks that all the bytes were read.
+  pub fn finish(&self) -> Result<()> {
+    if !self.bytes.is_empty() {
+      return Err(corrupted("unexpected trailing bytes"));
+    }
+    Ok(())
+  }
+
+  pub fn u8(&mut self) -> Result<u8> {
+    Ok(self.array::<1>()?[0])
+  }
+
+  pub fn u64(&mut self) -> Result<u64> {
+    Ok(u64::from_le_bytes(self.array()?))
+  }
+
+  pub fn f64(&mut self) -> Result<f64> {
+    Ok(f64::from_le_bytes(self.array()?))
+  }
+
+  pub fn metadata(&mut self) -> Result<Metadata> {
+    self.map(0)
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+    if len > self.bytes.len() {
+      return Err(corrupted("data ends unexpectedly"));
+    }
+    let (taken, rest) = self.bytes.split_at(len);
+    self.bytes = rest;
+    Ok(taken)
+  }
+
+  fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+    Ok(self.take(N)?.try_into().unwrap())
+  }
+
+  fn len(&mut self) -> Result<usize> {
+    Ok(u32::from_le_bytes(self.array()?) as usize)
+  }
+
+  pub fn string(&mut self) -> Result<String> {
+    let len = self.len()?;
+    String::from_utf8(self.take(len)?.to_vec())
+      .map_err(|_| corrupted("metadata string is not valid UTF-8"))
+  }
+
+  fn map(&mut self, depth: usize) -> Result<Metadata> {
+    if depth > MAX_DEPTH {
+      return Err(corrupted("metadata nested too deeply"));
+    }
+    let mut map = Metadata::new();
+    for _ in 0..self.len()? {
+      let key = self.string()?;
+      let value = self.value(depth + 1)?;
+      map.insert(key, value);
+    }
+    Ok(map)
+  }
+
+  fn value(&mut self, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+      return Err(corrupted("metadata nested too deeply"));
+    }
+    Ok(match self.array::<1>()?[0] {
+      0 => Value::Null,
+      1 => Value::Bool(self.array::<1>()?[0] != 0),
+      2 => Value::Int(i64::from_le_bytes(self.array()?)),
+      3 => Value::Float(f64::from_le_bytes(self.array()?)),
+      4 => Value::String(self.string()?),
+      5 => Value::List(
+        (0..self.len()?)
+          .map(|_| self.value(depth + 1))
+          .collect::<Result<_>>()?,
+      ),
+      6 => Value::Map(self.map(depth + 1)?),
+      tag => {
+        return Err(corrupted(&format!("unknown metadata value tag {tag}")))
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_header_round_trip() {
+    let header = Header {
+      dimension: 3,
+      count: 2,
+      next_id: 7,
+      metric: MetricKind::Minkowski(3.0),
+      element: f64::TAG,
+      vectors_crc: 1,
+      metadata_crc: 2,
+      metadata_len: 10,
+    };
+    let bytes = header.encode();
+    assert_eq!(Header::decode(&bytes).unwrap(), header);
+    assert_eq!(header.vectors_offset(), 96);
+    assert_eq!(header.file_len(), 96 + 48 + 10);
+    let header = Header {
+      element: f16::TAG,
+      ..header
+    };
+    assert_eq!(Header::decode(&header.encode()).unwrap(), header);
+    assert_eq!(header.file_len(), 96 + 12 + 10);
+
+    let mut damaged = bytes;
+    damaged[20] ^= 1;
+    assert_eq!(
+      Header::decode(&damaged),
+      Err(corrupted("header checksum mismatch"))
+    );
+  }
+
+  #[test]
+  fn test_metadata_round_trip() {
+    let mut nested = Metadata::new();
+    nested.insert("inner".to_string(), Value::from(vec![1, 2]));
+    let mut metadata = Metadata::new();
+    metadata.insert("title".to_string(), Value::from("Hello"));
+    metadata.insert("score".to_string(), Value::from(0.5));
+    metadata.insert("count".to_string(), Value::from(-3));
+    metadata.insert("draft".to_string(), Value::from(false));
+    metadata.insert("missing".to_string(), Value::Null);
+    metadata.insert("nested".to_string(), Value::from(nested));
+    let payloads = [metadata, Metadata::new()];
+
+    let bytes = encode_metadata(["title"].into_iter(), payloads.iter());
+    let (fields, decoded) = decode_metadata(&bytes, 2).unwrap();
+    assert_eq!(fields, ["title"]);
+    assert_eq!(decoded, payloads);
+    assert!(decode_metadata(&bytes[..bytes.len() - 1], 2).is_err());
+    assert!(decode_metadata(&bytes, 1).is_err());
+  }
+}
@@ -0,0 +1,433 @@
+use super::format::{corrupted, decode_metadata, Header, HEADER_LEN};
+use super::{check_element, check_file_len, new_database};
+use crate::distance::MetricKind;
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::metadata::Metadata;
+use crate::neighbors;
+use crate::vector_database::{check_vector, SearchResult, VectorId};
+use memmap2::Mmap;
+use ndarray::{ArrayView1, ArrayView2};
+use std::collections::HashMap;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// A read-only database backed by a memory-mapped file written by
+/// [`VectorDatabase::save`](crate::vector_database::VectorDatabase::save).
+///
+/// Vectors are read in place from the mapping, so opening the file does not
+/// read them and processes mapping the same file share a single copy in the
+/// page cache. Only the IDs and metadata are decoded when the file is opened.
+/// Searches return the same results as on the
+/// [`VectorDatabase`](crate::vector_database::VectorDatabase) that was saved.
+/// Vectors are read as the element type `T` they were saved as, `f64` by
+/// default.
+///
+/// The vector checksum is not verified when opening, since that would read
+/// every vector; call [`Self::verify`] to check it.
+///
+/// The file must not be modified while it is mapped. Saving a database over
+/// it is safe, since [`VectorDatabase::save`] replaces the file instead of
+/// writing into it, but the mapping keeps showing the old contents.
+///
+/// [`VectorDatabase::save`]: crate::vector_database::VectorDatabase::save
+///
+/// # Examples
+///
+/// ```
+/// use rustyvectors::storage::MappedDatabase;
+/// use rustyvectors::vector_database::VectorDatabase;
+///
+/// # let dir = tempfile::tempdir().unwrap();
+/// # let path = dir.path().join("vectors.rvdb");
+/// let mut db = VectorDatabase::new(2)?;
+/// let id = db.add(&[1.0, 2.0])?;
+/// db.save(&path)?;
+///
+/// let mapped = MappedDatabase::open(&path)?;
+/// assert_eq!(mapped.get(id).unwrap().to_vec(), [1.0, 2.0]);
+/// assert_eq!(mapped.nearest(&[1.0, 1.5])?, Some(id));
+/// # Ok::<(), rustyvectors::Error>(())
+/// ```
+pub struct MappedDatabase<T: Element = f64> {
+  mmap: Mmap,
+  header: Header,
+  /// The position in the file of each stored ID.
+  positions: HashMap<VectorId, usize>,
+  /// The payload of each vector, by position.
+  payloads: Vec<Metadata>,
+  element: PhantomData<T>,
+}
+
+impl MappedDatabase {
+  /// Maps the database file at `path`, which must store `f64` vectors.
+  ///
+  /// Returns the same errors as
+  /// [`VectorDatabase::open`](crate::vector_database::VectorDatabase::open),
+  /// except that a damaged vector section is only detected by
+  /// [`Self::verify`]. Returns [`Error::InvalidValue`] on big-endian targets,
+  /// which cannot read the little-endian vectors in place.
+  pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+    Self::open_with_element_type(path)
+  }
+}
+
+impl<T: Element> MappedDatabase<T> {
+  /// Maps the database file at `path`, which must store vectors of type `T`.
+  ///
+  /// See [`MappedDatabase::open`].
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::distance::MetricKind;
+  /// use rustyvectors::storage::MappedDatabase;
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// # let dir = tempfile::tempdir().unwrap();
+  /// # let path = dir.path().join("vectors.rvdb");
+  /// let mut db = VectorDatabase::<f32>::with_element_type(2, MetricKind::Euclidean)?;
+  /// let id = db.add(&[1.0, 2.0])?;
+  /// db.save(&path)?;
+  ///
+  /// let mapped = MappedDatabase::<f32>::open_with_element_type(&path)?;
+  /// assert_eq!(mapped.nearest(&[1.0, 1.5])?, Some(id));
+  /// assert!(MappedDatabase::open(&path).is_err());
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn open_with_element_type(path: impl AsRef<Path>) -> Result<Self> {
+    if cfg!(target_endian = "big") {
+      return Err(Error::InvalidValue(
+        "memory-mapped databases require a little-endian target".to_string(),
+      ));
+    }
+    let file = File::open(path)?;
+    // SAFETY: the mapping is only read, and callers are told not to modify
+    // the file while it is mapped.
+    let mmap = unsafe { Mmap::map(&file)? };
+    let Some(header) = mmap.get(..HEADER_LEN) else {
+      return Err(corrupted("file is truncated"));
+    };
+    let header = Header::decode(header.try_into().unwrap())?;
+    check_file_len(&header, mmap.len() as u64)?;
+    check_element::<T>(&header)?;
+    new_database::<T>(&header)?;
+
+    let metadata = &mmap[header.metadata_offset() as usize..];
+    if crc32fast::hash(metadata) != header.metadata_crc {
+      return Err(corrupted("metadata checksum mismatch"));
+    }
+    let (_, payloads) = decode_metadata(metadata, header.count)?;
+
+    let mut positions = HashMap::with_capacity(header.count);
+    let ids = &mmap[HEADER_LEN..header.vectors_offset() as usize];
+    for (position, id) in ids.chunks_exact(8).enumerate() {
+      let id = VectorId::from_le_bytes(id.try_into().unwrap());
+      if positions.insert(id, position).is_some() {
+        return Err(Error::Corrupted(format!("duplicate vector id {id}")));
+      }
+    }
+
+    Ok(Self {
+      mmap,
+      header,
+      positions,
+      payloads,
+      element: PhantomData,
+    })
+  }
+
+  /// Returns the number of components of the stored vectors.
+  pub fn dimension(&self) -> usize {
+    self.header.dimension
+  }
+
+  /// Returns the metric used to compare vectors.
+  pub fn metric(&self) -> MetricKind {
+    self.header.metric
+  }
+
+  /// Returns the number of vectors in the database.
+  pub fn len(&self) -> usize {
+    self.header.count
+  }
+
+  /// Returns `true` if the database contains no vectors.
+  pub fn is_empty(&self) -> bool {
+    self.header.count == 0
+  }
+
+  /// Returns `true` if a vector with the given ID is stored.
+  pub fn contains(&self, id: VectorId) -> bool {
+    self.positions.contains_key(&id)
+  }
+
+  /// Returns the IDs of the stored vectors, in no particular order.
+  pub fn ids(&self) -> impl Iterator<Item = VectorId> + '_ {
+    (0..self.len()).map(|position| self.id(position))
+  }
+
+  /// Checks the vectors against the checksum stored in the file, reading all
+  /// of them.
+  ///
+  /// Returns [`Error::Corrupted`] if they do not match.
+  pub fn verify(&self) -> Result<()> {
+    let sections =
+      &self.mmap[HEADER_LEN..self.header.metadata_offset() as usize];
+    if crc32fast::hash(sections) != self.header.vectors_crc {
+      return Err(corrupted("vector data checksum mismatch"));
+    }
+    Ok(())
+  }
+
+  /// Retrieves a vector by its ID, as a view into the mapped file.
+  pub fn get(&self, id: VectorId) -> Option<ArrayView1<'_, T>> {
+    self.positions.get(&id).map(|&position| self.row(position))
+  }
+
+  /// Retrieves the metadata payload of a vector by its ID.
+  pub fn metadata(&self, id: VectorId) -> Option<&Metadata> {
+    self
+      .positions
+      .get(&id)
+      .map(|&position| &self.payloads[position])
+  }
+
+  /// Finds the ID of the nearest vector to the given query vector.
+  ///
+  /// See [`VectorDatabase::nearest`](crate::vector_database::VectorDatabase::nearest).
+  pub fn nearest(&self, query: &[T]) -> Result<Option<VectorId>> {
+    Ok(self.k_nearest(query, 1)?.first().map(|&(id, _)| id))
+  }
+
+  /// Finds the `k` vectors closest to the given query vector.
+  ///
+  /// See [`VectorDatabase::k_nearest`](crate::vector_database::VectorDatabase::k_nearest).
+  pub fn k_nearest(
+    &self,
+    query: &[T],
+    k: usize,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    check_vector(query, self.dimension())?;
+    let rows =
+      ArrayView2::from_shape((self.len(), self.dimension()), self.vectors())
+        .expect("the vector section holds whole rows");
+    let query = ArrayView1::from(query);
+    Ok(neighbors::scan(
+      &self.header.metric,
+      query,
+      k,
+      rows,
+      |position| self.id(position),
+    ))
+  }
+
+  /// Finds the `k` vectors closest to the given query vector among those
+  /// whose metadata matches `filter`.
+  ///
+  /// Secondary indexes are not kept for mapped files, so every payload is
+  /// checked against the filter during the scan.
+  pub fn k_nearest_filtered(
+    &self,
+    query: &[T],
+    k: usize,
+    filter: &Filter,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    check_vector(query, self.dimension())?;
+    let matching = (0..self.len())
+      .filter(|&position| filter.matches(&self.payloads[position]));
+    Ok(self.top_k(query, k, matching))
+  }
+
+  /// Finds the `k` vectors closest to the given query vector along with their
+  /// metadata.
+  pub fn search(&self, query: &[T], k: usize) -> Result<Vec<SearchResult>> {
+    Ok(self.with_metadata(self.k_nearest(query, k)?))
+  }
+
+  /// Finds the `k` vectors closest to the given query vector among those
+  /// whose metadata matches `filter`, along with their metadata.
+  pub fn search_filtered(
+    &self,
+    query: &[T],
+    k: usize,
+    filter: &Filter,
+  ) -> Result<Vec<SearchResult>> {
+    Ok(self.with_metadata(self.k_nearest_filtered(query, k, filter)?))
+  }
+
+  fn with_metadata(
+    &self,
+    neighbors: Vec<(VectorId, f64)>,
+  ) -> Vec<SearchResult> {
+    neighbors
+      .into_iter()
+      .map(|(id, distance)| SearchResult {
+        id,
+        distance,
+        metadata: self.metadata(id).cloned().unwrap_or_default(),
+      })
+      .collect()
+  }
+
+  fn top_k(
+    &self,
+    query: &[T],
+    k: usize,
+    positions: impl Iterator<Item = usize>,
+  ) -> Vec<(VectorId, f64)> {
+    let rows =
+      positions.map(|position| (self.id(position), self.row(position)));
+    neighbors::top_k(&self.header.metric, ArrayView1::from(query), k, rows)
+  }
+
+  fn id(&self, position: usize) -> VectorId {
+    let offset = HEADER_LEN + position * 8;
+    VectorId::from_le_bytes(self.mmap[offset..offset + 8].try_into().unwrap())
+  }
+
+  fn row(&self, position: usize) -> ArrayView1<'_, T> {
+    let dimension = self.dimension();
+    ArrayView1::from(&self.vectors()[position * dimension..][..dimension])
+  }
+
+  /// Returns the vector section as a slice of elements.
+  fn vectors(&self) -> &[T] {
+    let start = self.header.vectors_offset() as usize;
+    let bytes = &self.mmap[start..self.header.metadata_offset() as usize];
+    let pointer = bytes.as_ptr().cast::<T>();
+    assert!(pointer.is_aligned(), "mapped vectors are not aligned");
+    // SAFETY: the pointer is aligned and valid for `bytes.len()` bytes, which
+    // the file layout makes a multiple of the size of `T` since `open`
+    // checked that the file stores `T`s; any bit pattern is a valid element,
+    // and `open` rejects big-endian targets, on which the little-endian
+    // components would be misread.
+    unsafe { std::slice::from_raw_parts(pointer, bytes.len() / T::SIZE) }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::metadata::Value;
+  use crate::vector_database::VectorDatabase;
+
+  fn saved_db(
+    metric: MetricKind,
+  ) -> (tempfile::TempDir, std::path::PathBuf, VectorDatabase) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.rvdb");
+    let mut db = VectorDatabase::with_metric(4, metric).unwrap();
+    for i in 0..200 {
+      let mut metadata = Metadata::new();
+      metadata.insert("bucket".to_string(), Value::from(i % 5));
+      let vector = [(i % 13) as f64, (i % 7) as f64, (i / 10) as f64, 1.0];
+      db.add_with_metadata(&vector, metadata).unwrap();
+    }
+    for id in (0..200).step_by(9) {
+      db.remove(id);
+    }
+    db.save(&path).unwrap();
+    (dir, path, db)
+  }
+
+  #[test]
+  fn test_searches_match_the_in_memory_database() {
+    for metric in [
+      MetricKind::Euclidean,
+      MetricKind::Cosine,
+      MetricKind::DotProduct,
+      MetricKind::Manhattan,
+    ] {
+      let (_dir, path, db) = saved_db(metric);
+      let mapped = MappedDatabase::open(&path).unwrap();
+      mapped.verify().unwrap();
+      assert_eq!(mapped.len(), db.len());
+      assert_eq!(mapped.metric(), metric);
+
+      let filter = Filter::eq("bucket", 3);
+      for query in [[0.0, 0.0, 0.0, 1.0], [5.5, 2.0, 9.0, -1.0]] {
+        assert_eq!(mapped.nearest(&query), db.nearest(&query));
+        assert_eq!(mapped.k_nearest(&query, 10), db.k_nearest(&query, 10));
+        assert_eq!(
+          mapped.search_filtered(&query, 10, &filter),
+          db.search_filtered(&query, 10, &filter)
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_rows_are_read_in_place() {
+    let (_dir, path, db) = saved_db(MetricKind::Euclidean);
+    let mapped = MappedDatabase::open(&path).unwrap();
+    let range = mapped.mmap.as_ptr_range();
+    for id in db.ids() {
+      let row = mapped.get(id).unwrap();
+      assert_eq!(row, db.get(id).unwrap());
+      assert!(range.contains(&row.as_ptr().cast()));
+      assert_eq!(mapped.metadata(id), db.metadata(id));
+    }
+    assert_eq!(mapped.get(0), None);
+    assert_eq!(
+      mapped.k_nearest(&[1.0], 1),
+      Err(Error::DimensionMismatch {
+        expected: 4,
+        found: 1
+      })
+    );
+  }
+
+  #[test]
+  fn test_half_precision_rows_are_read_in_place() {
+    use crate::element::f16;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.rvdb");
+    let mut db =
+      VectorDatabase::<f16>::with_element_type(3, MetricKind::Euclidean)
+        .unwrap();
+    for i in 0..50 {
+      let vector = [i as f32, (i % 7) as f32, 0.5].map(f16::from_f32);
+      db.add(&vector).unwrap();
+    }
+    db.save(&path).unwrap();
+
+    let mapped = MappedDatabase::<f16>::open_with_element_type(&path).unwrap();
+    mapped.verify().unwrap();
+    let range = mapped.mmap.as_ptr_range();
+    for id in db.ids() {
+      let row = mapped.get(id).unwrap();
+      assert_eq!(row, db.get(id).unwrap());
+      assert!(range.contains(&row.as_ptr().cast()));
+    }
+    let query = [20.2, 3.0, 0.0].map(f16::from_f32);
+    assert_eq!(mapped.k_nearest(&query, 5), db.k_nearest(&query, 5));
+    assert!(matches!(
+      MappedDatabase::open(&path),
+      Err(Error::InvalidValue(_))
+    ));
+  }
+
+  #[test]
+  fn test_damaged_vectors_fail_verification() {
+    let (_dir, path, _) = saved_db(MetricKind::Euclidean);
+    let mut bytes = std::fs::read(&path).unwrap();
+    let header = Header::decode(bytes[..HEADER_LEN].try_into().unwrap());
+    bytes[header.unwrap().vectors_offset() as usize + 3] ^= 1;
+    std::fs::write(&path, &bytes).unwrap();
+
+    let mapped = MappedDatabase::open(&path).unwrap();
+    assert_eq!(
+      mapped.verify(),
+      Err(corrupted("vector data checksum mismatch"))
+    );
+    std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+    assert_eq!(
+      MappedDatabase::open(&path).err(),
+      Some(corrupted("file is truncated"))
+    );
+  }
+}
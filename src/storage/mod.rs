@@ -0,0 +1,444 @@
+//! Persistence of a [`VectorDatabase`] to a single file.
+//!
+//! Besides saving and loading whole databases, this module provides
+//! [`MappedDatabase`], for searching a saved database without loading it, and
+//! [`DurableDatabase`], which records every change in a write-ahead log so
+//! that none is lost between saves and compacts it into new snapshots.
+//!
+//! [`VectorDatabase::save`] writes the database to a file that
+//! [`VectorDatabase::open`] loads back. All integers and floats are stored
+//! little-endian, and the file is laid out as follows:
+//!
+//! | Offset | Size | Content                                              |
+//! |--------|------|------------------------------------------------------|
+//! | 0      | 8    | The magic bytes [`MAGIC`]                            |
+//! | 8      | 4    | The format version, [`FORMAT_VERSION`]               |
+//! | 12     | 4    | Reserved, zero                                       |
+//! | 16     | 8    | The dimension                                        |
+//! | 24     | 8    | The number of vectors, `count`                       |
+//! | 32     | 8    | The next ID to be assigned                           |
+//! | 40     | 4    | The metric tag                                       |
+//! | 44     | 4    | The element type tag                                 |
+//! | 48     | 8    | The order of a Minkowski metric as an `f64`, else zero |
+//! | 56     | 4    | The CRC-32 of the ID and vector sections             |
+//! | 60     | 4    | The CRC-32 of the metadata section                   |
+//! | 64     | 8    | The length of the metadata section                   |
+//! | 72     | 4    | The CRC-32 of the first 72 bytes                     |
+//! | 76     | 4    | Reserved, zero                                       |
+//! | 80     | `8 × count` | The IDs, as `u64`s                            |
+//! |        | `size × count × dimension` | The vectors, as rows of elements |
+//! |        |      | The metadata section                                 |
+//!
+//! The metric tag is 0 for the Euclidean distance, 1 for the cosine
+//! similarity, 2 for the dot product, 3 for the Manhattan distance, 4 for the
+//! Chebyshev distance and 5 for a Minkowski distance. The element type tag is
+//! 0 for `f64`, 1 for `f32`, 2 for [`f16`](struct@crate::element::f16) and 3
+//! for [`bf16`](crate::element::bf16), whose `size` is 8, 4, 2 and 2 bytes.
+//! A database can only be loaded or mapped as the element type it was saved
+//! with.
+//!
+//! The metadata section starts with the names of the fields that have a
+//! secondary index, as a `u32` count followed by the names, and then holds
+//! the payload of each vector in the order of the ID section. Strings are
+//! stored as a `u32` byte length followed by UTF-8 bytes, and a payload as a
+//! `u32` number of entries followed by each key and value. A value starts
+//! with a tag byte: 0 null, 1 boolean (one byte), 2 integer (`i64`), 3 float
+//! (`f64`), 4 string, 5 list (a `u32` length followed by the values) and 6 map
+//! (encoded like a payload).
+//!
+//! The vector section starts at a multiple of 8 bytes, so that
+//! [`MappedDatabase`] can read the vectors in place from a memory-mapped file.
+//!
+//! [`DurableDatabase`] only stores `f64` vectors, since its log records them
+//! as such.
+//!
+//! Files written with another format version are rejected with
+//! [`Error::UnsupportedVersion`](crate::Error::UnsupportedVersion), and
+//! files whose checksums or structure do not match with
+//! [`Error::Corrupted`](crate::Error::Corrupted).
+
+mod durable;
+mod format;
+mod mmap;
+mod wal;
+
+pub use durable::{Compaction, DurableDatabase};
+pub use format::{FORMAT_VERSION, MAGIC};
+pub use mmap::MappedDatabase;
+pub use wal::{Recovery, SyncPolicy};
+
+pub(crate) use format::Component;
+
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::vector_database::{VectorDatabase, VectorId};
+use format::{
+  component_type, corrupted, decode_metadata, encode_metadata, Header,
+  HEADER_LEN,
+};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes `db` to a temporary file next to `path` and then renames it over
+/// `path`, so that a failed save never leaves a partially written database.
+pub(crate) fn save<T: Element>(
+  db: &VectorDatabase<T>,
+  path: &Path,
+) -> Result<()> {
+  let temporary = temporary_path(path);
+  let result = write_file(db, &temporary)
+    .and_then(|()| fs::rename(&temporary, path).map_err(Error::from));
+  if result.is_err() {
+    let _ = fs::remove_file(&temporary);
+    return result;
+  }
+  sync_parent(path)
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+  let mut name = path.file_name().map_or_else(OsString::new, OsString::from);
+  name.push(".tmp");
+  path.with_file_name(name)
+}
+
+/// Makes a rename within the directory of `path` durable.
+fn sync_parent(path: &Path) -> Result<()> {
+  #[cfg(unix)]
+  if let Some(parent) = path.parent() {
+    let parent = if parent.as_os_str().is_empty() {
+      Path::new(".")
+    } else {
+      parent
+    };
+    File::open(parent)?.sync_all()?;
+  }
+  #[cfg(not(unix))]
+  let _ = path;
+  Ok(())
+}
+
+fn write_file<T: Element>(db: &VectorDatabase<T>, path: &Path) -> Result<()> {
+  let metadata = encode_metadata(
+    db.indexed_fields(),
+    db.records().map(|(_, _, metadata)| metadata),
+  );
+  let mut file = File::create(path)?;
+  let mut writer = BufWriter::new(&mut file);
+  writer.write_all(&[0; HEADER_LEN])?;
+  let mut hasher = crc32fast::Hasher::new();
+  let (vectors, ids) = db.matrix();
+  for id in ids {
+    let bytes = id.to_le_bytes();
+    hasher.update(&bytes);
+    writer.write_all(&bytes)?;
+  }
+  let mut bytes = Vec::with_capacity(db.dimension() * T::SIZE);
+  for row in vectors.outer_iter() {
+    bytes.clear();
+    for &component in row {
+      component.put(&mut bytes);
+    }
+    hasher.update(&bytes);
+    writer.write_all(&bytes)?;
+  }
+  writer.write_all(&metadata)?;
+  writer.flush()?;
+  drop(writer);
+
+  let header = Header {
+    dimension: db.dimension(),
+    count: db.len(),
+    next_id: db.next_id(),
+    metric: db.metric(),
+    element: T::TAG,
+    vectors_crc: hasher.finalize(),
+    metadata_crc: crc32fast::hash(&metadata),
+    metadata_len: metadata.len() as u64,
+  };
+  file.seek(SeekFrom::Start(0))?;
+  file.write_all(&header.encode())?;
+  file.sync_all()?;
+  Ok(())
+}
+
+/// Reads a database written by [`save`] with the same element type.
+pub(crate) fn open<T: Element>(path: &Path) -> Result<VectorDatabase<T>> {
+  let file = File::open(path)?;
+  let file_len = file.metadata()?.len();
+  let mut reader = BufReader::new(file);
+  let mut header = [0; HEADER_LEN];
+  read_exact(&mut reader, &mut header)?;
+  let header = Header::decode(&header)?;
+  check_file_len(&header, file_len)?;
+  check_element::<T>(&header)?;
+
+  let mut hasher = crc32fast::Hasher::new();
+  let mut ids = Vec::with_capacity(header.count);
+  let mut bytes = [0; 8];
+  for _ in 0..header.count {
+    read_exact(&mut reader, &mut bytes)?;
+    hasher.update(&bytes);
+    ids.push(VectorId::from_le_bytes(bytes));
+  }
+  let mut vectors = Vec::with_capacity(header.count);
+  let mut row = Vec::new();
+  if header.count > 0 {
+    // The file length check bounds the dimension once there is a vector.
+    row.resize(header.dimension * T::SIZE, 0);
+  }
+  for _ in 0..header.count {
+    read_exact(&mut reader, &mut row)?;
+    hasher.update(&row);
+    vectors.push(decode_row(&row));
+  }
+  if hasher.finalize() != header.vectors_crc {
+    return Err(corrupted("vector data checksum mismatch"));
+  }
+
+  let mut metadata = vec![0; header.metadata_len as usize];
+  read_exact(&mut reader, &mut metadata)?;
+  if crc32fast::hash(&metadata) != header.metadata_crc {
+    return Err(corrupted("metadata checksum mismatch"));
+  }
+  let (fields, payloads) = decode_metadata(&metadata, header.count)?;
+
+  let records = ids.into_iter().zip(vectors).zip(payloads);
+  let mut db = new_database(&header)?;
+  db.reserve(header.count);
+  for ((id, vector), metadata) in records {
+    insert_record(&mut db, &header, id, &vector, metadata)?;
+  }
+  for field in &fields {
+    db.create_index(field);
+  }
+  Ok(db)
+}
+
+/// Returns an error unless the file holds vectors of type `T`.
+pub(crate) fn check_element<T: Element>(header: &Header) -> Result<()> {
+  if header.element != T::TAG {
+    let name = |tag| component_type(tag).expect("a known tag").0;
+    return Err(Error::InvalidValue(format!(
+      "the file stores {} vectors, not {}",
+      name(header.element),
+      name(T::TAG)
+    )));
+  }
+  Ok(())
+}
+
+/// Returns an error unless the file is exactly as long as `header` says.
+pub(crate) fn check_file_len(header: &Header, file_len: u64) -> Result<()> {
+  match file_len.cmp(&header.file_len()) {
+    std::cmp::Ordering::Less => Err(corrupted("file is truncated")),
+    std::cmp::Ordering::Greater => {
+      Err(corrupted("unexpected bytes at the end of the file"))
+    }
+    std::cmp::Ordering::Equal => Ok(()),
+  }
+}
+
+/// Creates an empty database with the dimension and metric of `header`.
+pub(crate) fn new_database<T: Element>(
+  header: &Header,
+) -> Result<VectorDatabase<T>> {
+  VectorDatabase::with_element_type(header.dimension, header.metric)
+    .map_err(|error| Error::Corrupted(format!("invalid header: {error}")))
+}
+
+/// Inserts a record read from a file, reporting invalid records as
+/// corruption.
+pub(crate) fn insert_record<T: Element>(
+  db: &mut VectorDatabase<T>,
+  header: &Header,
+  id: VectorId,
+  vector: &[T],
+  metadata: crate::metadata::Metadata,
+) -> Result<()> {
+  if id >= header.next_id {
+    return Err(Error::Corrupted(format!(
+      "vector id {id} is not below the next id {}",
+      header.next_id
+    )));
+  }
+  db.insert_with_id(id, vector, metadata)
+    .map_err(|error| Error::Corrupted(format!("invalid vector: {error}")))?;
+  db.advance_next_id(header.next_id);
+  Ok(())
+}
+
+/// Decodes a row of little-endian components.
+pub(crate) fn decode_row<T: Element>(bytes: &[u8]) -> Vec<T> {
+  bytes.chunks_exact(T::SIZE).map(T::from_le_bytes).collect()
+}
+
+/// Fills `buffer`, reporting a premature end of file as corruption.
+fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> Result<()> {
+  reader.read_exact(buffer).map_err(|error| {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+      corrupted("file is truncated")
+    } else {
+      Error::from(error)
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::distance::MetricKind;
+  use crate::filter::Filter;
+  use crate::metadata::{Metadata, Value};
+
+  fn sample_db() -> VectorDatabase {
+    let mut db =
+      VectorDatabase::with_metric(3, MetricKind::Minkowski(3.0)).unwrap();
+    for i in 0..20 {
+      let mut metadata = Metadata::new();
+      metadata.insert("parity".to_string(), Value::from(i % 2));
+      metadata.insert("name".to_string(), Value::from(format!("v{i}")));
+      db.add_with_metadata(&[i as f64, (i * i) as f64, -0.5], metadata)
+        .unwrap();
+    }
+    db.remove(3);
+    db.remove(19);
+    db.create_index("parity");
+    db
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.rvdb");
+    let db = sample_db();
+    db.save(&path).unwrap();
+
+    let mut loaded = VectorDatabase::open(&path).unwrap();
+    assert_eq!(loaded.dimension(), 3);
+    assert_eq!(loaded.metric(), MetricKind::Minkowski(3.0));
+    assert_eq!(loaded.len(), 18);
+    assert!(loaded.has_index("parity"));
+    for id in db.ids() {
+      assert_eq!(loaded.get(id), db.get(id));
+      assert_eq!(loaded.metadata(id), db.metadata(id));
+    }
+    let filter = Filter::eq("parity", 1);
+    assert_eq!(
+      loaded
+        .search_filtered(&[4.0, 4.0, 0.0], 5, &filter)
+        .unwrap(),
+      db.search_filtered(&[4.0, 4.0, 0.0], 5, &filter).unwrap()
+    );
+    assert_eq!(loaded.add(&[0.0; 3]).unwrap(), 20);
+    assert!(!dir.path().join("db.rvdb.tmp").exists());
+  }
+
+  #[test]
+  fn test_empty_database_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("empty.rvdb");
+    VectorDatabase::new(5).unwrap().save(&path).unwrap();
+    let loaded = VectorDatabase::open(&path).unwrap();
+    assert_eq!(loaded.dimension(), 5);
+    assert!(loaded.is_empty());
+  }
+
+  #[test]
+  fn test_element_types_round_trip() {
+    use crate::element::{bf16, f16};
+
+    fn round_trip<T: Element>(path: &Path) {
+      let mut db =
+        VectorDatabase::<T>::with_element_type(2, MetricKind::Cosine).unwrap();
+      for i in 0..10 {
+        db.add(&[T::from_f64(i as f64), T::from_f64(-0.5)]).unwrap();
+      }
+      db.remove(4);
+      db.save(path).unwrap();
+      let loaded = VectorDatabase::<T>::open_with_element_type(path).unwrap();
+      assert_eq!(loaded.metric(), MetricKind::Cosine);
+      assert_eq!(loaded.len(), 9);
+      for id in db.ids() {
+        assert_eq!(loaded.get(id), db.get(id));
+      }
+      let expected_len = HEADER_LEN + 9 * 8 + 9 * 2 * T::SIZE + 4 + 9 * 4;
+      assert_eq!(fs::metadata(path).unwrap().len(), expected_len as u64);
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.rvdb");
+    round_trip::<f32>(&path);
+    round_trip::<bf16>(&path);
+    round_trip::<f16>(&path);
+    assert_eq!(
+      VectorDatabase::open(&path).err(),
+      Some(Error::InvalidValue(
+        "the file stores f16 vectors, not f64".to_string()
+      ))
+    );
+    round_trip::<f64>(&path);
+    assert!(VectorDatabase::<f32>::open_with_element_type(&path).is_err());
+  }
+
+  #[test]
+  fn test_invalid_files_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.rvdb");
+    sample_db().save(&path).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    let open_with = |bytes: &[u8]| {
+      fs::write(&path, bytes).unwrap();
+      VectorDatabase::open(&path)
+    };
+
+    let mut wrong_magic = bytes.clone();
+    wrong_magic[0] = b'X';
+    assert_eq!(
+      open_with(&wrong_magic).err(),
+      Some(corrupted("not a rustyvectors database file"))
+    );
+
+    let mut newer = bytes.clone();
+    newer[8..12].copy_from_slice(&2_u32.to_le_bytes());
+    assert_eq!(
+      open_with(&newer).err(),
+      Some(Error::UnsupportedVersion {
+        found: 2,
+        supported: FORMAT_VERSION
+      })
+    );
+
+    let mut flipped = bytes.clone();
+    flipped[HEADER_LEN + 20 * 8] ^= 0x40;
+    assert_eq!(
+      open_with(&flipped).err(),
+      Some(corrupted("vector data checksum mismatch"))
+    );
+
+    let mut damaged_metadata = bytes.clone();
+    *damaged_metadata.last_mut().unwrap() ^= 1;
+    assert_eq!(
+      open_with(&damaged_metadata).err(),
+      Some(corrupted("metadata checksum mismatch"))
+    );
+
+    for len in [40, HEADER_LEN + 8, bytes.len() - 1] {
+      assert_eq!(
+        open_with(&bytes[..len]).err(),
+        Some(corrupted("file is truncated"))
+      );
+    }
+  }
+
+  #[test]
+  fn test_missing_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let error = VectorDatabase::open(dir.path().join("missing")).err();
+    assert_eq!(
+      error,
+      Some(Error::from(io::Error::from(io::ErrorKind::NotFound)))
+    );
+  }
+}
@@ -0,0 +1,445 @@
+//! The write-ahead log of a [`DurableDatabase`](super::DurableDatabase).
+//!
+//! A log file starts with a 16-byte header: the magic bytes `RSTYWAL\0`,
+//! the format version as a little-endian `u32` and four reserved zero bytes.
+//! It is followed by records, each made of the `u32` length and `u32` CRC-32
+//! of its payload, then the payload itself. A payload starts with a tag byte:
+//!
+//! - 1, an insertion: the `u64` ID, the `f64` components and the metadata,
+//! - 2, a removal: the `u64` ID,
+//! - 3, a metadata replacement: the `u64` ID and the new metadata,
+//! - 4, the creation of a secondary index: the field name,
+//! - 5, the removal of a secondary index: the field name,
+//! - 6, a vector replacement: the `u64` ID and the new `f64` components,
+//! - 7, an upsert: the `u64` ID, the `f64` components and the metadata,
+//!
+//! with metadata and strings encoded as in database files.
+
+use super::format::{corrupted, put_map, put_str, Decoder};
+use super::FORMAT_VERSION;
+use crate::error::{Error, Result};
+use crate::metadata::Metadata;
+use crate::vector_database::{VectorDatabase, VectorId};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The first bytes of every log file.
+const WAL_MAGIC: [u8; 8] = *b"RSTYWAL\0";
+
+const WAL_HEADER_LEN: u64 = 16;
+
+/// The size of the length and checksum preceding each record.
+const RECORD_HEADER_LEN: usize = 8;
+
+/// When a [`DurableDatabase`](super::DurableDatabase) flushes its log to
+/// stable storage.
+///
+/// Changes that were not flushed yet are lost if the machine crashes, but
+/// not if only the process does, since they have already been handed to the
+/// operating system.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+  /// Flush after every change, so that no acknowledged change is ever lost.
+  #[default]
+  Always,
+  /// Flush after every `n` changes, losing at most the last `n - 1` ones.
+  Batch(usize),
+  /// Flush on the first change made at least the given duration after the
+  /// previous flush. Changes followed by no other change stay unflushed until
+  /// [`sync`](super::DurableDatabase::sync) is called or the database is
+  /// dropped.
+  Interval(Duration),
+}
+
+/// What was found when replaying a log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recovery {
+  /// The number of records replayed.
+  pub replayed: usize,
+  /// The number of bytes at the end of the log that did not hold a complete
+  /// record, left by a write interrupted by a crash, and were discarded.
+  pub discarded_bytes: u64,
+}
+
+/// A change to a database, as stored in the log.
+pub(crate) enum Record {
+  Insert {
+    id: VectorId,
+    vector: Vec<f64>,
+    metadata: Metadata,
+  },
+  Remove(VectorId),
+  SetMetadata(VectorId, Metadata),
+  CreateIndex(String),
+  DropIndex(String),
+  Update {
+    id: VectorId,
+    vector: Vec<f64>,
+  },
+  Upsert {
+    id: VectorId,
+    vector: Vec<f64>,
+    metadata: Metadata,
+  },
+}
+
+/// Encodes the payload of a record. The vector and metadata are borrowed so
+/// that changes can be logged without copying them.
+pub(crate) enum RecordRef<'a> {
+  Insert {
+    id: VectorId,
+    vector: &'a [f64],
+    metadata: &'a Metadata,
+  },
+  Remove(VectorId),
+  SetMetadata(VectorId, &'a Metadata),
+  CreateIndex(&'a str),
+  DropIndex(&'a str),
+  Update {
+    id: VectorId,
+    vector: &'a [f64],
+  },
+  Upsert {
+    id: VectorId,
+    vector: &'a [f64],
+    metadata: &'a Metadata,
+  },
+}
+
+impl RecordRef<'_> {
+  fn encode(&self) -> Vec<u8> {
+    let mut bytes = vec![0; RECORD_HEADER_LEN];
+    match self {
+      RecordRef::Insert {
+        id,
+        vector,
+        metadata,
+      } => {
+        bytes.push(1);
+        bytes.extend_from_slice(&id.to_le_bytes());
+        for component in *vector {
+          bytes.extend_from_slice(&component.to_le_bytes());
+        }
+        put_map(&mut bytes, metadata);
+      }
+      RecordRef::Remove(id) => {
+        bytes.push(2);
+        bytes.extend_from_slice(&id.to_le_bytes());
+      }
+      RecordRef::SetMetadata(id, metadata) => {
+        bytes.push(3);
+        bytes.extend_from_slice(&id.to_le_bytes());
+        put_map(&mut bytes, metadata);
+      }
+      RecordRef::CreateIndex(field) => {
+        bytes.push(4);
+        put_str(&mut bytes, field);
+      }
+      RecordRef::DropIndex(field) => {
+        bytes.push(5);
+        put_str(&mut bytes, field);
+      }
+      RecordRef::Update { id, vector } => {
+        bytes.push(6);
+        bytes.extend_from_slice(&id.to_le_bytes());
+        for component in *vector {
+          bytes.extend_from_slice(&component.to_le_bytes());
+        }
+      }
+      RecordRef::Upsert {
+        id,
+        vector,
+        metadata,
+      } => {
+        bytes.push(7);
+        bytes.extend_from_slice(&id.to_le_bytes());
+        for component in *vector {
+          bytes.extend_from_slice(&component.to_le_bytes());
+        }
+        put_map(&mut bytes, metadata);
+      }
+    }
+    let payload = &bytes[RECORD_HEADER_LEN..];
+    let len = u32::try_from(payload.len()).expect("log record too large");
+    let crc = crc32fast::hash(payload);
+    bytes[0..4].copy_from_slice(&len.to_le_bytes());
+    bytes[4..8].copy_from_slice(&crc.to_le_bytes());
+    bytes
+  }
+}
+
+impl Record {
+  fn decode(payload: &[u8], dimension: usize) -> Result<Self> {
+    let mut decoder = Decoder::new(payload);
+    let record = match decoder.u8()? {
+      1 => Record::Insert {
+        id: decoder.u64()?,
+        vector: (0..dimension)
+          .map(|_| decoder.f64())
+          .collect::<Result<_>>()?,
+        metadata: decoder.metadata()?,
+      },
+      2 => Record::Remove(decoder.u64()?),
+      3 => Record::SetMetadata(decoder.u64()?, decoder.metadata()?),
+      4 => Record::CreateIndex(decoder.string()?),
+      5 => Record::DropIndex(decoder.string()?),
+      6 => Record::Update {
+        id: decoder.u64()?,
+        vector: (0..dimension)
+          .map(|_| decoder.f64())
+          .collect::<Result<_>>()?,
+      },
+      7 => Record::Upsert {
+        id: decoder.u64()?,
+        vector: (0..dimension)
+          .map(|_| decoder.f64())
+          .collect::<Result<_>>()?,
+        metadata: decoder.metadata()?,
+      },
+      tag => {
+        return Err(Error::Corrupted(format!("unknown log record tag {tag}")))
+      }
+    };
+    decoder.finish()?;
+    Ok(record)
+  }
+
+  /// Applies the change to `db`, reporting changes that cannot apply as
+  /// corruption.
+  pub(crate) fn apply(self, db: &mut VectorDatabase) -> Result<()> {
+    let result = match self {
+      Record::Insert {
+        id,
+        vector,
+        metadata,
+      } => db.insert_with_id(id, &vector, metadata),
+      Record::Remove(id) => {
+        db.remove(id).map(|_| ()).ok_or(Error::NotFound(id))
+      }
+      Record::SetMetadata(id, metadata) => {
+        db.set_metadata(id, metadata).map(|_| ())
+      }
+      Record::CreateIndex(field) => {
+        db.create_index(&field);
+        Ok(())
+      }
+      Record::DropIndex(field) => {
+        db.drop_index(&field);
+        Ok(())
+      }
+      Record::Update { id, vector } => db.update(id, &vector).map(|_| ()),
+      Record::Upsert {
+        id,
+        vector,
+        metadata,
+      } => db.upsert(id, &vector, metadata).map(|_| ()),
+    };
+    result.map_err(|error| {
+      Error::Corrupted(format!("log record does not apply: {error}"))
+    })
+  }
+}
+
+/// An append-only log file.
+pub(crate) struct Wal {
+  file: File,
+  /// The length of the valid part of the file, where the next record goes.
+  len: u64,
+  policy: SyncPolicy,
+  /// The number of records appended since the last flush.
+  unsynced: usize,
+  last_sync: Instant,
+}
+
+impl Wal {
+  /// Creates an empty log at `path`, failing if a file already exists.
+  pub fn create(path: &Path, policy: SyncPolicy) -> Result<Self> {
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create_new(true)
+      .open(path)?;
+    let mut header = [0; WAL_HEADER_LEN as usize];
+    header[0..8].copy_from_slice(&WAL_MAGIC);
+    header[8..12].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    file.write_all(&header)?;
+    file.sync_all()?;
+    Ok(Self::new(file, WAL_HEADER_LEN, policy))
+  }
+
+  fn new(file: File, len: u64, policy: SyncPolicy) -> Self {
+    Self {
+      file,
+      len,
+      policy,
+      unsynced: 0,
+      last_sync: Instant::now(),
+    }
+  }
+
+  /// Opens the log at `path` and replays its records into `db`.
+  ///
+  /// An incomplete record at the end of the log, as left by a crash in the
+  /// middle of a write, is discarded, and so are the zero bytes a crash may
+  /// leave past the last record. Any other invalid record, including one
+  /// running past the end of the file with valid records after it, is
+  /// reported as [`Error::Corrupted`] and leaves the file untouched.
+  pub fn replay(
+    path: &Path,
+    policy: SyncPolicy,
+    db: &mut VectorDatabase,
+  ) -> Result<(Self, Recovery)> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    if bytes.len() < WAL_HEADER_LEN as usize || bytes[0..8] != WAL_MAGIC {
+      return Err(corrupted("not a rustyvectors log file"));
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    if version != FORMAT_VERSION {
+      return Err(Error::UnsupportedVersion {
+        found: version,
+        supported: FORMAT_VERSION,
+      });
+    }
+
+    let mut recovery = Recovery::default();
+    let mut offset = WAL_HEADER_LEN as usize;
+    while offset < bytes.len() {
+      let rest = &bytes[offset..];
+      let Some(header) = rest.get(..RECORD_HEADER_LEN) else {
+        break;
+      };
+      let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+      let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+      // A torn write can only damage the last record, or leave zeros where
+      // the file was extended but not written.
+      let zeros = rest.iter().all(|&byte| byte == 0);
+      let Some(payload) = rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)
+      else {
+        // The length may be damaged rather than the record cut short.
+        if zeros || !holds_record(&rest[RECORD_HEADER_LEN..]) {
+          break;
+        }
+        return Err(Error::Corrupted(format!(
+          "log record at offset {offset} is damaged"
+        )));
+      };
+      let end = offset + RECORD_HEADER_LEN + len;
+      let torn = end == bytes.len() || zeros;
+      // Payloads hold at least a tag, so an empty one, whose checksum is 0,
+      // cannot have been written.
+      if len == 0 || crc32fast::hash(payload) != crc {
+        if torn {
+          break;
+        }
+        return Err(Error::Corrupted(format!(
+          "log record at offset {offset} is damaged"
+        )));
+      }
+      let record = match Record::decode(payload, db.dimension()) {
+        Ok(record) => record,
+        Err(_) if torn => break,
+        Err(error) => return Err(error),
+      };
+      record.apply(db)?;
+      recovery.replayed += 1;
+      offset = end;
+    }
+
+    recovery.discarded_bytes = (bytes.len() - offset) as u64;
+    if recovery.discarded_bytes > 0 {
+      file.set_len(offset as u64)?;
+      file.sync_all()?;
+    }
+    file.seek(SeekFrom::Start(offset as u64))?;
+    Ok((Self::new(file, offset as u64, policy), recovery))
+  }
+
+  /// Appends a record and flushes the log if the policy calls for it.
+  ///
+  /// If writing fails, the log is cut back to its previous length so that a
+  /// partial record does not hide the records appended after it.
+  pub fn append(&mut self, record: RecordRef) -> Result<()> {
+    self.append_all([record])
+  }
+
+  /// Appends records with a single write, flushing the log at most once
+  /// after them if the policy calls for it.
+  ///
+  /// Each record counts as one change for the policy. A crash during the
+  /// write may keep only the first records, but never a partial one.
+  pub fn append_all<'a>(
+    &mut self,
+    records: impl IntoIterator<Item = RecordRef<'a>>,
+  ) -> Result<()> {
+    let mut bytes = Vec::new();
+    let mut count = 0;
+    for record in records {
+      bytes.extend_from_slice(&record.encode());
+      count += 1;
+    }
+    if let Err(error) = self.file.write_all(&bytes) {
+      let _ = self.file.set_len(self.len);
+      let _ = self.file.seek(SeekFrom::Start(self.len));
+      return Err(error.into());
+    }
+    self.len += bytes.len() as u64;
+    self.unsynced += count;
+    let due = match self.policy {
+      SyncPolicy::Always => true,
+      SyncPolicy::Batch(n) => self.unsynced >= n,
+      SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+    };
+    if due {
+      self.sync()?;
+    }
+    Ok(())
+  }
+
+  /// Flushes the appended records to stable storage.
+  pub fn sync(&mut self) -> Result<()> {
+    if self.unsynced > 0 {
+      self.file.sync_data()?;
+      self.unsynced = 0;
+    }
+    self.last_sync = Instant::now();
+    Ok(())
+  }
+
+  pub fn policy(&self) -> SyncPolicy {
+    self.policy
+  }
+
+  pub fn set_policy(&mut self, policy: SyncPolicy) {
+    self.policy = policy;
+  }
+
+  /// Returns the size of the log file in bytes.
+  pub fn len(&self) -> u64 {
+    self.len
+  }
+}
+
+impl Drop for Wal {
+  fn drop(&mut self) {
+    let _ = self.sync();
+  }
+}
+
+/// Returns whether a whole record with a matching checksum starts anywhere in
+/// `bytes`, which a torn write cannot leave behind an incomplete record.
+fn holds_record(bytes: &[u8]) -> bool {
+  (0..bytes.len()).any(|start| {
+    let rest = &bytes[start..];
+    let Some(header) = rest.get(..RECORD_HEADER_LEN) else {
+      return false;
+    };
+    let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let payload = rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len);
+    len > 0 && payload.is_some_and(|payload| crc32fast::hash(payload) == crc)
+  })
+}
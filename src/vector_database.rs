@@ -1,179 +1,1872 @@
-use crate::distance::euclidean;
-use ndarray::{Array1, ArrayView1};
+use crate::distance::MetricKind;
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::filter::{FieldIndex, Filter};
+use crate::index::{check_radius, VectorIndex};
+use crate::matrix::Matrix;
+use crate::metadata::Metadata;
+use crate::neighbors;
+use crate::storage;
+use ndarray::{Array1, ArrayBase, ArrayView1, ArrayView2, Data, Ix2};
+#[cfg(feature = "rayon")]
+use rayon::ThreadPool;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+#[cfg(feature = "rayon")]
+use std::sync::Arc;
 use std::vec::Vec;
 
-pub struct VectorDatabase {
-  vectors: Vec<Array1<f64>>,
+/// The largest fraction of the database a filter's indexed candidates may
+/// cover for a filtered search to visit only them instead of scanning
+/// everything.
+const INDEX_SELECTIVITY: f64 = 0.1;
+
+/// The identifier assigned to a vector when it is added to a
+/// [`VectorDatabase`].
+///
+/// IDs are never reused, so an ID keeps referring to the same vector (or to
+/// nothing once it is removed) regardless of other insertions and removals.
+pub type VectorId = u64;
+
+/// A vector returned by [`VectorDatabase::search`], along with its payload.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchResult {
+  pub id: VectorId,
+  /// The metric's score between the query and the vector.
+  pub distance: f64,
+  pub metadata: Metadata,
 }
 
-impl Default for VectorDatabase {
-  fn default() -> Self {
-    Self::new()
-  }
+/// A collection of vectors of a fixed dimension, searchable by similarity.
+///
+/// Vector components are stored as `T`, which is `f64` unless the database
+/// is created with [`Self::with_element_type`]; see the
+/// [`element`](crate::element) module for the other types.
+pub struct VectorDatabase<T: Element = f64> {
+  /// The stored vectors, one per row in no particular order.
+  vectors: Matrix<T>,
+  /// The ID of the vector at the same position in `vectors`.
+  ids: Vec<VectorId>,
+  /// The payload of the vector at the same position in `vectors`.
+  payloads: Vec<Metadata>,
+  /// The position in `vectors` of each stored ID.
+  positions: HashMap<VectorId, usize>,
+  /// Secondary indexes over metadata fields, by field name.
+  indexes: BTreeMap<String, FieldIndex>,
+  /// The approximate nearest neighbor index kept in sync with the vectors.
+  index: Option<Box<dyn VectorIndex>>,
+  /// The thread pool exact searches run on, instead of the global one.
+  #[cfg(feature = "rayon")]
+  thread_pool: Option<Arc<ThreadPool>>,
+  next_id: VectorId,
+  dimension: usize,
+  metric: MetricKind,
 }
 
 impl VectorDatabase {
-  /// Creates a new `VectorDatabase`.
+  /// Creates a new `VectorDatabase` holding vectors of `dimension`
+  /// components.
+  ///
+  /// Returns [`Error::InvalidValue`] if `dimension` is zero.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let db = VectorDatabase::new(3)?;
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn new(dimension: usize) -> Result<Self> {
+    Self::with_metric(dimension, MetricKind::default())
+  }
+
+  /// Creates a new `VectorDatabase` holding vectors of `dimension`
+  /// components and comparing them with `metric`.
+  ///
+  /// Returns [`Error::InvalidValue`] if `dimension` is zero or if the order
+  /// of a [`MetricKind::Minkowski`] metric is not positive.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::distance::MetricKind;
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let db = VectorDatabase::with_metric(3, MetricKind::Cosine)?;
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn with_metric(dimension: usize, metric: MetricKind) -> Result<Self> {
+    Self::with_element_type(dimension, metric)
+  }
+
+  /// Loads a database of `f64` vectors written by [`Self::save`].
+  ///
+  /// Returns [`Error::Io`] if the file cannot be read,
+  /// [`Error::UnsupportedVersion`] if it was written in another version of
+  /// the file format, [`Error::Corrupted`] if it is not a database file, is
+  /// truncated or fails its checksums, and [`Error::InvalidValue`] if it
+  /// stores another element type.
+  pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+    Self::open_with_element_type(path)
+  }
+}
+
+impl<T: Element> VectorDatabase<T> {
+  /// Writes the database to the file at `path`, replacing it if it exists.
+  ///
+  /// The vectors, their IDs and metadata, the element type, the metric and
+  /// the secondary indexes are saved in the format described in the
+  /// [`storage`](crate::storage) module. The attached approximate nearest
+  /// neighbor index is not saved. The file is written next to `path` first
+  /// and then renamed, so an existing file is left intact if saving fails.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// # let dir = tempfile::tempdir().unwrap();
+  /// # let path = dir.path().join("vectors.rvdb");
+  /// let mut db = VectorDatabase::new(3)?;
+  /// let id = db.add(&[1.0, 2.0, 3.0])?;
+  /// db.save(&path)?;
+  ///
+  /// let db = VectorDatabase::open(&path)?;
+  /// assert_eq!(db.nearest(&[1.0, 2.0, 3.0])?, Some(id));
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+    storage::save(self, path.as_ref())
+  }
+
+  /// Loads a database of vectors of type `T` written by [`Self::save`].
+  ///
+  /// Returns the same errors as [`VectorDatabase::open`].
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::distance::MetricKind;
+  /// use rustyvectors::element::f16;
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// # let dir = tempfile::tempdir().unwrap();
+  /// # let path = dir.path().join("vectors.rvdb");
+  /// let mut db = VectorDatabase::<f16>::with_element_type(2, MetricKind::Cosine)?;
+  /// let id = db.add(&[f16::from_f32(1.0), f16::from_f32(2.0)])?;
+  /// db.save(&path)?;
+  ///
+  /// let db = VectorDatabase::<f16>::open_with_element_type(&path)?;
+  /// assert_eq!(db.get(id).unwrap()[1], f16::from_f32(2.0));
+  /// assert!(VectorDatabase::open(&path).is_err());
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn open_with_element_type(path: impl AsRef<Path>) -> Result<Self> {
+    storage::open(path.as_ref())
+  }
+
+  /// Creates a new `VectorDatabase` storing vectors of `dimension`
+  /// components as `T` and comparing them with `metric`.
+  ///
+  /// Returns the errors of [`VectorDatabase::with_metric`].
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::distance::MetricKind;
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::<f32>::with_element_type(2, MetricKind::Cosine)?;
+  /// let id = db.add(&[0.6, 0.8])?;
+  /// assert_eq!(db.nearest(&[3.0, 4.0])?, Some(id));
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn with_element_type(
+    dimension: usize,
+    metric: MetricKind,
+  ) -> Result<Self> {
+    if dimension == 0 {
+      return Err(Error::InvalidValue(
+        "dimension must be at least 1".to_string(),
+      ));
+    }
+    if let MetricKind::Minkowski(p) = metric {
+      if p.is_nan() || p <= 0.0 {
+        return Err(Error::InvalidValue(format!(
+          "Minkowski order must be positive, got {p}"
+        )));
+      }
+    }
+
+    Ok(Self {
+      vectors: Matrix::new(dimension),
+      ids: Vec::new(),
+      payloads: Vec::new(),
+      positions: HashMap::new(),
+      indexes: BTreeMap::new(),
+      index: None,
+      #[cfg(feature = "rayon")]
+      thread_pool: None,
+      next_id: 0,
+      dimension,
+      metric,
+    })
+  }
+
+  /// Returns the number of components of the stored vectors.
+  pub fn dimension(&self) -> usize {
+    self.dimension
+  }
+
+  /// Returns the metric used to compare vectors.
+  pub fn metric(&self) -> MetricKind {
+    self.metric
+  }
+
+  /// Returns the number of vectors in the database.
+  pub fn len(&self) -> usize {
+    self.vectors.len()
+  }
+
+  /// Returns `true` if the database contains no vectors.
+  pub fn is_empty(&self) -> bool {
+    self.vectors.is_empty()
+  }
+
+  /// Returns `true` if a vector with the given ID is stored.
+  pub fn contains(&self, id: VectorId) -> bool {
+    self.positions.contains_key(&id)
+  }
+
+  /// Returns the IDs of the stored vectors, in no particular order.
+  pub fn ids(&self) -> impl Iterator<Item = VectorId> + '_ {
+    self.ids.iter().copied()
+  }
+
+  /// Returns the number of vectors the database can hold without
+  /// reallocating its storage.
+  pub fn capacity(&self) -> usize {
+    self.vectors.capacity()
+  }
+
+  /// Reserves room for at least `additional` more vectors, so that adding
+  /// them does not reallocate the storage.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// db.reserve(1000);
+  /// assert!(db.capacity() >= 1000);
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn reserve(&mut self, additional: usize) {
+    self.vectors.reserve(additional);
+    self.ids.reserve(additional);
+    self.payloads.reserve(additional);
+    self.positions.reserve(additional);
+  }
+
+  /// Adds a vector to the database and returns its ID.
+  ///
+  /// Returns [`Error::DimensionMismatch`] if the vector does not have the
+  /// database's dimension and [`Error::InvalidValue`] if one of its components
+  /// is not finite.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// let id = db.add(&[1.0, 2.0, 3.0])?;
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn add(&mut self, vector: &[T]) -> Result<VectorId> {
+    self.add_with_metadata(vector, Metadata::new())
+  }
+
+  /// Adds a vector along with a metadata payload and returns its ID.
+  ///
+  /// The vector is validated as in [`Self::add`].
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::metadata::{Metadata, Value};
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// let mut metadata = Metadata::new();
+  /// metadata.insert("title".to_string(), Value::from("Hello"));
+  /// let id = db.add_with_metadata(&[1.0, 2.0, 3.0], metadata)?;
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn add_with_metadata(
+    &mut self,
+    vector: &[T],
+    metadata: Metadata,
+  ) -> Result<VectorId> {
+    let id = self.next_id;
+    self.insert_with_id(id, vector, metadata)?;
+    Ok(id)
+  }
+
+  /// Adds every row of `vectors` to the database and returns their IDs, in
+  /// the order of the rows.
+  ///
+  /// All the rows are validated as in [`Self::add`] before any of them is
+  /// added, so that an invalid row leaves the database unchanged. The storage
+  /// grows once for the whole batch, and the attached index, if any, indexes
+  /// the vectors together, which lets it build faster than from vectors added
+  /// one at a time. The vectors get no metadata.
   ///
   /// # Examples
   ///
   /// ```
+  /// use ndarray::arr2;
   /// use rustyvectors::vector_database::VectorDatabase;
   ///
-  /// let db = VectorDatabase::new();
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let ids = db.add_batch(&arr2(&[[1.0, 2.0], [3.0, 4.0]]))?;
+  /// assert_eq!(ids, [0, 1]);
+  /// assert!(db.add_batch(&arr2(&[[5.0, 6.0], [f64::NAN, 0.0]])).is_err());
+  /// assert_eq!(db.len(), 2);
+  /// # Ok::<(), rustyvectors::Error>(())
   /// ```
-  pub fn new() -> Self {
+  pub fn add_batch<S: Data<Elem = T>>(
+    &mut self,
+    vectors: &ArrayBase<S, Ix2>,
+  ) -> Result<Vec<VectorId>> {
+    let vectors = vectors.as_standard_layout();
+    let rows = vectors.as_slice().expect("standard layout");
+    self.check_rows(rows, vectors.ncols())?;
+    self.extend(rows)
+  }
+
+  /// Adds every vector of `vectors` to the database and returns their IDs, in
+  /// order.
+  ///
+  /// This behaves like [`Self::add_batch`], for vectors that are not already
+  /// in a matrix.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let vectors = vec![vec![1.0, 2.0], vec![3.0, 4.0]];
+  /// assert_eq!(db.add_batch_iter(&vectors)?, [0, 1]);
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn add_batch_iter<I>(&mut self, vectors: I) -> Result<Vec<VectorId>>
+  where
+    I: IntoIterator,
+    I::Item: AsRef<[T]>,
+  {
+    let vectors = vectors.into_iter();
+    let mut rows = Vec::with_capacity(vectors.size_hint().0 * self.dimension);
+    for vector in vectors {
+      let vector = vector.as_ref();
+      self.check_vector(vector)?;
+      rows.extend_from_slice(vector);
+    }
+    self.extend(&rows)
+  }
+
+  /// Checks that `rows` holds vectors of `columns` components, stored one
+  /// after the other, that can all be added under new IDs.
+  pub(crate) fn check_rows(&self, rows: &[T], columns: usize) -> Result<()> {
+    if columns != self.dimension {
+      return Err(Error::DimensionMismatch {
+        expected: self.dimension,
+        found: columns,
+      });
+    }
+    for vector in rows.chunks_exact(self.dimension) {
+      self.check_vector(vector)?;
+    }
+    let count = (rows.len() / self.dimension) as VectorId;
+    if self.next_id.checked_add(count).is_none() {
+      return Err(Error::InvalidValue("ids are out of range".to_string()));
+    }
+    Ok(())
+  }
+
+  /// Adds the validated vectors stored one after the other in `rows`, without
+  /// metadata, under consecutive new IDs.
+  fn extend(&mut self, rows: &[T]) -> Result<Vec<VectorId>> {
+    let count = rows.len() / self.dimension;
+    let first = self.next_id;
+    let next_id = first + count as VectorId;
+    let ids: Vec<_> = (first..next_id).collect();
+    if let Some(index) = &mut self.index {
+      let widened = T::widen(rows);
+      let vectors = ArrayView2::from_shape((count, self.dimension), &widened)
+        .expect("the rows are whole");
+      index.insert_batch(&ids, vectors)?;
+    }
+    self.advance_next_id(next_id);
+    self.reserve(count);
+    for &id in &ids {
+      self.positions.insert(id, self.ids.len());
+      self.ids.push(id);
+      self.payloads.push(Metadata::new());
+    }
+    self.vectors.extend(rows);
+    Ok(ids)
+  }
+
+  /// Stores a vector under an ID chosen by the caller, as when loading a
+  /// database, and makes sure that ID is never assigned again.
+  ///
+  /// Returns [`Error::InvalidValue`] if the ID is already in use.
+  pub(crate) fn insert_with_id(
+    &mut self,
+    id: VectorId,
+    vector: &[T],
+    metadata: Metadata,
+  ) -> Result<()> {
+    self.check_vector(vector)?;
+    if self.contains(id) {
+      return Err(Error::InvalidValue(format!("id {id} is already in use")));
+    }
+    let next_id = next_id_after(id)?;
+    if let Some(index) = &mut self.index {
+      index.insert(id, ArrayView1::from(&*T::widen(vector)))?;
+    }
+    self.advance_next_id(next_id);
+    for (field, index) in &mut self.indexes {
+      index.insert(field, id, &metadata);
+    }
+    self.positions.insert(id, self.vectors.len());
+    self.vectors.push(vector);
+    self.ids.push(id);
+    self.payloads.push(metadata);
+    Ok(())
+  }
+
+  /// Returns the ID the next added vector will get.
+  pub(crate) fn next_id(&self) -> VectorId {
+    self.next_id
+  }
+
+  /// Makes sure no ID below `next_id` is assigned by [`Self::add`].
+  pub(crate) fn advance_next_id(&mut self, next_id: VectorId) {
+    self.next_id = self.next_id.max(next_id);
+  }
+
+  /// Returns the ID, vector and payload of every stored vector, in storage
+  /// order.
+  pub(crate) fn records(
+    &self,
+  ) -> impl Iterator<Item = (VectorId, ArrayView1<'_, T>, &Metadata)> + '_ {
+    self.ids.iter().zip(&self.payloads).enumerate().map(
+      |(position, (&id, metadata))| (id, self.vectors.row(position), metadata),
+    )
+  }
+
+  /// Returns the stored vectors as a matrix with one vector per row, in
+  /// storage order, along with the ID of each row.
+  pub(crate) fn matrix(&self) -> (ArrayView2<'_, T>, &[VectorId]) {
+    (self.vectors.view(), &self.ids)
+  }
+
+  /// Returns a copy of the database without its attached index.
+  pub(crate) fn copy_without_index(&self) -> Self {
     Self {
-      vectors: Vec::new(),
+      vectors: self.vectors.clone(),
+      ids: self.ids.clone(),
+      payloads: self.payloads.clone(),
+      positions: self.positions.clone(),
+      indexes: self.indexes.clone(),
+      index: None,
+      #[cfg(feature = "rayon")]
+      thread_pool: self.thread_pool.clone(),
+      next_id: self.next_id,
+      dimension: self.dimension,
+      metric: self.metric,
     }
   }
 
-  /// Adds a vector to the database.
+  /// Returns the names of the fields that have a secondary index.
+  pub(crate) fn indexed_fields(
+    &self,
+  ) -> impl ExactSizeIterator<Item = &str> + '_ {
+    self.indexes.keys().map(String::as_str)
+  }
+
+  /// Removes a vector from the database by its ID.
+  ///
+  /// The IDs of the remaining vectors are unaffected.
   ///
   /// # Examples
   ///
   /// ```
   /// use rustyvectors::vector_database::VectorDatabase;
   ///
-  /// let mut db = VectorDatabase::new();
-  /// db.add(&[1.0, 2.0, 3.0]);
+  /// let mut db = VectorDatabase::new(3)?;
+  /// let id = db.add(&[1.0, 2.0, 3.0])?;
+  /// let removed_vector = db.remove(id);
+  /// # Ok::<(), rustyvectors::Error>(())
   /// ```
-  pub fn add(&mut self, vector: &[f64]) {
-    let new_vector =
-      Array1::from_shape_vec(vector.len(), vector.to_vec()).unwrap();
-    self.vectors.push(new_vector);
+  pub fn remove(&mut self, id: VectorId) -> Option<Array1<T>> {
+    let position = self.positions.remove(&id)?;
+    if let Some(index) = &mut self.index {
+      index.remove(id);
+    }
+    let vector = self.vectors.swap_remove(position);
+    self.ids.swap_remove(position);
+    let metadata = self.payloads.swap_remove(position);
+    for (field, index) in &mut self.indexes {
+      index.remove(field, id, &metadata);
+    }
+    if let Some(&moved) = self.ids.get(position) {
+      self.positions.insert(moved, position);
+    }
+    Some(vector)
   }
 
-  // Removes a vector from the database by its index.
+  /// Replaces the vector stored under `id`, keeping its ID and payload, and
+  /// returns the previous vector.
+  ///
+  /// The vector is validated as in [`Self::add`]. Returns [`Error::NotFound`]
+  /// if no vector has the given ID. The attached index, if any, is updated
+  /// too.
   ///
   /// # Examples
   ///
   /// ```
   /// use rustyvectors::vector_database::VectorDatabase;
   ///
-  /// let mut db = VectorDatabase::new();
-  /// db.add(&[1.0, 2.0, 3.0]);
-  /// let removed_vector = db.remove(0);
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let id = db.add(&[1.0, 2.0])?;
+  /// let previous = db.update(id, &[3.0, 4.0])?;
+  /// assert_eq!(previous.to_vec(), [1.0, 2.0]);
+  /// assert_eq!(db.nearest(&[3.0, 4.0])?, Some(id));
+  /// # Ok::<(), rustyvectors::Error>(())
   /// ```
-  pub fn remove(&mut self, index: usize) -> Option<Array1<f64>> {
-    if index < self.vectors.len() {
-      Some(self.vectors.remove(index))
-    } else {
-      None
+  pub fn update(&mut self, id: VectorId, vector: &[T]) -> Result<Array1<T>> {
+    self.check_vector(vector)?;
+    let &position = self.positions.get(&id).ok_or(Error::NotFound(id))?;
+    if let Some(index) = &mut self.index {
+      index.insert(id, ArrayView1::from(&*T::widen(vector)))?;
     }
+    Ok(self.vectors.replace(position, vector))
   }
 
-  /// Finds the index of the nearest vector to the given query vector.
+  /// Stores a vector and its payload under `id`, replacing the ones already
+  /// stored under it, and returns whether there were any.
+  ///
+  /// If no vector has the given ID, the vector is added under it, and
+  /// [`Self::add`] never assigns that ID afterwards. This can bring back the
+  /// ID of a removed vector. The vector is validated as in [`Self::add`], and
+  /// the attached index, if any, is updated too.
   ///
   /// # Examples
   ///
   /// ```
+  /// use rustyvectors::metadata::{Metadata, Value};
   /// use rustyvectors::vector_database::VectorDatabase;
   ///
-  /// let mut db = VectorDatabase::new();
-  /// db.add(&[1.0, 2.0, 3.0]);
-  /// db.add(&[4.0, 5.0, 6.0]);
-  /// let index = db.nearest(&[1.0, 2.0, 3.0]);
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let mut metadata = Metadata::new();
+  /// metadata.insert("version".to_string(), Value::from(1));
+  /// assert!(!db.upsert(7, &[1.0, 2.0], metadata.clone())?);
+  /// metadata.insert("version".to_string(), Value::from(2));
+  /// assert!(db.upsert(7, &[3.0, 4.0], metadata)?);
+  /// assert_eq!(db.metadata(7).unwrap()["version"], Value::from(2));
+  /// assert_eq!(db.add(&[0.0, 0.0])?, 8);
+  /// # Ok::<(), rustyvectors::Error>(())
   /// ```
-  pub fn nearest(&self, query: &[f64]) -> Option<usize> {
-    if self.vectors.is_empty() {
-      return None;
+  pub fn upsert(
+    &mut self,
+    id: VectorId,
+    vector: &[T],
+    metadata: Metadata,
+  ) -> Result<bool> {
+    if !self.contains(id) {
+      self.insert_with_id(id, vector, metadata)?;
+      return Ok(false);
     }
+    self.update(id, vector)?;
+    self.set_metadata(id, metadata)?;
+    Ok(true)
+  }
 
-    let query_array = ArrayView1::from(query);
-    let (index, _) = self
-      .vectors
+  /// Finds the ID of the nearest vector to the given query vector.
+  ///
+  /// This is a shorthand for the first result of [`Self::k_nearest`] and
+  /// follows the same ordering rules and validation.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// db.add(&[1.0, 2.0, 3.0])?;
+  /// db.add(&[4.0, 5.0, 6.0])?;
+  /// let id = db.nearest(&[1.0, 2.0, 3.0])?;
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn nearest(&self, query: &[T]) -> Result<Option<VectorId>> {
+    Ok(self.k_nearest(query, 1)?.first().map(|&(id, _)| id))
+  }
+
+  /// Finds the `k` vectors closest to the given query vector.
+  ///
+  /// Returns up to `k` `(id, distance)` pairs ordered from closest to
+  /// farthest. The distance is the score of the database's metric, so for
+  /// similarities such as [`MetricKind::Cosine`] it decreases along the list.
+  /// Only a heap of `k` candidates is kept during the scan, so the collection
+  /// is never sorted as a whole. With the `rayon` feature, large databases
+  /// are scanned in parallel, on the pool given to `set_thread_pool`
+  /// or else on the global rayon pool, with the same results.
+  ///
+  /// Equal distances are ordered by ascending ID. A `NaN` distance is
+  /// considered farther than any other distance, so such vectors are only
+  /// returned when there are fewer than `k` comparable ones.
+  ///
+  /// The query is validated like the vectors passed to [`Self::add`].
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// db.add(&[1.0, 2.0, 3.0])?;
+  /// let id = db.add(&[4.0, 5.0, 6.0])?;
+  /// db.add(&[7.0, 8.0, 9.0])?;
+  /// let neighbors = db.k_nearest(&[4.0, 5.0, 6.0], 2)?;
+  /// assert_eq!(neighbors[0], (id, 0.0));
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn k_nearest(
+    &self,
+    query: &[T],
+    k: usize,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    self.check_vector(query)?;
+    Ok(self.scan(query, k))
+  }
+
+  /// Finds the `k` vectors closest to the given query vector among those
+  /// whose metadata matches `filter`.
+  ///
+  /// The filter is applied while scanning, so up to `k` results are returned
+  /// as long as enough vectors match. When the filter involves fields with a
+  /// secondary index (see [`Self::create_index`]) and narrows the search down
+  /// to a small part of the database, only the indexed candidates are
+  /// visited. Results are ordered as in [`Self::k_nearest`].
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::filter::Filter;
+  /// use rustyvectors::metadata::{Metadata, Value};
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let mut metadata = Metadata::new();
+  /// metadata.insert("lang".to_string(), Value::from("fr"));
+  /// let id = db.add_with_metadata(&[5.0, 5.0], metadata)?;
+  /// db.add(&[0.0, 0.0])?;
+  /// let neighbors = db.k_nearest_filtered(&[0.0, 0.0], 1, &Filter::eq("lang", "fr"))?;
+  /// assert_eq!(neighbors[0].0, id);
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn k_nearest_filtered(
+    &self,
+    query: &[T],
+    k: usize,
+    filter: &Filter,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    self.check_vector(query)?;
+    let selective = filter.candidates(&self.indexes).filter(|ids| {
+      ids.len() as f64 <= self.vectors.len() as f64 * INDEX_SELECTIVITY
+    });
+    let matching = |&position: &usize| filter.matches(&self.payloads[position]);
+
+    Ok(match selective {
+      Some(ids) => {
+        let positions = ids.iter().filter_map(|id| self.positions.get(id));
+        self.top_k(query, k, positions.copied().filter(matching))
+      }
+      None => self.top_k(query, k, (0..self.vectors.len()).filter(matching)),
+    })
+  }
+
+  /// Finds approximately the `k` vectors closest to the given query vector
+  /// using the attached index (see [`Self::set_index`]).
+  ///
+  /// Without an attached index this is an exact search, as in
+  /// [`Self::k_nearest`]. The query is validated the same way.
+  pub fn k_nearest_approx(
+    &self,
+    query: &[T],
+    k: usize,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    self.check_vector(query)?;
+    match &self.index {
+      Some(index) => index.search(ArrayView1::from(&*T::widen(query)), k),
+      None => Ok(self.scan(query, k)),
+    }
+  }
+
+  /// Finds every vector within `radius` of the given query vector, or the
+  /// closest `limit` of them if there is a limit.
+  ///
+  /// Returns `(id, distance)` pairs ordered as in [`Self::k_nearest`]. For
+  /// similarities such as [`MetricKind::Cosine`], vectors are within the
+  /// radius if their score is at least `radius`. Vectors with a `NaN`
+  /// distance are never within it.
+  ///
+  /// The query is validated like the vectors passed to [`Self::add`], and
+  /// [`Error::InvalidValue`] is returned if `radius` is `NaN`.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let a = db.add(&[1.0, 0.0])?;
+  /// let b = db.add(&[0.0, 0.5])?;
+  /// db.add(&[3.0, 4.0])?;
+  /// assert_eq!(db.within_radius(&[0.0, 0.0], 1.0, None)?, [(b, 0.5), (a, 1.0)]);
+  /// assert_eq!(db.within_radius(&[0.0, 0.0], 1.0, Some(1))?, [(b, 0.5)]);
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn within_radius(
+    &self,
+    query: &[T],
+    radius: f64,
+    limit: Option<usize>,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    self.check_vector(query)?;
+    check_radius(radius)?;
+    Ok(self.scan_within(query, radius, limit))
+  }
+
+  /// Finds approximately the vectors within `radius` of the given query
+  /// vector using the attached index (see [`Self::set_index`]), or the
+  /// closest `limit` of them if there is a limit.
+  ///
+  /// Without an attached index this is an exact search, as in
+  /// [`Self::within_radius`]. The query and radius are validated the same
+  /// way.
+  pub fn within_radius_approx(
+    &self,
+    query: &[T],
+    radius: f64,
+    limit: Option<usize>,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    self.check_vector(query)?;
+    check_radius(radius)?;
+    match &self.index {
+      Some(index) => {
+        let query = T::widen(query);
+        index.search_within(ArrayView1::from(&*query), radius, limit)
+      }
+      None => Ok(self.scan_within(query, radius, limit)),
+    }
+  }
+
+  /// Finds the `k` vectors closest to the given query vector by re-scoring
+  /// the best `candidates` results of the attached index exactly.
+  ///
+  /// This recovers exact scores, and usually the exact ranking, from indexes
+  /// that only approximate them, such as [`Pq`](crate::index::Pq). Without an
+  /// attached index this is an exact search, as in [`Self::k_nearest`].
+  /// `candidates` is raised to `k` if smaller.
+  pub fn k_nearest_reranked(
+    &self,
+    query: &[T],
+    k: usize,
+    candidates: usize,
+  ) -> Result<Vec<(VectorId, f64)>> {
+    self.check_vector(query)?;
+    let Some(index) = &self.index else {
+      return Ok(self.scan(query, k));
+    };
+    let widened = T::widen(query);
+    let candidates =
+      index.search(ArrayView1::from(&*widened), candidates.max(k))?;
+    let positions = candidates
       .iter()
-      .enumerate()
-      .map(|(i, v)| (i, euclidean(&v.view(), &query_array)))
-      .min_by(|&(_, dist1), &(_, dist2)| {
-        dist1
-          .partial_cmp(&dist2)
-          .unwrap_or(std::cmp::Ordering::Equal)
-      })?;
+      .filter_map(|(id, _)| self.positions.get(id).copied());
+    Ok(self.top_k(query, k, positions))
+  }
 
-    Some(index)
+  /// Attaches an approximate nearest neighbor index, replacing any attached
+  /// one.
+  ///
+  /// The index must hold exactly the vectors of the database, as built for
+  /// instance by [`Hnsw::build`](crate::index::Hnsw::build), and use the same
+  /// dimension and metric; otherwise [`Error::InvalidValue`] is returned.
+  /// From then on, the database keeps it up to date as vectors are added and
+  /// removed, and [`Self::k_nearest_approx`] and
+  /// [`Self::within_radius_approx`] use it.
+  pub fn set_index(&mut self, index: impl VectorIndex + 'static) -> Result<()> {
+    if index.dimension() != self.dimension || index.metric() != self.metric {
+      return Err(Error::InvalidValue(
+        "index dimension and metric must match the database".to_string(),
+      ));
+    }
+    if index.len() != self.len() || !self.ids().all(|id| index.contains(id)) {
+      return Err(Error::InvalidValue(
+        "index must contain exactly the vectors of the database".to_string(),
+      ));
+    }
+    self.index = Some(Box::new(index));
+    Ok(())
   }
 
-  /// Retrieves a vector from the database by its index.
+  /// Returns the attached approximate nearest neighbor index, if any.
+  pub fn index(&self) -> Option<&dyn VectorIndex> {
+    self.index.as_deref()
+  }
+
+  /// Detaches and returns the approximate nearest neighbor index, if any.
+  pub fn take_index(&mut self) -> Option<Box<dyn VectorIndex>> {
+    self.index.take()
+  }
+
+  /// Scores the vectors at `positions` against `query` and keeps the best `k`
+  /// in a bounded heap.
+  fn top_k(
+    &self,
+    query: &[T],
+    k: usize,
+    positions: impl Iterator<Item = usize>,
+  ) -> Vec<(VectorId, f64)> {
+    let rows = positions
+      .map(|position| (self.ids[position], self.vectors.row(position)));
+    neighbors::top_k(&self.metric, ArrayView1::from(query), k, rows)
+  }
+
+  /// Makes exact searches run on `pool` instead of the global rayon thread
+  /// pool, with the `rayon` feature.
+  ///
+  /// A pool with fewer threads than the machine has cores keeps searches from
+  /// competing with the rest of the application for all of them.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  /// use std::sync::Arc;
+  ///
+  /// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+  /// let mut db = VectorDatabase::new(2)?;
+  /// db.set_thread_pool(Arc::new(pool));
+  /// let id = db.add(&[1.0, 2.0])?;
+  /// assert_eq!(db.nearest(&[1.0, 2.5])?, Some(id));
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  #[cfg(feature = "rayon")]
+  pub fn set_thread_pool(&mut self, pool: Arc<ThreadPool>) {
+    self.thread_pool = Some(pool);
+  }
+
+  /// Returns the thread pool exact searches run on, or `None` if they run on
+  /// the global one.
+  #[cfg(feature = "rayon")]
+  pub fn thread_pool(&self) -> Option<&Arc<ThreadPool>> {
+    self.thread_pool.as_ref()
+  }
+
+  /// Makes exact searches run on the global rayon thread pool again, and
+  /// returns the pool they ran on, if any.
+  #[cfg(feature = "rayon")]
+  pub fn take_thread_pool(&mut self) -> Option<Arc<ThreadPool>> {
+    self.thread_pool.take()
+  }
+
+  /// Scores every vector against `query` in storage order, a block at a
+  /// time, and keeps the best `k`.
+  ///
+  /// With the `rayon` feature, the blocks are scored in parallel on the
+  /// database's thread pool.
+  fn scan(&self, query: &[T], k: usize) -> Vec<(VectorId, f64)> {
+    let query = ArrayView1::from(query);
+    let rows = self.vectors.view();
+    self.on_thread_pool(|| {
+      neighbors::scan(&self.metric, query, k, rows, |position| {
+        self.ids[position]
+      })
+    })
+  }
+
+  /// Scores every vector against `query` like [`Self::scan`], and keeps those
+  /// within `radius`.
+  fn scan_within(
+    &self,
+    query: &[T],
+    radius: f64,
+    limit: Option<usize>,
+  ) -> Vec<(VectorId, f64)> {
+    let query = ArrayView1::from(query);
+    let rows = self.vectors.view();
+    self.on_thread_pool(|| {
+      neighbors::scan_within(&self.metric, query, radius, limit, rows, |p| {
+        self.ids[p]
+      })
+    })
+  }
+
+  /// Runs `f` on the database's thread pool, if it has one.
+  fn on_thread_pool<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+    #[cfg(feature = "rayon")]
+    if let Some(pool) = &self.thread_pool {
+      return pool.install(f);
+    }
+    f()
+  }
+
+  /// Finds the `k` vectors closest to each row of `queries`.
+  ///
+  /// Returns one list per query, in the order of the rows, each ordered like
+  /// the results of [`Self::k_nearest`]. For the Euclidean distance, the inner
+  /// product and the cosine similarity, the scores of a block of queries
+  /// against a block of vectors are computed from one matrix product, which
+  /// is much faster than searching for each query in turn; the Euclidean
+  /// distance is then derived as `sqrt(|q|² + |x|² - 2 q·x)`. Scores may
+  /// therefore differ from those of [`Self::k_nearest`] by rounding errors,
+  /// and for the Euclidean distance these errors grow with the norms of the
+  /// vectors rather than with their distance. Other metrics search for each
+  /// query in turn.
+  ///
+  /// Every query is validated like the vectors passed to [`Self::add`].
   ///
   /// # Examples
   ///
   /// ```
+  /// use ndarray::arr2;
   /// use rustyvectors::vector_database::VectorDatabase;
   ///
-  /// let mut db = VectorDatabase::new();
-  /// db.add(&[1.0, 2.0, 3.0]);
-  /// let vector = db.get(0);
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let a = db.add(&[0.0, 0.0])?;
+  /// let b = db.add(&[3.0, 4.0])?;
+  /// let neighbors = db.k_nearest_batch(&arr2(&[[0.0, 1.0], [3.0, 3.0]]), 1)?;
+  /// assert_eq!(neighbors, [[(a, 1.0)], [(b, 1.0)]]);
+  /// # Ok::<(), rustyvectors::Error>(())
   /// ```
-  pub fn get(&self, index: usize) -> Option<&Array1<f64>> {
-    if index < self.vectors.len() {
-      Some(self.vectors.get(index).unwrap())
-    } else {
-      None
+  pub fn k_nearest_batch<S: Data<Elem = T>>(
+    &self,
+    queries: &ArrayBase<S, Ix2>,
+    k: usize,
+  ) -> Result<Vec<Vec<(VectorId, f64)>>> {
+    if queries.ncols() != self.dimension {
+      return Err(Error::DimensionMismatch {
+        expected: self.dimension,
+        found: queries.ncols(),
+      });
     }
+    let queries = queries.as_standard_layout();
+    for query in queries.outer_iter() {
+      check_vector(query.to_slice().expect("standard layout"), self.dimension)?;
+    }
+    let queries = queries.view();
+    let rows = self.vectors.view();
+    Ok(self.on_thread_pool(|| {
+      neighbors::scan_batch(self.metric, queries, k, rows, |position| {
+        self.ids[position]
+      })
+    }))
+  }
+
+  /// Finds the `k` vectors closest to the given query vector along with their
+  /// metadata.
+  ///
+  /// This behaves like [`Self::k_nearest`], but each result also carries a
+  /// copy of the vector's payload.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::metadata::{Metadata, Value};
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(2)?;
+  /// let mut metadata = Metadata::new();
+  /// metadata.insert("source".to_string(), Value::from("a.txt"));
+  /// db.add_with_metadata(&[1.0, 0.0], metadata)?;
+  /// let results = db.search(&[1.0, 0.1], 1)?;
+  /// assert_eq!(results[0].metadata["source"], Value::from("a.txt"));
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn search(&self, query: &[T], k: usize) -> Result<Vec<SearchResult>> {
+    Ok(self.with_metadata(self.k_nearest(query, k)?))
+  }
+
+  /// Finds the `k` vectors closest to each row of `queries` along with their
+  /// metadata.
+  ///
+  /// This behaves like [`Self::k_nearest_batch`], but each result also
+  /// carries a copy of the vector's payload.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use ndarray::Array2;
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(2)?;
+  /// db.add(&[1.0, 0.0])?;
+  /// let queries = Array2::from_elem((1000, 2), 0.5);
+  /// let results = db.search_batch(&queries, 1)?;
+  /// assert_eq!(results.len(), 1000);
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn search_batch<S: Data<Elem = T>>(
+    &self,
+    queries: &ArrayBase<S, Ix2>,
+    k: usize,
+  ) -> Result<Vec<Vec<SearchResult>>> {
+    let neighbors = self.k_nearest_batch(queries, k)?;
+    Ok(
+      neighbors
+        .into_iter()
+        .map(|neighbors| self.with_metadata(neighbors))
+        .collect(),
+    )
+  }
+
+  /// Finds the `k` vectors closest to the given query vector among those
+  /// whose metadata matches `filter`, along with their metadata.
+  ///
+  /// This behaves like [`Self::k_nearest_filtered`], but each result also
+  /// carries a copy of the vector's payload.
+  pub fn search_filtered(
+    &self,
+    query: &[T],
+    k: usize,
+    filter: &Filter,
+  ) -> Result<Vec<SearchResult>> {
+    Ok(self.with_metadata(self.k_nearest_filtered(query, k, filter)?))
+  }
+
+  fn with_metadata(
+    &self,
+    neighbors: Vec<(VectorId, f64)>,
+  ) -> Vec<SearchResult> {
+    neighbors
+      .into_iter()
+      .map(|(id, distance)| SearchResult {
+        id,
+        distance,
+        metadata: self.metadata(id).cloned().unwrap_or_default(),
+      })
+      .collect()
   }
+
+  /// Builds a secondary index over the metadata field `field`.
+  ///
+  /// Filtered searches use the index to skip vectors that cannot match when
+  /// the filter is selective. The index is kept up to date as vectors are
+  /// added, removed or given new metadata. Creating an index that already
+  /// exists does nothing.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// db.create_index("lang");
+  /// assert!(db.has_index("lang"));
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn create_index(&mut self, field: &str) {
+    if self.indexes.contains_key(field) {
+      return;
+    }
+    let mut index = FieldIndex::default();
+    for (&id, metadata) in self.ids.iter().zip(&self.payloads) {
+      index.insert(field, id, metadata);
+    }
+    self.indexes.insert(field.to_string(), index);
+  }
+
+  /// Drops the secondary index over `field`, returning whether it existed.
+  pub fn drop_index(&mut self, field: &str) -> bool {
+    self.indexes.remove(field).is_some()
+  }
+
+  /// Returns `true` if a secondary index exists over `field`.
+  pub fn has_index(&self, field: &str) -> bool {
+    self.indexes.contains_key(field)
+  }
+
+  /// Retrieves a vector from the database by its ID, as a view into the
+  /// database's storage.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// let id = db.add(&[1.0, 2.0, 3.0])?;
+  /// let vector = db.get(id);
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn get(&self, id: VectorId) -> Option<ArrayView1<'_, T>> {
+    self
+      .positions
+      .get(&id)
+      .map(|&position| self.vectors.row(position))
+  }
+
+  /// Retrieves the metadata payload of a vector by its ID.
+  ///
+  /// Vectors added without metadata have an empty payload.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// use rustyvectors::metadata::{Metadata, Value};
+  /// use rustyvectors::vector_database::VectorDatabase;
+  ///
+  /// let mut db = VectorDatabase::new(3)?;
+  /// let mut metadata = Metadata::new();
+  /// metadata.insert("title".to_string(), Value::from("Hello"));
+  /// let id = db.add_with_metadata(&[1.0, 2.0, 3.0], metadata)?;
+  /// assert_eq!(db.metadata(id).unwrap()["title"], Value::from("Hello"));
+  /// # Ok::<(), rustyvectors::Error>(())
+  /// ```
+  pub fn metadata(&self, id: VectorId) -> Option<&Metadata> {
+    self
+      .positions
+      .get(&id)
+      .map(|&position| &self.payloads[position])
+  }
+
+  /// Replaces the metadata payload of a vector and returns the previous one.
+  ///
+  /// Returns [`Error::NotFound`] if no vector has the given ID.
+  pub fn set_metadata(
+    &mut self,
+    id: VectorId,
+    metadata: Metadata,
+  ) -> Result<Metadata> {
+    let &position = self.positions.get(&id).ok_or(Error::NotFound(id))?;
+    for (field, index) in &mut self.indexes {
+      index.remove(field, id, &self.payloads[position]);
+      index.insert(field, id, &metadata);
+    }
+    Ok(std::mem::replace(&mut self.payloads[position], metadata))
+  }
+
+  /// Checks that `vector` can be stored in or compared against the database.
+  fn check_vector(&self, vector: &[T]) -> Result<()> {
+    check_vector(vector, self.dimension)
+  }
+}
+
+/// Returns the ID [`VectorDatabase::add`] assigns after a vector is stored
+/// under `id`, failing if `id` is the largest one and there is none.
+pub(crate) fn next_id_after(id: VectorId) -> Result<VectorId> {
+  id.checked_add(1)
+    .ok_or_else(|| Error::InvalidValue(format!("id {id} is out of range")))
+}
+
+/// Checks that `vector` has `dimension` components, all of them finite.
+pub(crate) fn check_vector<T: Element>(
+  vector: &[T],
+  dimension: usize,
+) -> Result<()> {
+  if vector.len() != dimension {
+    return Err(Error::DimensionMismatch {
+      expected: dimension,
+      found: vector.len(),
+    });
+  }
+  let mut values = vector.iter().map(|value| value.to_f64());
+  if let Some(value) = values.find(|value| !value.is_finite()) {
+    return Err(Error::InvalidValue(format!(
+      "vector components must be finite, got {value}"
+    )));
+  }
+  Ok(())
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::metadata::Value;
 
   #[test]
-  fn test_default() {
-    let db = VectorDatabase::default();
+  fn test_new() {
+    let db = VectorDatabase::new(3).unwrap();
+    assert_eq!(db.dimension(), 3);
     assert_eq!(db.get(0), None);
   }
 
+  #[test]
+  fn test_invalid_configuration() {
+    assert!(matches!(
+      VectorDatabase::new(0),
+      Err(Error::InvalidValue(_))
+    ));
+    assert!(matches!(
+      VectorDatabase::with_metric(3, MetricKind::Minkowski(0.0)),
+      Err(Error::InvalidValue(_))
+    ));
+  }
+
+  #[test]
+  fn test_dimension_mismatch() {
+    let mut db = VectorDatabase::new(3).unwrap();
+    assert_eq!(
+      db.add(&[1.0, 2.0]),
+      Err(Error::DimensionMismatch {
+        expected: 3,
+        found: 2
+      })
+    );
+    db.add(&[1.0, 2.0, 3.0]).unwrap();
+    assert_eq!(
+      db.k_nearest(&[1.0; 64], 1),
+      Err(Error::DimensionMismatch {
+        expected: 3,
+        found: 64
+      })
+    );
+    assert!(db.nearest(&[1.0]).is_err());
+  }
+
+  #[test]
+  fn test_non_finite_values_are_rejected() {
+    let mut db = VectorDatabase::new(2).unwrap();
+    assert!(matches!(
+      db.add(&[f64::NAN, 0.0]),
+      Err(Error::InvalidValue(_))
+    ));
+    assert!(matches!(
+      db.nearest(&[0.0, f64::INFINITY]),
+      Err(Error::InvalidValue(_))
+    ));
+    assert!(db.is_empty());
+  }
+
   #[test]
   fn test_add() {
-    let mut db = VectorDatabase::new();
-    db.add(&[1.0, 2.0, 3.0]);
-    assert_eq!(db.get(0), Some(&ndarray::arr1(&[1.0, 2.0, 3.0])));
+    let mut db = VectorDatabase::new(3).unwrap();
+    db.add(&[1.0, 2.0, 3.0]).unwrap();
+    assert_eq!(db.get(0), Some(ndarray::arr1(&[1.0, 2.0, 3.0]).view()));
   }
 
   #[test]
   fn test_get() {
-    let mut db = VectorDatabase::new();
-    db.add(&[1.0, 2.0, 3.0]);
-    assert_eq!(db.get(0), Some(&ndarray::arr1(&[1.0, 2.0, 3.0])));
+    let mut db = VectorDatabase::new(3).unwrap();
+    db.add(&[1.0, 2.0, 3.0]).unwrap();
+    assert_eq!(db.get(0), Some(ndarray::arr1(&[1.0, 2.0, 3.0]).view()));
   }
 
   #[test]
   fn test_nearest() {
-    let mut db = VectorDatabase::new();
-    db.add(&[1.0, 2.0, 3.0]);
-    db.add(&[4.0, 5.0, 6.0]);
-    assert_eq!(db.nearest(&[1.0, 2.0, 3.0]), Some(0));
+    let mut db = VectorDatabase::new(3).unwrap();
+    db.add(&[1.0, 2.0, 3.0]).unwrap();
+    db.add(&[4.0, 5.0, 6.0]).unwrap();
+    assert_eq!(db.nearest(&[1.0, 2.0, 3.0]).unwrap(), Some(0));
+
+    assert_eq!(db.nearest(&[4.0, 5.0, 6.0]).unwrap(), Some(1));
+  }
+
+  #[test]
+  fn test_k_nearest() {
+    let mut db = VectorDatabase::new(2).unwrap();
+    db.add(&[0.0, 0.0]).unwrap();
+    db.add(&[3.0, 4.0]).unwrap();
+    db.add(&[1.0, 0.0]).unwrap();
+    db.add(&[0.0, 2.0]).unwrap();
 
-    assert_eq!(db.nearest(&[4.0, 5.0, 6.0]), Some(1));
+    assert_eq!(
+      db.k_nearest(&[0.0, 0.0], 3).unwrap(),
+      vec![(0, 0.0), (2, 1.0), (3, 2.0)]
+    );
+    assert_eq!(db.k_nearest(&[0.0, 0.0], 10).unwrap().len(), 4);
+    assert!(db.k_nearest(&[0.0, 0.0], 0).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_k_nearest_ties_and_nan() {
+    // The dot product of the first vector with the query overflows to
+    // `inf - inf`, which is `NaN`.
+    let mut db =
+      VectorDatabase::with_metric(2, MetricKind::DotProduct).unwrap();
+    db.add(&[1e200, 1e200]).unwrap();
+    db.add(&[1.0, 0.0]).unwrap();
+    db.add(&[0.0, -1.0]).unwrap();
+
+    let query = [1e200, -1e200];
+    let neighbors = db.k_nearest(&query, 3).unwrap();
+    assert_eq!(neighbors[0], (1, 1e200));
+    assert_eq!(neighbors[1], (2, 1e200));
+    assert_eq!(neighbors[2].0, 0);
+    assert!(neighbors[2].1.is_nan());
+
+    assert_eq!(
+      db.k_nearest(&query, 2).unwrap(),
+      vec![(1, 1e200), (2, 1e200)]
+    );
+    assert_eq!(db.nearest(&query).unwrap(), Some(1));
+  }
+
+  #[test]
+  fn test_k_nearest_with_similarity_metric() {
+    let mut db = VectorDatabase::with_metric(2, MetricKind::Cosine).unwrap();
+    db.add(&[10.0, 0.0]).unwrap();
+    db.add(&[1.0, 1.0]).unwrap();
+    db.add(&[0.0, 1.0]).unwrap();
+
+    assert_eq!(db.metric(), MetricKind::Cosine);
+    assert_eq!(db.nearest(&[0.0, 3.0]).unwrap(), Some(2));
+    let neighbors = db.k_nearest(&[1.0, 0.0], 3).unwrap();
+    assert_eq!(neighbors[0], (0, 1.0));
+    assert_eq!(neighbors[1].0, 1);
+    assert_eq!(neighbors[2], (2, 0.0));
   }
 
   #[test]
   fn test_remove() {
-    let mut db = VectorDatabase::new();
-    db.add(&[1.0, 2.0, 3.0]);
-    db.add(&[4.0, 5.0, 6.0]);
-    assert_eq!(db.remove(0), Some(ndarray::arr1(&[1.0, 2.0, 3.0])));
-    assert_eq!(db.get(0), Some(&ndarray::arr1(&[4.0, 5.0, 6.0])));
+    let mut db = VectorDatabase::new(3).unwrap();
+    let first = db.add(&[1.0, 2.0, 3.0]).unwrap();
+    let second = db.add(&[4.0, 5.0, 6.0]).unwrap();
+    assert_eq!(db.remove(first), Some(ndarray::arr1(&[1.0, 2.0, 3.0])));
+    assert_eq!(db.get(first), None);
+    assert_eq!(db.remove(first), None);
+    assert_eq!(db.get(second), Some(ndarray::arr1(&[4.0, 5.0, 6.0]).view()));
+    assert_eq!(db.len(), 1);
+  }
+
+  #[test]
+  fn test_ids_survive_removals() {
+    let mut db = VectorDatabase::new(1).unwrap();
+    let ids: Vec<_> = (0..5).map(|i| db.add(&[i as f64]).unwrap()).collect();
+    db.remove(ids[1]);
+    db.remove(ids[3]);
+    let new_id = db.add(&[10.0]).unwrap();
+
+    assert!(!ids.contains(&new_id));
+    for &i in &[0, 2, 4] {
+      assert_eq!(db.get(ids[i]), Some(ndarray::arr1(&[i as f64]).view()));
+      assert_eq!(db.nearest(&[i as f64]).unwrap(), Some(ids[i]));
+    }
+    assert_eq!(db.nearest(&[9.0]).unwrap(), Some(new_id));
+    assert!(!db.contains(ids[3]));
+    assert_eq!(db.ids().count(), 4);
+  }
+
+  #[test]
+  fn test_blocked_scan_matches_row_by_row_scan() {
+    // With 16 components, a scan block holds 256 vectors.
+    let mut db = VectorDatabase::new(16).unwrap();
+    db.reserve(1000);
+    let capacity = db.capacity();
+    assert!(capacity >= 1000);
+    for i in 0..1000 {
+      let vector: Vec<f64> =
+        (0..16).map(|j| ((i * 7 + j * 13) % 101) as f64).collect();
+      let mut metadata = Metadata::new();
+      metadata.insert("i".to_string(), Value::from(i));
+      db.add_with_metadata(&vector, metadata).unwrap();
+    }
+    assert_eq!(db.capacity(), capacity);
+    for id in (0..1000).step_by(3) {
+      db.remove(id);
+    }
+    assert_eq!(db.get(999), None);
+    assert_eq!(db.get(998).unwrap()[0], ((998 * 7) % 101) as f64);
+
+    let query = [50.0; 16];
+    let everything = Filter::gte("i", 0);
+    assert_eq!(
+      db.k_nearest(&query, 20).unwrap(),
+      db.k_nearest_filtered(&query, 20, &everything).unwrap()
+    );
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_parallel_scan_matches_sequential_scan() {
+    // Only 10 distinct vectors, so that ties span the blocks of every thread.
+    let mut db = VectorDatabase::with_metric(8, MetricKind::Cosine).unwrap();
+    for i in 0..5000 {
+      let vector: Vec<f64> = (0..8).map(|j| ((i % 10) * j) as f64).collect();
+      db.add(&vector).unwrap();
+    }
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build();
+    db.set_thread_pool(Arc::new(pool.unwrap()));
+    assert_eq!(db.thread_pool().unwrap().current_num_threads(), 3);
+
+    let query = [1.0, 0.0, 2.0, 0.0, 3.0, 0.0, 4.0, 0.0];
+    for k in [1, 7, 600, 6000] {
+      let sequential = db.top_k(&query, k, 0..db.len());
+      assert_eq!(db.k_nearest(&query, k).unwrap(), sequential);
+    }
+    assert!(db.take_thread_pool().is_some());
+    assert_eq!(
+      db.k_nearest(&query, 600).unwrap(),
+      db.top_k(&query, 600, 0..db.len())
+    );
+  }
+
+  #[test]
+  fn test_batch_search_matches_single_queries() {
+    let mut rng = crate::random::Rng::new(22);
+    let mut random = |len: usize| -> Vec<f64> {
+      (0..len).map(|_| rng.next_f64() * 2.0 - 1.0).collect()
+    };
+    let vectors = random(2500 * 12);
+    let queries =
+      ndarray::Array2::from_shape_vec((300, 12), random(300 * 12)).unwrap();
+    for metric in [
+      MetricKind::Euclidean,
+      MetricKind::Cosine,
+      MetricKind::DotProduct,
+      MetricKind::Manhattan,
+    ] {
+      let mut db = VectorDatabase::with_metric(12, metric).unwrap();
+      for vector in vectors.chunks(12) {
+        db.add(vector).unwrap();
+      }
+      db.remove(7);
+      let batch = db.k_nearest_batch(&queries, 5).unwrap();
+      assert_eq!(batch.len(), 300);
+      for (query, neighbors) in queries.outer_iter().zip(&batch) {
+        let expected = db.k_nearest(query.as_slice().unwrap(), 5).unwrap();
+        assert_eq!(neighbors.len(), 5);
+        for (&(id, score), &(expected_id, expected_score)) in
+          neighbors.iter().zip(&expected)
+        {
+          assert_eq!(id, expected_id);
+          assert!((score - expected_score).abs() < 1e-9);
+        }
+      }
+    }
+
+    let db = VectorDatabase::new(12).unwrap();
+    let transposed = queries.t();
+    assert!(matches!(
+      db.k_nearest_batch(&transposed, 1),
+      Err(Error::DimensionMismatch {
+        expected: 12,
+        found: 300
+      })
+    ));
+    let mut invalid = queries.clone();
+    invalid[[299, 0]] = f64::NAN;
+    assert!(matches!(
+      db.k_nearest_batch(&invalid, 1),
+      Err(Error::InvalidValue(_))
+    ));
+    assert_eq!(db.search_batch(&queries, 1).unwrap()[0], []);
+
+    let mut db =
+      VectorDatabase::<f32>::with_element_type(12, MetricKind::Euclidean)
+        .unwrap();
+    for vector in vectors.chunks(12) {
+      db.add(&vector.iter().map(|&x| x as f32).collect::<Vec<_>>())
+        .unwrap();
+    }
+    let query = queries.row(0).mapv(|x| x as f32);
+    let batch = db
+      .k_nearest_batch(&query.view().insert_axis(ndarray::Axis(0)), 3)
+      .unwrap();
+    let expected = db.k_nearest(query.as_slice().unwrap(), 3).unwrap();
+    assert_eq!(batch[0].len(), 3);
+    for (&(id, score), &(expected_id, expected_score)) in
+      batch[0].iter().zip(&expected)
+    {
+      assert_eq!(id, expected_id);
+      assert!((score - expected_score).abs() < 1e-9);
+    }
+  }
+
+  #[test]
+  fn test_add_batch() {
+    use crate::index::{Hnsw, HnswConfig};
+
+    let vectors: Vec<_> = (0..300)
+      .map(|i| [(i % 17) as f64, (i / 17) as f64 * 0.5])
+      .collect();
+    let mut expected = VectorDatabase::new(2).unwrap();
+    for vector in &vectors {
+      expected.add(vector).unwrap();
+    }
+    let mut db = VectorDatabase::new(2).unwrap();
+    db.set_index(
+      Hnsw::new(2, MetricKind::Euclidean, HnswConfig::default()).unwrap(),
+    )
+    .unwrap();
+    let matrix = ndarray::Array2::from(vectors[..200].to_vec());
+    assert!(matches!(
+      db.add_batch(&matrix.t()),
+      Err(Error::DimensionMismatch { found: 200, .. })
+    ));
+    assert_eq!(db.add_batch(&matrix).unwrap(), (0..200).collect::<Vec<_>>());
+    // Adding the rows one at a time would have doubled the capacity to 256.
+    assert!((200..256).contains(&db.capacity()));
+    assert_eq!(
+      db.add_batch_iter(&vectors[200..]).unwrap(),
+      (200..300).collect::<Vec<_>>()
+    );
+    for id in expected.ids() {
+      assert_eq!(db.get(id), expected.get(id));
+      assert_eq!(db.metadata(id), Some(&Metadata::new()));
+    }
+
+    let invalid = [[1.0, 1.0], [f64::INFINITY, 0.0]];
+    assert!(db.add_batch(&ndarray::arr2(&invalid)).is_err());
+    assert!(db.add_batch_iter(invalid).is_err());
+    assert!(db.add_batch_iter([&[1.0, 1.0][..], &[1.0]]).is_err());
+    assert_eq!(db.len(), 300);
+    assert_eq!(db.index().unwrap().len(), 300);
+    assert_eq!(db.k_nearest(&[1.0, 1.0], 1).unwrap()[0].1, 0.0);
+    assert_eq!(db.add(&[0.0, 0.0]).unwrap(), 300);
+
+    for query in [[3.2, 1.1], [16.0, 8.0], [8.4, 4.6]] {
+      assert_eq!(
+        db.k_nearest_approx(&query, 5).unwrap(),
+        db.k_nearest(&query, 5).unwrap()
+      );
+    }
+  }
+
+  #[test]
+  fn test_update_and_upsert() {
+    use crate::index::{Hnsw, HnswConfig};
+
+    let mut db = VectorDatabase::new(2).unwrap();
+    db.create_index("kind");
+    let mut metadata = Metadata::new();
+    metadata.insert("kind".to_string(), Value::from("a"));
+    let a = db.add_with_metadata(&[0.0, 0.0], metadata.clone()).unwrap();
+    let b = db.add(&[10.0, 10.0]).unwrap();
+    db.set_index(Hnsw::build(&db, HnswConfig::default()).unwrap())
+      .unwrap();
+
+    assert_eq!(db.update(a, &[20.0, 20.0]).unwrap().to_vec(), [0.0, 0.0]);
+    assert_eq!(db.get(a), Some(ndarray::arr1(&[20.0, 20.0]).view()));
+    assert_eq!(db.metadata(a), Some(&metadata));
+    assert_eq!(db.k_nearest_approx(&[19.0, 19.0], 1).unwrap()[0].0, a);
+    assert!(matches!(db.update(9, &[0.0, 0.0]), Err(Error::NotFound(9))));
+    assert!(matches!(
+      db.update(a, &[0.0]),
+      Err(Error::DimensionMismatch { .. })
+    ));
+
+    metadata.insert("kind".to_string(), Value::from("b"));
+    assert!(db.upsert(b, &[1.0, 1.0], metadata.clone()).unwrap());
+    let only_b = Filter::eq("kind", "b");
+    assert_eq!(
+      db.k_nearest_filtered(&[0.0, 0.0], 5, &only_b).unwrap(),
+      [(b, 2.0_f64.sqrt())]
+    );
+    assert!(!db.upsert(5, &[2.0, 2.0], Metadata::new()).unwrap());
+    assert_eq!(db.len(), 3);
+    assert_eq!(db.index().unwrap().len(), 3);
+    assert_eq!(db.add(&[3.0, 3.0]).unwrap(), 6);
+    assert!(matches!(
+      db.upsert(VectorId::MAX, &[0.0, 0.0], Metadata::new()),
+      Err(Error::InvalidValue(_))
+    ));
+
+    // Bringing back a removed ID indexes it under its new payload only.
+    assert!(db.remove(b).is_some());
+    let mut revived = Metadata::new();
+    revived.insert("kind".to_string(), Value::from("a"));
+    assert!(!db.upsert(b, &[4.0, 4.0], revived.clone()).unwrap());
+    assert_eq!(db.metadata(b), Some(&revived));
+    assert_eq!(db.len(), 4);
+    assert_eq!(db.index().unwrap().len(), 4);
+    assert_eq!(db.k_nearest_filtered(&[0.0, 0.0], 5, &only_b).unwrap(), []);
+    let only_a = Filter::eq("kind", "a");
+    assert_eq!(
+      db.k_nearest_filtered(&[5.0, 5.0], 5, &only_a).unwrap(),
+      [(b, 2.0_f64.sqrt()), (a, 15.0 * 2.0_f64.sqrt())]
+    );
+    assert_eq!(db.k_nearest_approx(&[4.0, 4.0], 1).unwrap()[0].0, b);
+    assert_eq!(db.add(&[5.0, 5.0]).unwrap(), 7);
+  }
+
+  #[test]
+  fn test_radius_search() {
+    use crate::index::{Ivf, IvfConfig};
+
+    let mut db = VectorDatabase::new(2).unwrap();
+    for i in 0..900 {
+      db.add(&[(i % 30) as f64, (i / 30) as f64]).unwrap();
+    }
+    let query = [10.3, 10.6];
+    let expected: Vec<_> = db
+      .k_nearest(&query, db.len())
+      .unwrap()
+      .into_iter()
+      .take_while(|&(_, distance)| distance <= 2.5)
+      .collect();
+    assert_eq!(expected.len(), 20);
+    assert_eq!(db.within_radius(&query, 2.5, None).unwrap(), expected);
+    assert_eq!(
+      db.within_radius(&query, 2.5, Some(5)).unwrap(),
+      expected[..5]
+    );
+    assert_eq!(db.within_radius(&query, 0.1, None).unwrap(), []);
+    assert!(matches!(
+      db.within_radius(&query, f64::NAN, None),
+      Err(Error::InvalidValue(_))
+    ));
+
+    // Probing every list makes the index exact, so that the doubling searches
+    // must find the same vectors.
+    let config = IvfConfig {
+      nlist: 8,
+      nprobe: 8,
+      ..IvfConfig::default()
+    };
+    db.set_index(Ivf::build(&db, config).unwrap()).unwrap();
+    for limit in [None, Some(0), Some(5), Some(100)] {
+      let expected = db.within_radius(&query, 2.5, limit).unwrap();
+      assert_eq!(
+        db.within_radius_approx(&query, 2.5, limit).unwrap(),
+        expected
+      );
+    }
+    assert_eq!(
+      db.within_radius_approx(&query, 100.0, None).unwrap().len(),
+      900
+    );
+
+    let mut db = VectorDatabase::with_metric(2, MetricKind::Cosine).unwrap();
+    let a = db.add(&[1.0, 0.0]).unwrap();
+    let b = db.add(&[1.0, 1.0]).unwrap();
+    db.add(&[-1.0, 0.0]).unwrap();
+    let neighbors = db.within_radius(&[2.0, 0.0], 0.5, None).unwrap();
+    assert_eq!(
+      neighbors.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+      [a, b]
+    );
+  }
+
+  #[test]
+  fn test_narrow_element_types() {
+    use crate::element::{bf16, f16};
+    use crate::index::{Hnsw, HnswConfig};
+
+    let mut db =
+      VectorDatabase::<f32>::with_element_type(2, MetricKind::Euclidean)
+        .unwrap();
+    let a = db.add(&[1.0, 2.0]).unwrap();
+    let b = db.add(&[4.0, 6.0]).unwrap();
+    assert_eq!(db.k_nearest(&[1.0, 2.0], 2).unwrap(), [(a, 0.0), (b, 5.0)]);
+    assert_eq!(db.get(b), Some(ndarray::arr1(&[4.0_f32, 6.0]).view()));
+    assert!(matches!(
+      db.add(&[f32::NAN, 0.0]),
+      Err(Error::InvalidValue(_))
+    ));
+    db.set_index(Hnsw::build(&db, HnswConfig::default()).unwrap())
+      .unwrap();
+    let c = db.add(&[4.0, 5.0]).unwrap();
+    assert_eq!(db.k_nearest_approx(&[4.0, 5.0], 1).unwrap(), [(c, 0.0)]);
+
+    let mut db =
+      VectorDatabase::<bf16>::with_element_type(1, MetricKind::DotProduct)
+        .unwrap();
+    db.add(&[bf16::from_f32(3.0)]).unwrap();
+    assert!(matches!(
+      db.add(&[bf16::INFINITY]),
+      Err(Error::InvalidValue(_))
+    ));
+    assert_eq!(
+      db.search(&[bf16::from_f32(0.5)], 1).unwrap()[0].distance,
+      1.5
+    );
+
+    // A value rounded to the nearest f16 keeps its 11 significant bits.
+    let mut db =
+      VectorDatabase::<f16>::with_element_type(1, MetricKind::Euclidean)
+        .unwrap();
+    let id = db.add(&[f16::from_f64(0.1)]).unwrap();
+    assert_eq!(db.remove(id).unwrap()[0].to_f64(), 0.0999755859375);
+  }
+
+  #[test]
+  fn test_metadata() {
+    let mut db = VectorDatabase::new(2).unwrap();
+    let mut metadata = Metadata::new();
+    metadata.insert("title".to_string(), Value::from("first"));
+    metadata.insert("year".to_string(), Value::from(2023));
+    let first = db.add_with_metadata(&[0.0, 0.0], metadata.clone()).unwrap();
+    let second = db.add(&[1.0, 1.0]).unwrap();
+
+    assert_eq!(db.metadata(first), Some(&metadata));
+    assert_eq!(db.metadata(second), Some(&Metadata::new()));
+
+    let mut updated = Metadata::new();
+    updated.insert("title".to_string(), Value::from("second"));
+    assert_eq!(
+      db.set_metadata(second, updated.clone()),
+      Ok(Metadata::new())
+    );
+    assert_eq!(
+      db.set_metadata(42, Metadata::new()),
+      Err(Error::NotFound(42))
+    );
+
+    // Removing the first vector moves the second one in storage, which must
+    // not detach it from its payload.
+    db.remove(first);
+    assert_eq!(db.metadata(first), None);
+    assert_eq!(db.metadata(second), Some(&updated));
+  }
+
+  #[test]
+  fn test_search_returns_metadata() {
+    let mut db = VectorDatabase::new(2).unwrap();
+    for i in 0..3 {
+      let mut metadata = Metadata::new();
+      metadata.insert("index".to_string(), Value::from(i));
+      db.add_with_metadata(&[i as f64, 0.0], metadata).unwrap();
+    }
+
+    let results = db.search(&[1.9, 0.0], 2).unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].id, 2);
+    assert_eq!(results[0].metadata["index"], Value::from(2));
+    assert_eq!(results[1].id, 1);
+    assert_eq!(results[1].metadata["index"], Value::from(1));
+  }
+
+  fn tagged_db() -> VectorDatabase {
+    let mut db = VectorDatabase::new(1).unwrap();
+    for i in 0..100 {
+      let mut metadata = Metadata::new();
+      metadata.insert("parity".to_string(), Value::from(i % 2));
+      metadata.insert("rare".to_string(), Value::from(i % 50 == 0));
+      db.add_with_metadata(&[i as f64], metadata).unwrap();
+    }
+    db
+  }
+
+  #[test]
+  fn test_k_nearest_filtered_returns_k_results() {
+    let db = tagged_db();
+    let odd = Filter::eq("parity", 1);
+
+    // A post-filter over the 4 nearest vectors would only keep 2 of them.
+    assert_eq!(
+      db.k_nearest_filtered(&[10.0], 4, &odd).unwrap(),
+      vec![(9, 1.0), (11, 1.0), (7, 3.0), (13, 3.0)]
+    );
+    let results = db
+      .search_filtered(&[10.0], 2, &Filter::eq("rare", true))
+      .unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].id, 0);
+    assert_eq!(results[1].id, 50);
+    assert_eq!(results[1].metadata["rare"], Value::from(true));
+  }
+
+  #[test]
+  fn test_k_nearest_filtered_with_indexes() {
+    let mut db = tagged_db();
+    let filter = Filter::eq("rare", true).and(Filter::lt("parity", 1));
+    let unindexed = db.k_nearest_filtered(&[60.0], 5, &filter).unwrap();
+
+    db.create_index("rare");
+    db.create_index("parity");
+    assert_eq!(
+      db.k_nearest_filtered(&[60.0], 5, &filter).unwrap(),
+      unindexed
+    );
+    assert_eq!(unindexed, vec![(50, 10.0), (0, 60.0)]);
+
+    // Indexes follow removals and metadata updates.
+    db.remove(50);
+    let mut metadata = Metadata::new();
+    metadata.insert("rare".to_string(), Value::from(true));
+    metadata.insert("parity".to_string(), Value::from(0));
+    db.set_metadata(64, metadata).unwrap();
+    let mut metadata = Metadata::new();
+    metadata.insert("rare".to_string(), Value::from(true));
+    db.add_with_metadata(&[61.0], metadata).unwrap();
+    assert_eq!(
+      db.k_nearest_filtered(&[60.0], 5, &filter).unwrap(),
+      vec![(64, 4.0), (0, 60.0)]
+    );
+    assert_eq!(
+      db.k_nearest_filtered(&[60.0], 1, &Filter::eq("rare", true))
+        .unwrap(),
+      vec![(100, 1.0)]
+    );
+
+    assert!(db.drop_index("rare"));
+    assert!(!db.has_index("rare"));
+    assert!(db.has_index("parity"));
+  }
+
+  #[test]
+  fn test_attached_index_follows_changes() {
+    use crate::index::{Hnsw, HnswConfig};
+
+    let mut db = tagged_db();
+    let hnsw = Hnsw::build(&db, HnswConfig::default()).unwrap();
+    db.set_index(hnsw).unwrap();
+    assert_eq!(
+      db.k_nearest_approx(&[41.2], 2).unwrap(),
+      db.k_nearest(&[41.2], 2).unwrap()
+    );
+
+    db.remove(41);
+    let id = db.add(&[41.1]).unwrap();
+    assert_eq!(db.index().unwrap().len(), 100);
+    assert_eq!(db.k_nearest_approx(&[41.2], 1).unwrap()[0].0, id);
+    assert!(db.take_index().is_some());
+    assert!(db.index().is_none());
+  }
+
+  #[test]
+  fn test_set_index_rejects_mismatched_index() {
+    use crate::index::{Hnsw, HnswConfig};
+
+    let mut db = tagged_db();
+    let empty =
+      Hnsw::new(1, MetricKind::Euclidean, HnswConfig::default()).unwrap();
+    assert!(matches!(db.set_index(empty), Err(Error::InvalidValue(_))));
+    let other_metric =
+      Hnsw::new(1, MetricKind::Cosine, HnswConfig::default()).unwrap();
+    assert!(matches!(
+      db.set_index(other_metric),
+      Err(Error::InvalidValue(_))
+    ));
   }
 
   #[test]
   fn test_digit_recognition() {
-    let mut db = VectorDatabase::new();
+    let mut db = VectorDatabase::new(64).unwrap();
 
     // adding reference "images" (here simplified as 1D arrays of length 64)
     // normally, these would be preprocessed image data.
-    db.add(&[1.0; 64]); // imagine this is a '0'
-    db.add(&[2.0; 64]); // imagine this is a '1'
+    db.add(&[1.0; 64]).unwrap(); // imagine this is a '0'
+    db.add(&[2.0; 64]).unwrap(); // imagine this is a '1'
 
     // Now we get a new "image" and want to recognize which digit it is
     let new_image = [1.05; 64]; // this should be recognized as '0'
-    let nearest = db.nearest(&new_image);
+    let nearest = db.nearest(&new_image).unwrap();
     assert_eq!(nearest, Some(0));
 
     let new_image = [2.05; 64]; // this should be recognized as '1'
-    let nearest = db.nearest(&new_image);
+    let nearest = db.nearest(&new_image).unwrap();
     assert_eq!(nearest, Some(1));
   }
 }